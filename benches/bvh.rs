@@ -0,0 +1,116 @@
+//! Ray intersection speed of each BVH method, over the same rays through the RandomSpheres and FinalScene scenes
+//!
+//! For each scene, the primitives are generated from the same seed, so every variant holds the same objects:
+//!
+//! - `sah_list`: the scene's own hierarchies built with the Sah method, with its top level left as a list, as it was
+//!   rendered before the top level was flattened
+//! - `sah_tree`: the same, with a SAH BvhNode tree built over the top level
+//! - `flat`: the scene's own hierarchies built as FlatBvhs, with a FlatBvh over the top level, as `--bvh flat` renders
+//!
+//! So `sah_tree` against `flat` measures FlatBvh alone, and `sah_list` against `sah_tree` measures the top level's
+//! hierarchy alone.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rust_raytracer::{
+    animation::CameraKey, bvh::*, camera, flat_bvh::FlatBvh, hittable::*,
+    hittable_list::HittableList, lens::Lens, ray::Ray, scene, util::*, vec3::*,
+};
+
+const SEED: u64 = 1;
+// Camera rays across and down the image, each followed by one random bounce from what it hits
+const RAYS_WIDE: u32 = 64;
+const RAYS_HIGH: u32 = 36;
+
+/// The scene's top level list and settings, with its own hierarchies built by method
+fn build_scene(which: scene::Scene, method: BvhMethod) -> (HittableList, scene::SceneData) {
+    let mut scene_dat = scene::SceneData::new();
+    scene_dat.bvh_method = method;
+    seed_rng(SEED);
+    let world = scene::match_scene(which, &mut scene_dat);
+    (world, scene_dat)
+}
+
+/// Camera rays through a grid over the image, and a random bounce from each camera ray's hit
+fn build_rays(world: &dyn Hittable, scene_dat: &scene::SceneData) -> Vec<Ray> {
+    let pose = CameraKey {
+        frame: 0.0,
+        lookfrom: scene_dat.lookfrom,
+        lookat: scene_dat.lookat,
+        vup: scene_dat.vup,
+        vfov: scene_dat.vfov,
+        aperture: scene_dat.aperture,
+        dist_to_focus: scene_dat.dist_to_focus,
+    };
+    let cam = camera::new_camera(
+        &camera::Projection::perspective(),
+        &Lens::circle(),
+        &pose,
+        scene_dat.aspect_ratio,
+        0.0,
+        1.0,
+    );
+
+    seed_rng(SEED);
+    let mut rays = Vec::new();
+    for j in 0..RAYS_HIGH {
+        for i in 0..RAYS_WIDE {
+            let u = (i as f64 + random_double()) / RAYS_WIDE as f64;
+            let v = (j as f64 + random_double()) / RAYS_HIGH as f64;
+            let r = match cam.get_ray(u, v) {
+                Some(r) => r,
+                None => continue,
+            };
+
+            let mut rec = HitRecord::new();
+            if world.hit(&r, 0.001, INFINITY, &mut rec) {
+                let bounce = rec.normal + random_unit_vector();
+                rays.push(Ray::new(&rec.p, &bounce, r.time()));
+            }
+            rays.push(r);
+        }
+    }
+    rays
+}
+
+fn trace(world: &dyn Hittable, rays: &[Ray]) -> usize {
+    let mut rec = HitRecord::new();
+    rays.iter()
+        .filter(|r| world.hit(r, 0.001, INFINITY, &mut rec))
+        .count()
+}
+
+fn bench_scene(c: &mut Criterion, name: &str, which: scene::Scene) {
+    let (sah_list, scene_dat) = build_scene(which, BvhMethod::Sah);
+    let sah_tree = BvhNode::new_sah(&sah_list, 0.0, 1.0);
+    let (flat_list, _) = build_scene(which, BvhMethod::Flat);
+    let flat = FlatBvh::new(&flat_list, 0.0, 1.0);
+
+    let rays = build_rays(&sah_list, &scene_dat);
+    // Every variant must agree on what the rays hit, or the timings compare different work. Rays passing through
+    // fog hit it at random, so those few may differ
+    let hits = trace(&sah_list, &rays);
+    for other in [trace(&sah_tree, &rays), trace(&flat, &rays)] {
+        assert!(hits.abs_diff(other) <= rays.len() / 100);
+    }
+
+    let mut group = c.benchmark_group(name);
+    group.bench_function("sah_list", |b| {
+        b.iter(|| trace(black_box(&sah_list), &rays))
+    });
+    group.bench_function("sah_tree", |b| {
+        b.iter(|| trace(black_box(&sah_tree), &rays))
+    });
+    group.bench_function("flat", |b| b.iter(|| trace(black_box(&flat), &rays)));
+    group.finish();
+}
+
+fn random_spheres(c: &mut Criterion) {
+    bench_scene(c, "random_spheres", scene::Scene::RandomSpheres);
+}
+
+fn final_scene(c: &mut Criterion) {
+    bench_scene(c, "final_scene", scene::Scene::FinalScene);
+}
+
+criterion_group!(benches, random_spheres, final_scene);
+criterion_main!(benches);
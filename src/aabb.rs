@@ -29,6 +29,15 @@ impl AABB {
         self.max
     }
 
+    pub fn centroid(&self) -> Point {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
     pub fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> bool {
         for a in 0..3 {
             let t0 = f64::min(
@@ -1,4 +1,4 @@
-use crate::{aabb::*, hittable::*, materials::*, ray::Ray, vec3::*};
+use crate::{aabb::*, hittable::*, materials::*, ray::Ray, util::*, vec3::*};
 
 use std::sync::Arc;
 
@@ -62,6 +62,28 @@ impl Hittable for XYRect {
         );
         (true, outbox)
     }
+
+    fn pdf_value(&self, o: &Point, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(o, v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        let distance_squared = rec.t * rec.t * v.length_squared();
+        let cosine = f64::abs(dot(*v, rec.normal) / v.length());
+
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, o: &Point) -> Vec3 {
+        let random_point = Point::new(
+            random_double_range(self.x0, self.x1),
+            random_double_range(self.y0, self.y1),
+            self.k,
+        );
+        random_point - *o
+    }
 }
 
 pub struct XZRect {
@@ -124,6 +146,28 @@ impl Hittable for XZRect {
         );
         (true, outbox)
     }
+
+    fn pdf_value(&self, o: &Point, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(o, v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+        let distance_squared = rec.t * rec.t * v.length_squared();
+        let cosine = f64::abs(dot(*v, rec.normal) / v.length());
+
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, o: &Point) -> Vec3 {
+        let random_point = Point::new(
+            random_double_range(self.x0, self.x1),
+            self.k,
+            random_double_range(self.z0, self.z1),
+        );
+        random_point - *o
+    }
 }
 
 pub struct YZRect {
@@ -186,4 +230,26 @@ impl Hittable for YZRect {
         );
         (true, outbox)
     }
+
+    fn pdf_value(&self, o: &Point, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(o, v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+
+        let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+        let distance_squared = rec.t * rec.t * v.length_squared();
+        let cosine = f64::abs(dot(*v, rec.normal) / v.length());
+
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, o: &Point) -> Vec3 {
+        let random_point = Point::new(
+            self.k,
+            random_double_range(self.y0, self.y1),
+            random_double_range(self.z0, self.z1),
+        );
+        random_point - *o
+    }
 }
@@ -0,0 +1,355 @@
+//! Per pixel radiance sums, and their checkpoint files for resuming interrupted renders
+
+use crate::{color::luminance, vec3::Color};
+use image::{GrayImage, Luma};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+const MAGIC: &[u8; 8] = b"RTCKPT02";
+
+#[derive(Debug)]
+pub enum CheckpointErr {
+    IoError { err: std::io::Error },
+    InvalidFile { err: String },
+}
+
+impl std::fmt::Display for CheckpointErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckpointErr::IoError { err } => write!(f, "{}", err),
+            CheckpointErr::InvalidFile { err } => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for CheckpointErr {
+    fn from(err: std::io::Error) -> Self {
+        CheckpointErr::IoError { err }
+    }
+}
+
+/**
+ * Sum of the samples taken for each pixel, and how many were taken, in image row order.
+ *
+ * Pixel (col, row) is at index row * width + col, with row 0 at the top of the image. Each pixel also keeps the
+ * sum of its samples' squared luminance, for estimating its noise, and the sample count it is being rendered up to.
+ */
+pub struct Accumulator {
+    width: u32,
+    height: u32,
+    sum: Vec<Color>,
+    sum_sq: Vec<f64>,
+    count: Vec<u32>,
+    target: Vec<u32>,
+}
+
+/// Identifies the render a checkpoint belongs to, so a mismatched resume is refused
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RenderKey {
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u32,
+    pub seed: u64,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Accumulator {
+        let len = (width * height) as usize;
+        Accumulator {
+            width,
+            height,
+            sum: vec![Color::new_e(); len],
+            sum_sq: vec![0.0; len],
+            count: vec![0; len],
+            target: vec![0; len],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count.len()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn index(&self, col: u32, row: u32) -> usize {
+        (row * self.width + col) as usize
+    }
+
+    /// Adds sum, the total of samples more samples, and sum_sq, the total of their squared luminances, to the pixel at index
+    pub fn add(&mut self, index: usize, sum: Color, sum_sq: f64, samples: u32) {
+        self.sum[index] += sum;
+        self.sum_sq[index] += sum_sq;
+        self.count[index] += samples;
+    }
+
+    pub fn sum(&self, index: usize) -> Color {
+        self.sum[index]
+    }
+
+    pub fn count(&self, index: usize) -> u32 {
+        self.count[index]
+    }
+
+    /// Sample count the pixel is being rendered up to
+    pub fn target(&self, index: usize) -> u32 {
+        self.target[index]
+    }
+
+    pub fn set_target(&mut self, index: usize, target: u32) {
+        self.target[index] = target;
+    }
+
+    /**
+     * Estimated error of the pixel's average luminance, relative to that average.
+     *
+     * This is the standard error of the mean, so it shrinks with the square root of the sample count. Pixels with
+     * fewer than two samples have no estimate, and report infinite error.
+     */
+    pub fn relative_error(&self, index: usize) -> f64 {
+        let n = self.count[index] as f64;
+        let mean = luminance(self.sum[index]) / f64::max(n, 1.0);
+
+        // Near black pixels would otherwise need endless samples to get their relative error down
+        f64::sqrt(self.variance(index)) / f64::max(mean, 0.001)
+    }
+
+    /**
+     * Estimated variance of the pixel's average luminance.
+     *
+     * Pixels with fewer than two samples have no estimate, and report infinite variance.
+     */
+    pub fn variance(&self, index: usize) -> f64 {
+        let n = self.count[index] as f64;
+        if n < 2.0 {
+            return f64::INFINITY;
+        }
+
+        let mean = luminance(self.sum[index]) / n;
+        let sample_variance = f64::max(self.sum_sq[index] / n - mean * mean, 0.0) * n / (n - 1.0);
+        sample_variance / n
+    }
+
+    /**
+     * Saves an 8 bit grey image of fname showing each pixel's sample count, with white for the most sampled pixel.
+     */
+    pub fn save_sample_map(&self, fname: &str) -> Result<(), image::ImageError> {
+        let max = self.count.iter().copied().max().unwrap_or(0).max(1) as f64;
+        let img = GrayImage::from_fn(self.width, self.height, |col, row| {
+            let c = self.count[self.index(col, row)] as f64;
+            Luma([(255.0 * c / max).round() as u8])
+        });
+
+        img.save(fname)
+    }
+
+    /// Average of the pixel's samples, or black if it has none yet
+    pub fn average(&self, index: usize) -> Color {
+        if self.count[index] == 0 {
+            return Color::new_e();
+        }
+        self.sum[index] / self.count[index] as f64
+    }
+
+    /**
+     * Writes the accumulated sums to fname, tagged with key.
+     *
+     * The file is written beside fname first and then renamed over it, so an interruption never leaves a partial
+     * checkpoint in place of a good one. Values are stored bit for bit, so resuming loses no precision.
+     */
+    pub fn save_checkpoint(&self, fname: &str, key: &RenderKey) -> Result<(), CheckpointErr> {
+        let tmp = format!("{}.tmp", fname);
+        {
+            let mut out = BufWriter::new(File::create(&tmp)?);
+            out.write_all(MAGIC)?;
+            out.write_all(&key.width.to_le_bytes())?;
+            out.write_all(&key.height.to_le_bytes())?;
+            out.write_all(&key.samples_per_pixel.to_le_bytes())?;
+            out.write_all(&key.seed.to_le_bytes())?;
+
+            for i in 0..self.len() {
+                out.write_all(&self.sum[i].x().to_le_bytes())?;
+                out.write_all(&self.sum[i].y().to_le_bytes())?;
+                out.write_all(&self.sum[i].z().to_le_bytes())?;
+                out.write_all(&self.sum_sq[i].to_le_bytes())?;
+                out.write_all(&self.count[i].to_le_bytes())?;
+                out.write_all(&self.target[i].to_le_bytes())?;
+            }
+
+            out.flush()?;
+        }
+        std::fs::rename(&tmp, fname)?;
+
+        Ok(())
+    }
+
+    /// Reads a checkpoint written by save_checkpoint, returning it with the key of the render it belongs to
+    pub fn load_checkpoint(fname: &str) -> Result<(Accumulator, RenderKey), CheckpointErr> {
+        let file = File::open(fname)?;
+        let file_len = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(CheckpointErr::InvalidFile {
+                err: format!("'{}' is not a render checkpoint", fname),
+            });
+        }
+
+        let key = RenderKey {
+            width: read_u32(&mut input)?,
+            height: read_u32(&mut input)?,
+            samples_per_pixel: read_u32(&mut input)?,
+            seed: u64::from_le_bytes(read_bytes(&mut input)?),
+        };
+
+        // Check the size before allocating, so a corrupt header can't ask for a huge buffer
+        let pixels = key.width as u64 * key.height as u64;
+        if file_len != MAGIC.len() as u64 + 20 + pixels * 40 {
+            return Err(CheckpointErr::InvalidFile {
+                err: format!("'{}' is the wrong size for its image", fname),
+            });
+        }
+
+        let mut acc = Accumulator::new(key.width, key.height);
+        for i in 0..acc.sum.len() {
+            let r = f64::from_le_bytes(read_bytes(&mut input)?);
+            let g = f64::from_le_bytes(read_bytes(&mut input)?);
+            let b = f64::from_le_bytes(read_bytes(&mut input)?);
+            acc.sum[i] = Color::new(r, g, b);
+            acc.sum_sq[i] = f64::from_le_bytes(read_bytes(&mut input)?);
+            acc.count[i] = read_u32(&mut input)?;
+            acc.target[i] = read_u32(&mut input)?;
+        }
+
+        Ok((acc, key))
+    }
+}
+
+fn read_bytes<const N: usize>(input: &mut impl Read) -> Result<[u8; N], CheckpointErr> {
+    let mut b = [0u8; N];
+    match input.read_exact(&mut b) {
+        Ok(_) => Ok(b),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            Err(CheckpointErr::InvalidFile {
+                err: "checkpoint is truncated".to_string(),
+            })
+        }
+        Err(e) => Err(CheckpointErr::IoError { err: e }),
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> Result<u32, CheckpointErr> {
+    Ok(u32::from_le_bytes(read_bytes(input)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path in the temporary directory, unique to this process and test
+    fn temp_path(name: &str) -> String {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("rust_raytracer_{}_{}", std::process::id(), name));
+        path.to_str().unwrap().to_string()
+    }
+
+    fn key() -> RenderKey {
+        RenderKey {
+            width: 3,
+            height: 2,
+            samples_per_pixel: 64,
+            seed: 0x0123_4567_89ab_cdef,
+        }
+    }
+
+    fn filled() -> Accumulator {
+        let mut acc = Accumulator::new(3, 2);
+        for i in 0..acc.len() {
+            let x = i as f64;
+            // Values that don't round trip through text or f32 exactly
+            acc.add(
+                i,
+                Color::new(0.1 * x, 1.0 / 3.0 + x, f64::MIN_POSITIVE),
+                x / 7.0,
+                i as u32 + 1,
+            );
+            acc.set_target(i, 2 * i as u32);
+        }
+        acc
+    }
+
+    #[test]
+    fn checkpoint_round_trips_bit_for_bit() {
+        let fname = temp_path("round_trip.ckpt");
+        let acc = filled();
+        acc.save_checkpoint(&fname, &key()).unwrap();
+        let (loaded, loaded_key) = Accumulator::load_checkpoint(&fname).unwrap();
+        std::fs::remove_file(&fname).unwrap();
+
+        assert_eq!(loaded_key, key());
+        assert_eq!((loaded.width(), loaded.height()), (3, 2));
+        for i in 0..acc.len() {
+            for a in 0..3 {
+                assert_eq!(loaded.sum(i)[a].to_bits(), acc.sum(i)[a].to_bits());
+            }
+            assert_eq!(loaded.sum_sq[i].to_bits(), acc.sum_sq[i].to_bits());
+            assert_eq!(loaded.count(i), acc.count(i));
+            assert_eq!(loaded.target(i), acc.target(i));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_and_foreign_files() {
+        let fname = temp_path("truncated.ckpt");
+        filled().save_checkpoint(&fname, &key()).unwrap();
+        let bytes = std::fs::read(&fname).unwrap();
+
+        // Cut off in the pixel data, and in the header
+        for len in [bytes.len() - 1, MAGIC.len() + 6] {
+            std::fs::write(&fname, &bytes[..len]).unwrap();
+            assert!(matches!(
+                Accumulator::load_checkpoint(&fname),
+                Err(CheckpointErr::InvalidFile { .. })
+            ));
+        }
+
+        // Trailing data is the wrong size too
+        let mut longer = bytes.clone();
+        longer.push(0);
+        std::fs::write(&fname, &longer).unwrap();
+        assert!(matches!(
+            Accumulator::load_checkpoint(&fname),
+            Err(CheckpointErr::InvalidFile { .. })
+        ));
+
+        let mut foreign = bytes;
+        foreign[0] = b'X';
+        std::fs::write(&fname, &foreign).unwrap();
+        assert!(matches!(
+            Accumulator::load_checkpoint(&fname),
+            Err(CheckpointErr::InvalidFile { .. })
+        ));
+
+        std::fs::remove_file(&fname).unwrap();
+    }
+
+    #[test]
+    fn variance_of_the_mean() {
+        let mut acc = Accumulator::new(1, 1);
+        acc.add(0, Color::new(1.0, 1.0, 1.0), 1.0, 1);
+        assert_eq!(acc.variance(0), f64::INFINITY);
+
+        // Luminances 1 and 3 have sample variance 2, so the mean of two has variance 1
+        acc.add(0, Color::new(3.0, 3.0, 3.0), 9.0, 1);
+        assert!((acc.variance(0) - 1.0).abs() < 1e-12);
+        assert!((acc.relative_error(0) - 0.5).abs() < 1e-12);
+        assert_eq!(acc.average(0), Color::new(2.0, 2.0, 2.0));
+    }
+}
@@ -0,0 +1,242 @@
+//! Keyframed camera paths, and naming of the frames of an image sequence
+
+use crate::vec3::*;
+use std::ops::{Add, Mul, Range, Sub};
+use std::path::Path;
+
+/// Where the camera is and how it is set up at one frame of an animation
+#[derive(Debug, Copy, Clone)]
+pub struct CameraKey {
+    pub frame: f64,
+    pub lookfrom: Point,
+    pub lookat: Point,
+    pub vup: Vec3,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub dist_to_focus: f64,
+}
+
+/**
+ * Camera moving smoothly through a series of keys.
+ *
+ * Every setting follows a Catmull-Rom spline through its values at the keys, so the camera passes through each key
+ * without sudden changes of speed. Keys may be any number of frames apart. Before the first key and after the last
+ * the camera holds still.
+ */
+pub struct CameraPath {
+    keys: Vec<CameraKey>,
+}
+
+impl CameraPath {
+    /// Path through keys, in any order. Returns None if there are none.
+    pub fn new(mut keys: Vec<CameraKey>) -> Option<CameraPath> {
+        if keys.is_empty() {
+            return None;
+        }
+        keys.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+
+        Some(CameraPath { keys })
+    }
+
+    pub fn at(&self, frame: f64) -> CameraKey {
+        let keys = &self.keys;
+        let next = keys.partition_point(|k| k.frame <= frame);
+        if next == 0 || next == keys.len() {
+            return CameraKey {
+                frame,
+                ..keys[next.saturating_sub(1)]
+            };
+        }
+
+        let i = next - 1;
+        let frames: Vec<f64> = keys.iter().map(|k| k.frame).collect();
+
+        CameraKey {
+            frame,
+            lookfrom: spline(&frames, i, frame, |k| keys[k].lookfrom),
+            lookat: spline(&frames, i, frame, |k| keys[k].lookat),
+            vup: spline(&frames, i, frame, |k| keys[k].vup),
+            vfov: spline(&frames, i, frame, |k| keys[k].vfov),
+            aperture: f64::max(0.0, spline(&frames, i, frame, |k| keys[k].aperture)),
+            dist_to_focus: spline(&frames, i, frame, |k| keys[k].dist_to_focus),
+        }
+    }
+}
+
+/**
+ * Catmull-Rom spline through value(k) at frames[k], evaluated at frame between keys i and i + 1.
+ *
+ * The slope at each key is that of the line between its neighbours, or towards its only neighbour at either end, so
+ * unevenly spaced keys keep a steady speed through each key.
+ */
+fn spline<T>(frames: &[f64], i: usize, frame: f64, value: impl Fn(usize) -> T) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>,
+{
+    let last = frames.len() - 1;
+    let slope = |k: usize| -> T {
+        let (a, b) = (k.saturating_sub(1), usize::min(k + 1, last));
+        (value(b) - value(a)) * (1.0 / (frames[b] - frames[a]))
+    };
+
+    let h = frames[i + 1] - frames[i];
+    let s = (frame - frames[i]) / h;
+    let (s2, s3) = (s * s, s * s * s);
+
+    // Cubic Hermite basis, with the slopes scaled from per frame to per segment
+    value(i) * (2.0 * s3 - 3.0 * s2 + 1.0)
+        + slope(i) * ((s3 - 2.0 * s2 + s) * h)
+        + value(i + 1) * (-2.0 * s3 + 3.0 * s2)
+        + slope(i + 1) * ((s3 - s2) * h)
+}
+
+/**
+ * Parses a range of frames, given as START..END with END excluded, START..=END with END included, or a single
+ * frame number.
+ */
+pub fn parse_frames(s: &str) -> Result<Range<u32>, String> {
+    let number = |n: &str| -> Result<u32, String> {
+        n.trim()
+            .parse::<u32>()
+            .map_err(|_| format!("'{}' is not a valid frame number", n))
+    };
+
+    let range = if let Some((a, b)) = s.split_once("..=") {
+        number(a)?..number(b)?.saturating_add(1)
+    } else if let Some((a, b)) = s.split_once("..") {
+        number(a)?..number(b)?
+    } else {
+        let f = number(s)?;
+        f..f.saturating_add(1)
+    };
+
+    if range.is_empty() {
+        return Err(String::from("The range contains no frames"));
+    }
+    Ok(range)
+}
+
+/// Name for one frame of a sequence saved as fname, such as out_0001.png for frame 1 of out.png
+pub fn frame_fname(fname: &str, frame: u32) -> String {
+    let path = Path::new(fname);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => path
+            .with_file_name(format!(
+                "{}_{:04}.{}",
+                stem.to_string_lossy(),
+                frame,
+                ext.to_string_lossy()
+            ))
+            .to_string_lossy()
+            .into_owned(),
+        _ => format!("{}_{:04}", fname, frame),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(frame: f64, x: f64, vfov: f64) -> CameraKey {
+        CameraKey {
+            frame,
+            lookfrom: Point::new(x, 1.0, -x),
+            lookat: Point::new(0.0, 0.0, 0.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov,
+            aperture: 0.1,
+            dist_to_focus: 10.0,
+        }
+    }
+
+    fn assert_pose(a: &CameraKey, b: &CameraKey) {
+        assert!(
+            (a.lookfrom - b.lookfrom).length() < 1e-9,
+            "{:?} is not {:?}",
+            a,
+            b
+        );
+        assert!(
+            (a.lookat - b.lookat).length() < 1e-9,
+            "{:?} is not {:?}",
+            a,
+            b
+        );
+        assert!((a.vfov - b.vfov).abs() < 1e-9, "{:?} is not {:?}", a, b);
+        assert!(
+            (a.aperture - b.aperture).abs() < 1e-9,
+            "{:?} is not {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn parses_ranges_and_single_frames() {
+        assert_eq!(parse_frames("0..120"), Ok(0..120));
+        assert_eq!(parse_frames("10..=20"), Ok(10..21));
+        assert_eq!(parse_frames("7"), Ok(7..8));
+        assert_eq!(parse_frames(" 3 .. 5 "), Ok(3..5));
+        assert_eq!(parse_frames("0..=4294967295"), Ok(0..u32::MAX));
+    }
+
+    #[test]
+    fn rejects_empty_and_malformed_ranges() {
+        for s in ["5..5", "9..2", "..4", "a..b", "1...3", "-1", ""] {
+            assert!(parse_frames(s).is_err(), "{} parsed", s);
+        }
+    }
+
+    #[test]
+    fn numbers_frames_before_the_extension() {
+        assert_eq!(frame_fname("out.png", 1), "out_0001.png");
+        assert_eq!(
+            frame_fname("renders/shot.exr", 120),
+            "renders/shot_0120.exr"
+        );
+        assert_eq!(frame_fname("out", 12345), "out_12345");
+    }
+
+    #[test]
+    fn path_passes_through_its_keys() {
+        let keys = vec![
+            key(24.0, 5.0, 30.0),
+            key(0.0, 0.0, 40.0),
+            key(10.0, 2.0, 60.0),
+        ];
+        let path = CameraPath::new(keys.clone()).unwrap();
+
+        for k in &keys {
+            let at = path.at(k.frame);
+            assert_eq!(at.frame, k.frame);
+            assert_pose(&at, k);
+        }
+
+        // Held still outside the keys
+        assert_pose(&path.at(-5.0), &keys[1]);
+        assert_pose(&path.at(100.0), &keys[0]);
+        assert_eq!(path.at(100.0).frame, 100.0);
+    }
+
+    #[test]
+    fn steady_motion_stays_steady() {
+        // Unevenly spaced keys along a straight line at a constant speed
+        let path = CameraPath::new(vec![
+            key(0.0, 0.0, 40.0),
+            key(4.0, 4.0, 40.0),
+            key(20.0, 20.0, 40.0),
+        ])
+        .unwrap();
+        for frame in [1.0, 2.5, 7.0, 13.0, 19.5] {
+            assert_pose(&path.at(frame), &key(frame, frame, 40.0));
+        }
+    }
+
+    #[test]
+    fn a_single_key_holds_still() {
+        let path = CameraPath::new(vec![key(3.0, 1.0, 50.0)]).unwrap();
+        assert_pose(&path.at(0.0), &key(0.0, 1.0, 50.0));
+        assert_pose(&path.at(9.0), &key(9.0, 1.0, 50.0));
+        assert!(CameraPath::new(Vec::new()).is_none());
+    }
+}
@@ -0,0 +1,542 @@
+//! Auxiliary output images (AOVs) describing what each pixel first sees, for compositing and denoising
+
+use crate::{
+    accumulator::Accumulator,
+    camera::Camera,
+    color,
+    environment::Environment,
+    hittable::*,
+    picture::{Picture, PictureErr, PictureType},
+    util::*,
+    vec3::*,
+};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// Jittered rays averaged for the normal and albedo of each pixel, so edges are antialiased like the color image
+const AOV_SAMPLES: u32 = 8;
+// Keeps AOV random streams apart from the color image's
+const AOV_STREAM: u64 = 0x414F_5653;
+
+arg_enum! {
+    /**
+     * Public AovType enum naming each auxiliary output.
+     *
+     * Normal: shading normal of the first hit, facing the camera
+     * Albedo: base color of the first hit's material, or the background where nothing is hit
+     * Depth: distance from the camera to the first hit
+     * Position: world position of the first hit
+     * Id: number of the first hit's material, counting from 1 in the order materials first appear, top left to bottom right
+     */
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum AovType {
+        Normal,
+        Albedo,
+        Depth,
+        Position,
+        Id,
+    }
+}
+
+impl AovType {
+    fn name(&self) -> &'static str {
+        match self {
+            AovType::Normal => "normal",
+            AovType::Albedo => "albedo",
+            AovType::Depth => "depth",
+            AovType::Position => "position",
+            AovType::Id => "id",
+        }
+    }
+}
+
+/**
+ * Auxiliary values for every pixel, in the same order as Accumulator.
+ *
+ * Pixels where nothing is hit have a zero normal, depth, position and id. The light emitted by the first hit is
+ * kept too, for the denoiser, though it isn't an output of its own.
+ */
+pub struct Aovs {
+    width: u32,
+    height: u32,
+    normal: Vec<Vec3>,
+    albedo: Vec<Color>,
+    depth: Vec<f64>,
+    position: Vec<Point>,
+    id: Vec<u32>,
+    emitted: Vec<Color>,
+}
+
+struct PixelAovs {
+    normal: Vec3,
+    albedo: Color,
+    emitted: Color,
+    depth: f64,
+    position: Point,
+    // Address of the material hit, identifying it until ids are assigned
+    material: Option<usize>,
+}
+
+impl Aovs {
+    /**
+     * Traces primary rays to find the auxiliary values of every pixel of a width by height image.
+     *
+     * Normal and albedo average several rays across the pixel, while depth, position and id come from a single ray
+     * through its center, as averages of those would describe points that aren't in the scene.
+     */
+    pub fn render(
+        width: u32,
+        height: u32,
+        cam: &(dyn Camera + Sync),
+        world: &(dyn Hittable + Sync),
+        background: &Color,
+        environment: Option<&(dyn Environment + Sync + Send)>,
+        seed: u64,
+    ) -> Aovs {
+        // Points the camera doesn't cover are a miss, with no albedo rather than the background's. Other misses
+        // return what they see of the background.
+        let trace = |u: f64, v: f64| -> (bool, HitRecord, f64, Option<Color>) {
+            let mut rec = HitRecord::new();
+            match cam.get_ray(u, v) {
+                Some(r) => {
+                    let hit = world.hit(&r, 0.001, INFINITY, &mut rec);
+                    let seen = match environment {
+                        Some(env) if !hit => env.value(&r.direction()),
+                        _ => *background,
+                    };
+                    (hit, rec, r.direction().length(), Some(seen))
+                }
+                None => (false, rec, 0.0, None),
+            }
+        };
+
+        let pixels: Vec<PixelAovs> = (0..width * height)
+            .into_par_iter()
+            .map(|index| {
+                let (col, row) = (index % width, index / width);
+                let j = height - row - 1;
+                seed_rng(stream_seed(seed ^ AOV_STREAM, index as u64));
+
+                let mut normal = Vec3::new_e();
+                let mut albedo = Color::new_e();
+                let mut emitted = Color::new_e();
+                for _ in 0..AOV_SAMPLES {
+                    let u = (col as f64 + random_double()) / (width - 1) as f64;
+                    let v = (j as f64 + random_double()) / (height - 1) as f64;
+                    let (hit, rec, _, seen) = trace(u, v);
+                    if hit {
+                        normal += rec.normal;
+                        albedo += rec.mat_ptr.albedo(rec.u, rec.v, &rec.p);
+                        emitted += rec.mat_ptr.emitted(rec.u, rec.v, &rec.p);
+                    } else if let Some(b) = seen {
+                        albedo += b;
+                    }
+                }
+
+                // A lens barrel may block some rays through the center, so the first that isn't blocked is used
+                let u = (col as f64 + 0.5) / (width - 1) as f64;
+                let v = (j as f64 + 0.5) / (height - 1) as f64;
+                let (hit, rec, dir_len, _) = (1..AOV_SAMPLES)
+                    .map(|_| trace(u, v))
+                    .find(|(_, _, _, seen)| seen.is_some())
+                    .unwrap_or_else(|| trace(u, v));
+
+                PixelAovs {
+                    normal: if normal.length_squared() > 0.0 {
+                        unit_vector(normal)
+                    } else {
+                        normal
+                    },
+                    albedo: albedo / AOV_SAMPLES as f64,
+                    emitted: emitted / AOV_SAMPLES as f64,
+                    depth: if hit { rec.t * dir_len } else { 0.0 },
+                    position: if hit { rec.p } else { Point::new_e() },
+                    material: if hit {
+                        Some(Arc::as_ptr(&rec.mat_ptr) as *const () as usize)
+                    } else {
+                        None
+                    },
+                }
+            })
+            .collect();
+
+        // Numbered in raster order, so ids are the same from run to run
+        let mut ids: HashMap<usize, u32> = HashMap::new();
+        let id = pixels
+            .iter()
+            .map(|p| match p.material {
+                Some(m) => {
+                    let next = ids.len() as u32 + 1;
+                    *ids.entry(m).or_insert(next)
+                }
+                None => 0,
+            })
+            .collect();
+
+        Aovs {
+            width,
+            height,
+            normal: pixels.iter().map(|p| p.normal).collect(),
+            albedo: pixels.iter().map(|p| p.albedo).collect(),
+            depth: pixels.iter().map(|p| p.depth).collect(),
+            position: pixels.iter().map(|p| p.position).collect(),
+            id,
+            emitted: pixels.iter().map(|p| p.emitted).collect(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Whether anything is hit through the pixel at index
+    pub fn hit(&self, index: usize) -> bool {
+        self.id[index] != 0
+    }
+
+    pub fn normal(&self, index: usize) -> Vec3 {
+        self.normal[index]
+    }
+
+    pub fn albedo(&self, index: usize) -> Color {
+        self.albedo[index]
+    }
+
+    pub fn depth(&self, index: usize) -> f64 {
+        self.depth[index]
+    }
+
+    /// Light emitted towards the camera by the first hit, averaged over the pixel
+    pub fn emitted(&self, index: usize) -> Color {
+        self.emitted[index]
+    }
+
+    /// Values of aov as colors, with single channel values repeated in each channel
+    fn raw(&self, aov: AovType) -> Vec<Color> {
+        match aov {
+            AovType::Normal => self.normal.clone(),
+            AovType::Albedo => self.albedo.clone(),
+            AovType::Depth => self.depth.iter().map(|&d| Color::new(d, d, d)).collect(),
+            AovType::Position => self.position.clone(),
+            AovType::Id => self
+                .id
+                .iter()
+                .map(|&i| Color::new(i as f64, i as f64, i as f64))
+                .collect(),
+        }
+    }
+
+    /**
+     * Values of aov mapped into [0, 1] for 8 and 16 bit images.
+     *
+     * Normals are mapped from [-1, 1], depth is scaled by the furthest hit, positions are scaled to the box around
+     * every hit, and ids are given distinct colors.
+     */
+    fn display(&self, aov: AovType) -> Vec<Color> {
+        match aov {
+            AovType::Normal => self
+                .normal
+                .iter()
+                .map(|&n| 0.5 * (n + Vec3::new(1.0, 1.0, 1.0)))
+                .collect(),
+            AovType::Albedo => self.albedo.clone(),
+            AovType::Depth => {
+                let max = self.depth.iter().copied().fold(0.0, f64::max);
+                let scale = if max > 0.0 { 1.0 / max } else { 0.0 };
+                self.depth
+                    .iter()
+                    .map(|&d| Color::new(d * scale, d * scale, d * scale))
+                    .collect()
+            }
+            AovType::Position => {
+                let hits: Vec<Point> = (0..self.id.len())
+                    .filter(|&i| self.id[i] != 0)
+                    .map(|i| self.position[i])
+                    .collect();
+                let (mut min, mut max) = (Point::new_e(), Point::new_e());
+                if let Some(first) = hits.first() {
+                    min = *first;
+                    max = *first;
+                }
+                for p in &hits {
+                    for a in 0..3 {
+                        min[a] = f64::min(min[a], p[a]);
+                        max[a] = f64::max(max[a], p[a]);
+                    }
+                }
+
+                (0..self.id.len())
+                    .map(|i| {
+                        if self.id[i] == 0 {
+                            return Color::new_e();
+                        }
+                        let mut c = Color::new_e();
+                        for a in 0..3 {
+                            let extent = max[a] - min[a];
+                            c[a] = if extent > 0.0 {
+                                (self.position[i][a] - min[a]) / extent
+                            } else {
+                                0.5
+                            };
+                        }
+                        c
+                    })
+                    .collect()
+            }
+            AovType::Id => self.id.iter().map(|&i| id_color(i)).collect(),
+        }
+    }
+
+    /**
+     * Saves aov as its own image of outtype, named after fname with the AOV's name before the extension.
+     *
+     * Float types store the values themselves. Other types store them mapped for display, with albedo sRGB encoded
+     * and everything else linear.
+     */
+    pub fn save(
+        &self,
+        aov: AovType,
+        fname: &str,
+        aspect_ratio: f64,
+        outtype: PictureType,
+    ) -> Result<(), PictureErr> {
+        let float = matches!(
+            outtype,
+            PictureType::Exr | PictureType::Hdr | PictureType::Pfm
+        );
+        let values = if float {
+            self.raw(aov)
+        } else {
+            self.display(aov)
+        };
+
+        let tone = color::ToneSettings {
+            tonemap: color::ToneMap::Clamp,
+            exposure: 0.0,
+            white_point: None,
+            transfer: if aov == AovType::Albedo {
+                color::Transfer::Srgb
+            } else {
+                color::Transfer::Linear
+            },
+        };
+
+        let mut img = Picture::new(
+            self.width,
+            aspect_ratio,
+            1,
+            &aov_fname(fname, aov.name()),
+            outtype,
+            tone,
+        )?;
+
+        let width = self.width as usize;
+        for r in 0..self.height {
+            let start = r as usize * width;
+            img.write_row(&values[start..start + width], r)?;
+        }
+
+        img.save()
+    }
+
+    /**
+     * Saves the average radiance in acc and each AOV in aovs as layers of one OpenEXR file.
+     *
+     * The color is the first layer, named color, and each AOV is a layer named after it.
+     */
+    pub fn save_layers(
+        &self,
+        fname: &str,
+        acc: &Accumulator,
+        aovs: &[AovType],
+    ) -> Result<(), exr::error::Error> {
+        use exr::prelude::*;
+
+        let size = Vec2(self.width as usize, self.height as usize);
+        let channel =
+            |name: &str, values: Vec<f32>| AnyChannel::new(name, FlatSamples::F32(values));
+        let split = |values: &[Vec3], names: [&str; 3]| -> Vec<AnyChannel<FlatSamples>> {
+            (0..3)
+                .map(|a| channel(names[a], values.iter().map(|v| v[a] as f32).collect()))
+                .collect()
+        };
+        let layer = |name: &str, channels: Vec<AnyChannel<FlatSamples>>| {
+            Layer::new(
+                size,
+                LayerAttributes::named(name),
+                Encoding::FAST_LOSSLESS,
+                AnyChannels::sort(channels.into()),
+            )
+        };
+
+        let color: Vec<Color> = (0..acc.len()).map(|i| acc.average(i)).collect();
+        let mut layers = vec![layer("color", split(&color, ["R", "G", "B"]))];
+
+        for aov in aovs {
+            let channels = match aov {
+                AovType::Normal => split(&self.normal, ["X", "Y", "Z"]),
+                AovType::Albedo => split(&self.albedo, ["R", "G", "B"]),
+                AovType::Depth => {
+                    vec![channel("Z", self.depth.iter().map(|&d| d as f32).collect())]
+                }
+                AovType::Position => split(&self.position, ["X", "Y", "Z"]),
+                AovType::Id => vec![channel("id", self.id.iter().map(|&i| i as f32).collect())],
+            };
+            layers.push(layer(aov.name(), channels));
+        }
+
+        let attributes = ImageAttributes::new(IntegerBounds::from_dimensions(size));
+        Image::from_layers(attributes, layers)
+            .write()
+            .to_file(fname)
+    }
+}
+
+/// Name for an AOV image beside fname, such as render.normal.png for render.png
+pub fn aov_fname(fname: &str, aov: &str) -> String {
+    let path = Path::new(fname);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => path
+            .with_file_name(format!(
+                "{}.{}.{}",
+                stem.to_string_lossy(),
+                aov,
+                ext.to_string_lossy()
+            ))
+            .to_string_lossy()
+            .into_owned(),
+        _ => format!("{}.{}", fname, aov),
+    }
+}
+
+/// Distinct color for each id, with black for no hit
+fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::new_e();
+    }
+
+    // Golden ratio steps around the hue circle keep neighbouring ids apart
+    let hue = (id as f64 * 0.618_033_988_749_895).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    Color::new(r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aarect::XYRect,
+        animation::CameraKey,
+        camera::{new_camera, Projection, ProjectionType},
+        hittable_list::HittableList,
+        lens::Lens,
+        materials::{Lambertian, Material},
+    };
+
+    /**
+     * Eight by four pixels of rectangles facing an orthographic camera 8 units across.
+     *
+     * Pixel centers are at x = -3.43, -2.29, -1.14, 0, 1.14, 2.29, 3.43, 4.57 across, and y = 2.67, 1.33, 0, -1.33
+     * down. The rectangles are added in the reverse of the order they first appear in.
+     */
+    fn rects() -> Aovs {
+        let material = |g: f64| -> Arc<dyn Material + Sync + Send> {
+            Arc::new(Lambertian::new(Color::new(g, g, g)))
+        };
+        let (first, second, third) = (material(0.2), material(0.4), material(0.6));
+        let rect = |x0, x1, y0, y1, m: &Arc<dyn Material + Sync + Send>| {
+            Arc::new(XYRect::new(x0, x1, y0, y1, -5.0, Arc::clone(m)))
+                as Arc<dyn Hittable + Sync + Send>
+        };
+        let world = HittableList {
+            objects: vec![
+                // Bottom row, the middle column
+                rect(-0.5, 0.5, -2.0, -1.0, &third),
+                // Second row, the right three columns
+                rect(2.0, 5.0, 1.0, 2.0, &second),
+                // Top two rows, the left two columns
+                rect(-4.0, -1.7, 0.5, 3.0, &first),
+                // Bottom row, the fifth column, in a material already seen
+                rect(1.0, 1.5, -2.0, -1.0, &first),
+            ],
+        };
+
+        let projection = Projection {
+            kind: ProjectionType::Orthographic,
+            view_width: Some(8.0),
+            ..Projection::perspective()
+        };
+        let pose = CameraKey {
+            frame: 0.0,
+            lookfrom: Point::new(0.0, 0.0, 0.0),
+            lookat: Point::new(0.0, 0.0, -1.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 90.0,
+            aperture: 0.0,
+            dist_to_focus: 1.0,
+        };
+        let cam = new_camera(&projection, &Lens::circle(), &pose, 2.0, 0.0, 1.0);
+        Aovs::render(
+            8,
+            4,
+            cam.as_ref(),
+            &world,
+            &Color::new(0.1, 0.2, 0.3),
+            None,
+            1,
+        )
+    }
+
+    #[test]
+    fn ids_count_materials_in_raster_order() {
+        let aovs = rects();
+        #[rustfmt::skip]
+        let expected = [
+            1, 1, 0, 0, 0, 0, 0, 0,
+            1, 1, 0, 0, 0, 2, 2, 2,
+            0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 3, 1, 0, 0, 0,
+        ];
+        assert_eq!(aovs.id, expected);
+    }
+
+    #[test]
+    fn misses_see_the_background() {
+        let aovs = rects();
+        for i in 0..aovs.id.len() {
+            if aovs.hit(i) {
+                assert!((aovs.depth(i) - 5.0).abs() < 1e-9);
+                assert!((aovs.position[i].z() + 5.0).abs() < 1e-9);
+            } else {
+                assert_eq!(aovs.depth(i), 0.0, "pixel {}", i);
+                assert_eq!(aovs.position[i], Point::new_e(), "pixel {}", i);
+            }
+        }
+
+        // Pixels no jittered ray can reach a rectangle from have no normal and see the background as their albedo
+        for i in [3, 4, 5, 11, 19, 20, 21, 22, 23] {
+            assert_eq!(aovs.normal(i), Vec3::new_e(), "pixel {}", i);
+            assert_eq!(aovs.emitted(i), Color::new_e());
+            assert!(
+                (aovs.albedo(i) - Color::new(0.1, 0.2, 0.3)).length() < 1e-9,
+                "pixel {} has albedo {}",
+                i,
+                aovs.albedo(i)
+            );
+        }
+    }
+}
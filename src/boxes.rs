@@ -85,4 +85,12 @@ impl Hittable for Box {
     fn hit(&self, r: &Ray, t0: f64, t1: f64, rec: &mut HitRecord) -> bool {
         self.sides.hit(r, t0, t1, rec)
     }
+
+    fn pdf_value(&self, o: &Point, v: &Vec3) -> f64 {
+        self.sides.pdf_value(o, v)
+    }
+
+    fn random(&self, o: &Point) -> Vec3 {
+        self.sides.random(o)
+    }
 }
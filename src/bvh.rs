@@ -1,20 +1,297 @@
-use crate::{aabb::AABB, aabb::*, hittable::*, hittable_list::HittableList, ray::Ray, util::*};
+use crate::{
+    aabb::AABB, aabb::*, flat_bvh::FlatBvh, hittable::*, hittable_list::HittableList, ray::Ray,
+    util::*, vec3::*,
+};
 use std::cmp::Ordering;
 use std::f64;
 use std::sync::Arc;
 
+arg_enum! {
+    /**
+     * Public BvhMethod enum to select how BvhNode trees are built.
+     *
+     * Random: splits on a random axis at the median object. Quick to build, but gives poor trees that differ each run
+     * Sah: binned surface area heuristic, choosing the best axis and split position. Deterministic, and faster to trace
+     * Flat: the Sah tree stored contiguously as a FlatBvh, traversed without pointer chasing. Fastest to trace
+     */
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum BvhMethod {
+        Random,
+        Sah,
+        Flat,
+    }
+}
+
+const SAH_BINS: usize = 16;
+const SAH_MAX_LEAF: usize = 4;
+// Costs of a box test and of an object intersection, relative to each other
+const SAH_TRAVERSAL_COST: f64 = 0.125;
+const SAH_INTERSECT_COST: f64 = 1.0;
+
+/// An object being sorted into a BVH, with its bounding box cached
+pub struct BuildPrim {
+    pub obj: Arc<dyn Hittable + Sync + Send>,
+    pub bbox: AABB,
+    pub centroid: Point,
+}
+
+pub fn build_prims(
+    objects: &[Arc<dyn Hittable + Sync + Send>],
+    time0: f64,
+    time1: f64,
+) -> Vec<BuildPrim> {
+    objects
+        .iter()
+        .map(|o| {
+            let (b, bbox) = o.bounding_box(time0, time1);
+            if !b {
+                eprintln!("No bounding box in BVH construction.");
+            }
+            BuildPrim {
+                obj: Arc::clone(o),
+                bbox,
+                centroid: bbox.centroid(),
+            }
+        })
+        .collect()
+}
+
+/// Bounding box of every primitive in prims, which must not be empty
+pub fn prims_bbox(prims: &[BuildPrim]) -> AABB {
+    prims
+        .iter()
+        .skip(1)
+        .fold(prims[0].bbox, |acc, p| surrounding_box(&acc, &p.bbox))
+}
+
+/**
+ * Partitions prims about the cheapest split found by the binned surface area heuristic.
+ *
+ * Returns the index of the first primitive on the right of the split along with the axis split on, or None if prims
+ * has no more than max_leaf primitives and is cheaper to keep as a single leaf. The result depends only on the order
+ * of prims, never on chance.
+ */
+pub fn sah_partition(prims: &mut [BuildPrim], max_leaf: usize) -> Option<(usize, usize)> {
+    let n = prims.len();
+    if n <= 1 {
+        return None;
+    }
+
+    let mut cmin = prims[0].centroid;
+    let mut cmax = prims[0].centroid;
+    for p in prims.iter() {
+        for a in 0..3 {
+            cmin[a] = f64::min(cmin[a], p.centroid[a]);
+            cmax[a] = f64::max(cmax[a], p.centroid[a]);
+        }
+    }
+
+    let parent_area = f64::max(prims_bbox(prims).surface_area(), f64::MIN_POSITIVE);
+    let bin_of = |p: &BuildPrim, axis: usize| -> usize {
+        let extent = cmax[axis] - cmin[axis];
+        let b = ((p.centroid[axis] - cmin[axis]) / extent * SAH_BINS as f64) as usize;
+        usize::min(b, SAH_BINS - 1)
+    };
+
+    // (cost, axis, last bin on the left)
+    let mut best: Option<(f64, usize, usize)> = None;
+
+    for axis in 0..3 {
+        if cmax[axis] - cmin[axis] <= 0.0 {
+            continue;
+        }
+
+        let mut counts = [0usize; SAH_BINS];
+        let mut boxes: [Option<AABB>; SAH_BINS] = [None; SAH_BINS];
+        for p in prims.iter() {
+            let b = bin_of(p, axis);
+            counts[b] += 1;
+            boxes[b] = Some(match boxes[b] {
+                Some(bx) => surrounding_box(&bx, &p.bbox),
+                None => p.bbox,
+            });
+        }
+
+        // Sweep from the right to find the area and count right of each candidate split
+        let mut right_area = [0.0; SAH_BINS];
+        let mut right_count = [0usize; SAH_BINS];
+        let mut acc: Option<AABB> = None;
+        let mut count = 0;
+        for b in (1..SAH_BINS).rev() {
+            acc = merge_boxes(acc, boxes[b]);
+            count += counts[b];
+            right_area[b] = acc.map_or(0.0, |bx| bx.surface_area());
+            right_count[b] = count;
+        }
+
+        let mut acc: Option<AABB> = None;
+        let mut count = 0;
+        for b in 0..SAH_BINS - 1 {
+            acc = merge_boxes(acc, boxes[b]);
+            count += counts[b];
+            if count == 0 || right_count[b + 1] == 0 {
+                continue;
+            }
+
+            let left_area = acc.map_or(0.0, |bx| bx.surface_area());
+            let cost = SAH_TRAVERSAL_COST
+                + SAH_INTERSECT_COST
+                    * (left_area * count as f64 + right_area[b + 1] * right_count[b + 1] as f64)
+                    / parent_area;
+
+            if best.is_none_or(|(c, _, _)| cost < c) {
+                best = Some((cost, axis, b));
+            }
+        }
+    }
+
+    let (cost, axis, split_bin) = match best {
+        Some(b) => b,
+        // Every centroid is in the same place, so no split separates anything
+        None => {
+            if n <= max_leaf {
+                return None;
+            }
+            return Some((n / 2, 0));
+        }
+    };
+
+    if n <= max_leaf && SAH_INTERSECT_COST * n as f64 <= cost {
+        return None;
+    }
+
+    let mut mid = 0;
+    for i in 0..n {
+        if bin_of(&prims[i], axis) <= split_bin {
+            prims.swap(i, mid);
+            mid += 1;
+        }
+    }
+
+    Some((mid, axis))
+}
+
+fn merge_boxes(a: Option<AABB>, b: Option<AABB>) -> Option<AABB> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(surrounding_box(&a, &b)),
+        (Some(a), None) => Some(a),
+        (None, b) => b,
+    }
+}
+
+/// A BVH leaf holding a few objects, tested in turn
+pub struct BvhLeaf {
+    pub objects: Vec<Arc<dyn Hittable + Sync + Send>>,
+    pub bbox: AABB,
+}
+
+impl Hittable for BvhLeaf {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let mut hit_anything = false;
+        let mut closest_so_far = t_max;
+
+        for object in &self.objects {
+            if object.hit(r, t_min, closest_so_far, rec) {
+                hit_anything = true;
+                closest_so_far = rec.t;
+            }
+        }
+
+        hit_anything
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> (bool, AABB) {
+        (true, self.bbox)
+    }
+}
+
+fn sah_build(prims: &mut [BuildPrim], max_leaf: usize) -> Arc<dyn Hittable + Sync + Send> {
+    if prims.len() == 1 {
+        return Arc::clone(&prims[0].obj);
+    }
+
+    let bbox = prims_bbox(prims);
+    match sah_partition(prims, max_leaf) {
+        None => Arc::new(BvhLeaf {
+            objects: prims.iter().map(|p| Arc::clone(&p.obj)).collect(),
+            bbox,
+        }),
+        Some((mid, _)) => {
+            let (l, r) = prims.split_at_mut(mid);
+            Arc::new(BvhNode {
+                left: sah_build(l, SAH_MAX_LEAF),
+                right: sah_build(r, SAH_MAX_LEAF),
+                bbox,
+            })
+        }
+    }
+}
+
 pub struct BvhNode {
     pub left: Arc<dyn Hittable + Sync + Send>,
     pub right: Arc<dyn Hittable + Sync + Send>,
     pub bbox: AABB,
 }
 
+/**
+ * Builds a bounding volume hierarchy over every object in list, with the given method.
+ *
+ * Panics if list is empty, unless method is Flat, as the other trees always have two children at their root.
+ */
+pub fn build(
+    list: &mut HittableList,
+    time0: f64,
+    time1: f64,
+    method: BvhMethod,
+) -> Arc<dyn Hittable + Sync + Send> {
+    match method {
+        BvhMethod::Random => Arc::new(BvhNode::new_l(list, time0, time1)),
+        BvhMethod::Sah => Arc::new(BvhNode::new_sah(list, time0, time1)),
+        BvhMethod::Flat => Arc::new(FlatBvh::new(list, time0, time1)),
+    }
+}
+
 impl BvhNode {
     pub fn new_l(list: &mut HittableList, time0: f64, time1: f64) -> BvhNode {
         let len = list.objects.len();
         BvhNode::new(&mut list.objects, 0, len, time0, time1)
     }
 
+    /**
+     * Builds a tree using the binned surface area heuristic. Leaves hold up to a few objects where that is cheaper
+     * than splitting further. Unlike new, building the same list always gives the same tree.
+     *
+     * list must hold at least one object, as every node has two children, so panics if it is empty.
+     */
+    pub fn new_sah(list: &HittableList, time0: f64, time1: f64) -> BvhNode {
+        assert!(
+            !list.objects.is_empty(),
+            "BVH must be built over at least one object"
+        );
+        let mut prims = build_prims(&list.objects, time0, time1);
+
+        if prims.len() == 1 {
+            let obj = Arc::clone(&prims[0].obj);
+            return BvhNode {
+                left: Arc::clone(&obj),
+                right: obj,
+                bbox: prims[0].bbox,
+            };
+        }
+
+        // The root always splits, so that it has two children
+        let bbox = prims_bbox(&prims);
+        let (mid, _) =
+            sah_partition(&mut prims, 1).expect("BVH root with more than one object must split");
+        let (l, r) = prims.split_at_mut(mid);
+
+        BvhNode {
+            left: sah_build(l, SAH_MAX_LEAF),
+            right: sah_build(r, SAH_MAX_LEAF),
+            bbox,
+        }
+    }
+
     pub fn new(
         objects: &mut Vec<Arc<dyn Hittable + Sync + Send>>,
         start: usize,
@@ -50,7 +327,7 @@ impl BvhNode {
                 right = Arc::clone(&objects[start]);
             }
         } else {
-            objects.sort_unstable_by(|a, b| comparator(Arc::clone(a), Arc::clone(b)));
+            objects[start..end].sort_unstable_by(|a, b| comparator(Arc::clone(a), Arc::clone(b)));
 
             let mid = start + object_span / 2;
             left = Arc::new(BvhNode::new(objects, start, mid, time0, time1));
@@ -130,3 +407,115 @@ pub fn box_z_compare(
 ) -> Ordering {
     box_compare(a, b, 2)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{materials::NoHit, sphere::Sphere};
+
+    fn prims(spheres: &[(Point, f64)]) -> Vec<BuildPrim> {
+        let objects: Vec<Arc<dyn Hittable + Sync + Send>> = spheres
+            .iter()
+            .map(|(c, r)| {
+                Arc::new(Sphere::new(*c, *r, Arc::new(NoHit::new())))
+                    as Arc<dyn Hittable + Sync + Send>
+            })
+            .collect();
+        build_prims(&objects, 0.0, 1.0)
+    }
+
+    #[test]
+    fn splits_between_clusters_on_the_widest_gap() {
+        // Two clusters far apart along y, with only a little spread in x and z
+        let mut spheres = Vec::new();
+        for i in 0..4 {
+            let d = i as f64 * 0.1;
+            spheres.push((Point::new(d, d, -d), 0.1));
+            spheres.push((Point::new(d, 10.0 + d, -d), 0.1));
+        }
+        let mut p = prims(&spheres);
+
+        let (mid, axis) = sah_partition(&mut p, SAH_MAX_LEAF).expect("clusters should split");
+        assert_eq!(axis, 1);
+        assert_eq!(mid, 4);
+        assert!(p[..mid].iter().all(|q| q.centroid.y() < 5.0));
+        assert!(p[mid..].iter().all(|q| q.centroid.y() > 5.0));
+    }
+
+    #[test]
+    fn picks_the_split_with_the_lowest_cost() {
+        // Evenly spaced along x, except for one outlier far to the right, which should be split off on its own
+        let mut spheres: Vec<(Point, f64)> = (0..7)
+            .map(|i| (Point::new(i as f64, 0.0, 0.0), 0.5))
+            .collect();
+        spheres.push((Point::new(100.0, 0.0, 0.0), 0.5));
+        let mut p = prims(&spheres);
+
+        let (mid, axis) = sah_partition(&mut p, SAH_MAX_LEAF).unwrap();
+        assert_eq!(axis, 0);
+        assert_eq!(mid, 7);
+        assert_eq!(p[7].centroid.x(), 100.0);
+    }
+
+    #[test]
+    fn keeps_small_overlapping_groups_as_a_leaf() {
+        // Splitting two nearly coincident large spheres costs more than testing both
+        let mut p = prims(&[
+            (Point::new(0.0, 0.0, 0.0), 5.0),
+            (Point::new(0.01, 0.0, 0.0), 5.0),
+        ]);
+        assert_eq!(sah_partition(&mut p, SAH_MAX_LEAF), None);
+
+        let mut one = prims(&[(Point::new(0.0, 0.0, 0.0), 1.0)]);
+        assert_eq!(sah_partition(&mut one, SAH_MAX_LEAF), None);
+    }
+
+    #[test]
+    fn halves_coincident_centroids_above_the_leaf_size() {
+        let spheres: Vec<(Point, f64)> = (0..6)
+            .map(|i| (Point::new(1.0, 2.0, 3.0), 1.0 + i as f64))
+            .collect();
+
+        let mut p = prims(&spheres);
+        assert_eq!(sah_partition(&mut p, SAH_MAX_LEAF), Some((3, 0)));
+
+        let mut p = prims(&spheres[..SAH_MAX_LEAF]);
+        assert_eq!(sah_partition(&mut p, SAH_MAX_LEAF), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one object")]
+    fn sah_tree_needs_an_object() {
+        let list = HittableList {
+            objects: Vec::new(),
+        };
+        BvhNode::new_sah(&list, 0.0, 1.0);
+    }
+
+    #[test]
+    fn sah_tree_finds_the_same_hits_as_a_list() {
+        let mut list = HittableList {
+            objects: Vec::new(),
+        };
+        for i in 0..20 {
+            let c = Point::new((i * 7 % 11) as f64, (i * 3 % 5) as f64, -(i as f64));
+            list.add(Arc::new(Sphere::new(c, 0.4, Arc::new(NoHit::new()))));
+        }
+        let tree = BvhNode::new_sah(&list, 0.0, 1.0);
+
+        let mut hits = 0;
+        for i in 0..50 {
+            let origin = Point::new(5.0, 2.0, 10.0);
+            let target = Point::new((i % 10) as f64, (i / 10) as f64, -(i as f64) * 0.4);
+            let r = Ray::new(&origin, &(target - origin), 0.0);
+            let (mut a, mut b) = (HitRecord::new(), HitRecord::new());
+            let hit_list = list.hit(&r, 0.001, INFINITY, &mut a);
+            assert_eq!(hit_list, tree.hit(&r, 0.001, INFINITY, &mut b));
+            if hit_list {
+                assert_eq!(a.t, b.t);
+                hits += 1;
+            }
+        }
+        assert!(hits > 0);
+    }
+}
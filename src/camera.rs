@@ -1,9 +1,324 @@
+use crate::animation::CameraKey;
+use crate::lens::Lens;
 use crate::ray::Ray;
 use crate::util::*;
 use crate::vec3::*;
 use std::f64;
 
-pub struct Camera {
+arg_enum! {
+    /**
+     * Public ProjectionType enum to select how the camera maps the scene onto the image.
+     *
+     * Perspective: thin lens perspective, with depth of field from the aperture
+     * Orthographic: parallel rays, keeping sizes the same at every distance
+     * Fisheye: circular fisheye fitting the shorter side of the image, black beyond the circle
+     * Equirectangular: full 360 degree panorama, longitude across the image and latitude up it
+     */
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum ProjectionType {
+        Perspective,
+        Orthographic,
+        Fisheye,
+        Equirectangular,
+    }
+}
+
+arg_enum! {
+    /**
+     * Public FisheyeMapping enum to select how a fisheye spaces angles across the image.
+     *
+     * Equidistant: distance from the center proportional to the angle off the view direction
+     * Equisolid: each area of the image covering the same solid angle, as many real fisheye lenses do
+     */
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum FisheyeMapping {
+        Equidistant,
+        Equisolid,
+    }
+}
+
+arg_enum! {
+    /**
+     * Public StereoFormat enum to select how the two eyes of a stereo render are laid out in the image.
+     *
+     * SideBySide: left eye in the left half of the image, right eye in the right half
+     * TopBottom: left eye in the top half of the image, right eye in the bottom half
+     * Ods: omni-directional stereo, a full equirectangular panorama for each eye, laid out top and bottom
+     */
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum StereoFormat {
+        SideBySide,
+        TopBottom,
+        Ods,
+    }
+}
+
+arg_enum! {
+    /**
+     * Public StereoMethod enum to select how the eyes of a side by side or top bottom render converge.
+     *
+     * OffAxis: parallel eyes with their images shifted to meet at the convergence distance, without vertical parallax
+     * ToeIn: eyes turned in to look at the point at the convergence distance, as a pair of real cameras would be
+     */
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum StereoMethod {
+        OffAxis,
+        ToeIn,
+    }
+}
+
+/// Projection and its settings, which aren't animated
+#[derive(Debug, Clone, Copy)]
+pub struct Projection {
+    pub kind: ProjectionType,
+    /// Width of an orthographic view in world units, or the width a perspective view would have at lookat if None
+    pub view_width: Option<f64>,
+    /// Field of view in degrees across the fisheye's image circle
+    pub fisheye_fov: f64,
+    pub fisheye_mapping: FisheyeMapping,
+}
+
+impl Projection {
+    pub fn perspective() -> Projection {
+        Projection {
+            kind: ProjectionType::Perspective,
+            view_width: None,
+            fisheye_fov: 180.0,
+            fisheye_mapping: FisheyeMapping::Equidistant,
+        }
+    }
+}
+
+/// Stereo settings, which aren't animated. A mono render has no format.
+#[derive(Debug, Clone, Copy)]
+pub struct Stereo {
+    pub format: Option<StereoFormat>,
+    /// Distance between the eyes in world units, or a thirtieth of the convergence distance if None
+    pub interocular: Option<f64>,
+    /// Distance in front of the camera that appears at the depth of the screen, or the distance to lookat if None
+    pub convergence: Option<f64>,
+    pub method: StereoMethod,
+}
+
+impl Stereo {
+    pub fn mono() -> Stereo {
+        Stereo {
+            format: None,
+            interocular: None,
+            convergence: None,
+            method: StereoMethod::OffAxis,
+        }
+    }
+
+    /// Aspect ratio of the whole image, holding both eyes that each have aspect_ratio
+    pub fn image_aspect_ratio(&self, aspect_ratio: f64) -> f64 {
+        match self.format {
+            None => aspect_ratio,
+            Some(StereoFormat::SideBySide) => 2.0 * aspect_ratio,
+            Some(StereoFormat::TopBottom) | Some(StereoFormat::Ods) => aspect_ratio / 2.0,
+        }
+    }
+}
+
+pub trait Camera {
+    /**
+     * Ray through the point s across and t up the image, both from 0 to 1, at a random time in the shutter interval.
+     *
+     * Returns None for points the projection doesn't cover, which are black.
+     */
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray>;
+}
+
+/**
+ * Builds the camera for projection, placed and set up as in pose, with its shutter open from t0 to t1.
+ *
+ * Only the perspective camera has a lens, so lens, the aperture and focus distance don't affect other projections.
+ */
+pub fn new_camera(
+    projection: &Projection,
+    lens: &Lens,
+    pose: &CameraKey,
+    aspect_ratio: f64,
+    t0: f64,
+    t1: f64,
+) -> Box<dyn Camera + Sync + Send> {
+    let view = View::new(&pose.lookfrom, &pose.lookat, &pose.vup, t0, t1);
+
+    match projection.kind {
+        ProjectionType::Perspective => Box::new(PerspectiveCamera::new(
+            &pose.lookfrom,
+            &pose.lookat,
+            &pose.vup,
+            pose.vfov,
+            aspect_ratio,
+            pose.aperture,
+            pose.dist_to_focus,
+            lens,
+            t0,
+            t1,
+        )),
+        ProjectionType::Orthographic => {
+            let width = projection.view_width.unwrap_or_else(|| {
+                let h = f64::tan(degs_to_rads(pose.vfov) / 2.0);
+                2.0 * h * aspect_ratio * (pose.lookfrom - pose.lookat).length()
+            });
+            Box::new(OrthographicCamera {
+                view,
+                width,
+                height: width / aspect_ratio,
+            })
+        }
+        ProjectionType::Fisheye => Box::new(FisheyeCamera {
+            view,
+            aspect_ratio,
+            half_fov: degs_to_rads(projection.fisheye_fov) / 2.0,
+            mapping: projection.fisheye_mapping,
+        }),
+        ProjectionType::Equirectangular => Box::new(EquirectangularCamera { view }),
+    }
+}
+
+/**
+ * Builds the camera for a width by height image holding both eyes of a stereo render, each with aspect_ratio.
+ *
+ * The eyes are set interocular apart across the view, centered on lookfrom. Side by side and top bottom renders
+ * give each eye the camera for projection, and off-axis convergence shifts only the perspective camera's image, so
+ * other projections keep parallel eyes. Ods ignores projection, convergence and method, as each eye is a panorama
+ * converging at infinity.
+ *
+ * Returns an error if the image is too small to give each eye at least 2 by 2 pixels.
+ */
+pub fn new_stereo_camera(
+    projection: &Projection,
+    lens: &Lens,
+    stereo: &Stereo,
+    pose: &CameraKey,
+    aspect_ratio: f64,
+    width: u32,
+    height: u32,
+    t0: f64,
+    t1: f64,
+) -> Result<Box<dyn Camera + Sync + Send>, String> {
+    let format = match stereo.format {
+        Some(f) => f,
+        None => return Ok(new_camera(projection, lens, pose, aspect_ratio, t0, t1)),
+    };
+
+    // Each eye maps its pixels to s and t from 0 to 1, which needs two of them each way
+    let (eye_width, eye_height) = match format {
+        StereoFormat::SideBySide => (width / 2, height),
+        StereoFormat::TopBottom | StereoFormat::Ods => (width, height / 2),
+    };
+    if eye_width < 2 || eye_height < 2 {
+        return Err(format!(
+            "A {}x{} stereo image leaves each eye {}x{} pixels, where at least 2x2 are needed",
+            width, height, eye_width, eye_height
+        ));
+    }
+
+    let forward = pose.lookat - pose.lookfrom;
+    let convergence = stereo.convergence.unwrap_or_else(|| forward.length());
+    let interocular = stereo.interocular.unwrap_or(convergence / 30.0);
+
+    // Left eye first, each offset by half the interocular distance along the view's right
+    let sides = [-0.5 * interocular, 0.5 * interocular];
+
+    let eyes = if format == StereoFormat::Ods {
+        sides.map(|side| -> Box<dyn Camera + Sync + Send> {
+            Box::new(OdsCamera {
+                view: View::new(&pose.lookfrom, &pose.lookat, &pose.vup, t0, t1),
+                side,
+            })
+        })
+    } else {
+        let right = unit_vector(cross(forward, pose.vup));
+        let target = pose.lookfrom + convergence * unit_vector(forward);
+
+        sides.map(|side| -> Box<dyn Camera + Sync + Send> {
+            let offset = side * right;
+            let eye = CameraKey {
+                lookfrom: pose.lookfrom + offset,
+                lookat: match stereo.method {
+                    StereoMethod::OffAxis => pose.lookat + offset,
+                    StereoMethod::ToeIn => target,
+                },
+                ..*pose
+            };
+
+            match (stereo.method, projection.kind) {
+                (StereoMethod::OffAxis, ProjectionType::Perspective) => {
+                    let mut cam = PerspectiveCamera::new(
+                        &eye.lookfrom,
+                        &eye.lookat,
+                        &eye.vup,
+                        eye.vfov,
+                        aspect_ratio,
+                        eye.aperture,
+                        eye.dist_to_focus,
+                        lens,
+                        t0,
+                        t1,
+                    );
+                    // Moving the image back across by the eye's offset, scaled from the convergence distance to the
+                    // focus plane, centers both eyes on the same point at the convergence distance
+                    cam.lower_left_corner += (-side * eye.dist_to_focus / convergence) * cam.u;
+                    Box::new(cam)
+                }
+                _ => new_camera(projection, lens, &eye, aspect_ratio, t0, t1),
+            }
+        })
+    };
+
+    Ok(Box::new(StereoCamera {
+        eyes,
+        side_by_side: format == StereoFormat::SideBySide,
+        width,
+        height,
+    }))
+}
+
+/// Position and orientation shared by the projections without a lens, with w pointing back from the view direction
+struct View {
+    origin: Point,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    time0: f64,
+    time1: f64,
+}
+
+impl View {
+    fn new(lookfrom: &Vec3, lookat: &Vec3, vup: &Vec3, t0: f64, t1: f64) -> View {
+        let w = unit_vector(*lookfrom - *lookat);
+        let u = unit_vector(cross(*vup, w));
+        let v = cross(w, u);
+
+        View {
+            origin: *lookfrom,
+            u,
+            v,
+            w,
+            time0: t0,
+            time1: t1,
+        }
+    }
+
+    fn ray(&self, origin: &Point, direction: &Vec3) -> Ray {
+        Ray::new(
+            origin,
+            direction,
+            random_double_range(self.time0, self.time1),
+        )
+    }
+
+    /// Direction at polar angle theta from the view direction, turned phi counter-clockwise from u
+    fn direction(&self, theta: f64, phi: f64) -> Vec3 {
+        f64::sin(theta) * (f64::cos(phi) * self.u + f64::sin(phi) * self.v)
+            - f64::cos(theta) * self.w
+    }
+}
+
+pub struct PerspectiveCamera {
     origin: Point,
     lower_left_corner: Point,
     horizontal: Vec3,
@@ -12,11 +327,15 @@ pub struct Camera {
     v: Vec3,
     w: Vec3,
     lens_radius: f64,
+    lens: Lens,
+    aspect_ratio: f64,
+    // Half the image's diagonal, measured in image heights
+    half_diagonal: f64,
     time0: f64,
     time1: f64,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     pub fn new(
         lookfrom: &Vec3,
         lookat: &Vec3,
@@ -25,9 +344,10 @@ impl Camera {
         aspect_ratio: f64,
         aperture: f64,
         focus_dist: f64,
+        lens: &Lens,
         t0: f64,
         t1: f64,
-    ) -> Camera {
+    ) -> PerspectiveCamera {
         let theta = degs_to_rads(vfov);
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h;
@@ -42,7 +362,7 @@ impl Camera {
         let vertical = focus_dist * viewport_height * v;
         let lower_left_corner = *origin - horizontal / 2.0 - vertical / 2.0 - focus_dist * w;
 
-        Camera {
+        PerspectiveCamera {
             origin: *origin,
             horizontal,
             vertical,
@@ -51,21 +371,328 @@ impl Camera {
             v,
             w,
             lens_radius: aperture / 2.0,
+            lens: lens.clone(),
+            aspect_ratio,
+            half_diagonal: f64::sqrt(aspect_ratio * aspect_ratio + 1.0) / 2.0,
             time0: t0,
             time1: t1,
         }
     }
+}
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        // Position from the middle of the image, reaching 1 at the corners, for the lens barrel to clip the aperture
+        let x = (s - 0.5) * self.aspect_ratio / self.half_diagonal;
+        let y = (t - 0.5) / self.half_diagonal;
+        // A pinhole has no barrel to block it, though it still draws a point so random streams stay the same
+        let rd = match self.lens.sample(x, y) {
+            Some(p) => self.lens_radius * p,
+            None if self.lens_radius > 0.0 => return None,
+            None => Vec3::new_e(),
+        };
         let offset = self.u * rd.x() + self.v * rd.y();
 
-        Ray::new(
+        Some(Ray::new(
             &(self.origin + offset),
             &(self.lower_left_corner + s * self.horizontal + t * self.vertical
                 - self.origin
                 - offset),
             random_double_range(self.time0, self.time1),
+        ))
+    }
+}
+
+/// Parallel rays from a width by height rectangle centered on lookfrom
+pub struct OrthographicCamera {
+    view: View,
+    width: f64,
+    height: f64,
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let v = &self.view;
+        let origin = v.origin + (s - 0.5) * self.width * v.u + (t - 0.5) * self.height * v.v;
+        Some(v.ray(&origin, &-v.w))
+    }
+}
+
+/// Circular fisheye, with the circle fitting the shorter side of the image
+pub struct FisheyeCamera {
+    view: View,
+    aspect_ratio: f64,
+    half_fov: f64,
+    mapping: FisheyeMapping,
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        // Position on the image with the circle's radius as 1
+        let fit = f64::min(self.aspect_ratio, 1.0);
+        let x = (2.0 * s - 1.0) * self.aspect_ratio / fit;
+        let y = (2.0 * t - 1.0) / fit;
+        let r = f64::sqrt(x * x + y * y);
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.half_fov,
+            FisheyeMapping::Equisolid => {
+                2.0 * f64::asin(clamp(r * f64::sin(self.half_fov / 2.0), -1.0, 1.0))
+            }
+        };
+
+        let v = &self.view;
+        Some(v.ray(&v.origin, &v.direction(theta, f64::atan2(y, x))))
+    }
+}
+
+/// Full sphere of directions, with lookat at the center of the image
+pub struct EquirectangularCamera {
+    view: View,
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let longitude = (s - 0.5) * 2.0 * PI;
+        let latitude = (t - 0.5) * PI;
+
+        let v = &self.view;
+        let dir = f64::cos(latitude) * (f64::sin(longitude) * v.u - f64::cos(longitude) * v.w)
+            + f64::sin(latitude) * v.v;
+        Some(v.ray(&v.origin, &dir))
+    }
+}
+
+/**
+ * Two eye cameras sharing one image, split between whole columns or rows of pixels so no pixel sees both eyes.
+ *
+ * Each eye maps its own pixels to s and t as a mono render of its size would.
+ */
+pub struct StereoCamera {
+    eyes: [Box<dyn Camera + Sync + Send>; 2],
+    side_by_side: bool,
+    width: u32,
+    height: u32,
+}
+
+impl Camera for StereoCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        // Position in pixels, with t up the image from the bottom row
+        let x = s * (self.width - 1) as f64;
+        let y = t * (self.height - 1) as f64;
+
+        if self.side_by_side {
+            let split = self.width / 2;
+            let (eye, x, width) = if x < split as f64 {
+                (0, x, split)
+            } else {
+                (1, x - split as f64, self.width - split)
+            };
+            self.eyes[eye].get_ray(x / (width - 1) as f64, t)
+        } else {
+            // The left eye is on top, so starts above the bottom half's rows
+            let split = self.height - self.height / 2;
+            let (eye, y, height) = if y < split as f64 {
+                (1, y, split)
+            } else {
+                (0, y - split as f64, self.height - split)
+            };
+            self.eyes[eye].get_ray(s, y / (height - 1) as f64)
+        }
+    }
+}
+
+/**
+ * One eye of an omni-directional stereo panorama.
+ *
+ * Every ray starts on a circle of radius side around lookfrom, tangent to it, so each direction is seen as by eyes
+ * turned to face it. The circle shrinks towards the poles, where eyes facing every way would disagree.
+ */
+pub struct OdsCamera {
+    view: View,
+    side: f64,
+}
+
+impl Camera for OdsCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let longitude = (s - 0.5) * 2.0 * PI;
+        let latitude = (t - 0.5) * PI;
+
+        let v = &self.view;
+        let forward = f64::sin(longitude) * v.u - f64::cos(longitude) * v.w;
+        let right = f64::cos(longitude) * v.u + f64::sin(longitude) * v.w;
+        let dir = f64::cos(latitude) * forward + f64::sin(latitude) * v.v;
+        let origin = v.origin + (self.side * f64::cos(latitude)) * right;
+        Some(v.ray(&origin, &dir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// At the origin looking down -z with y up, so u, v and -w are x, y and -z
+    fn pose(vfov: f64) -> CameraKey {
+        CameraKey {
+            frame: 0.0,
+            lookfrom: Point::new(0.0, 0.0, 0.0),
+            lookat: Point::new(0.0, 0.0, -1.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov,
+            aperture: 0.0,
+            dist_to_focus: 1.0,
+        }
+    }
+
+    fn camera(kind: ProjectionType, aspect_ratio: f64) -> Box<dyn Camera + Sync + Send> {
+        let projection = Projection {
+            kind,
+            ..Projection::perspective()
+        };
+        new_camera(
+            &projection,
+            &Lens::circle(),
+            &pose(90.0),
+            aspect_ratio,
+            0.0,
+            1.0,
         )
     }
+
+    fn assert_direction(r: &Ray, expected: Vec3) {
+        let d = unit_vector(r.direction());
+        assert!(
+            (d - unit_vector(expected)).length() < 1e-9,
+            "{} is not towards {}",
+            d,
+            expected
+        );
+    }
+
+    #[test]
+    fn perspective_spans_the_field_of_view() {
+        let cam = camera(ProjectionType::Perspective, 2.0);
+        assert_direction(&cam.get_ray(0.5, 0.5).unwrap(), Vec3::new(0.0, 0.0, -1.0));
+        // 90 degrees vertically, and twice as wide
+        assert_direction(&cam.get_ray(0.5, 1.0).unwrap(), Vec3::new(0.0, 1.0, -1.0));
+        assert_direction(&cam.get_ray(0.0, 0.0).unwrap(), Vec3::new(-2.0, -1.0, -1.0));
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let projection = Projection {
+            kind: ProjectionType::Orthographic,
+            view_width: Some(4.0),
+            ..Projection::perspective()
+        };
+        let cam = new_camera(&projection, &Lens::circle(), &pose(90.0), 2.0, 0.0, 1.0);
+        for (s, t, x, y) in [
+            (0.0, 0.0, -2.0, -1.0),
+            (1.0, 1.0, 2.0, 1.0),
+            (0.5, 0.5, 0.0, 0.0),
+        ] {
+            let r = cam.get_ray(s, t).unwrap();
+            assert!((r.origin() - Point::new(x, y, 0.0)).length() < 1e-9);
+            assert_direction(&r, Vec3::new(0.0, 0.0, -1.0));
+        }
+
+        // Without a width, it matches a perspective view's at lookat, 1 away
+        let cam = camera(ProjectionType::Orthographic, 2.0);
+        assert!((cam.get_ray(1.0, 0.5).unwrap().origin().x() - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fisheye_maps_the_circle_to_its_field_of_view() {
+        let cam = camera(ProjectionType::Fisheye, 1.0);
+        assert_direction(&cam.get_ray(0.5, 0.5).unwrap(), Vec3::new(0.0, 0.0, -1.0));
+        // 180 degrees across, so the rim looks sideways
+        assert_direction(&cam.get_ray(1.0, 0.5).unwrap(), Vec3::new(1.0, 0.0, 0.0));
+        assert_direction(&cam.get_ray(0.5, 1.0).unwrap(), Vec3::new(0.0, 1.0, 0.0));
+        // Equidistant, so halfway out is halfway round
+        assert_direction(&cam.get_ray(0.75, 0.5).unwrap(), Vec3::new(1.0, 0.0, -1.0));
+        assert!(cam.get_ray(1.0, 1.0).is_none());
+
+        // On a wide image the circle fits the height
+        let wide = camera(ProjectionType::Fisheye, 2.0);
+        assert_direction(&wide.get_ray(0.75, 0.5).unwrap(), Vec3::new(1.0, 0.0, 0.0));
+        assert!(wide.get_ray(0.9, 0.5).is_none());
+    }
+
+    #[test]
+    fn equisolid_fisheye_gives_the_center_more_room() {
+        let projection = Projection {
+            kind: ProjectionType::Fisheye,
+            fisheye_mapping: FisheyeMapping::Equisolid,
+            ..Projection::perspective()
+        };
+        let cam = new_camera(&projection, &Lens::circle(), &pose(90.0), 1.0, 0.0, 1.0);
+        assert_direction(&cam.get_ray(1.0, 0.5).unwrap(), Vec3::new(1.0, 0.0, 0.0));
+
+        let theta = 2.0 * f64::asin(0.5 * f64::sin(PI / 4.0));
+        let d = unit_vector(cam.get_ray(0.75, 0.5).unwrap().direction());
+        assert!((f64::acos(-d.z()) - theta).abs() < 1e-9);
+    }
+
+    #[test]
+    fn stereo_needs_two_pixels_each_way_for_each_eye() {
+        let stereo = |format: StereoFormat, width: u32, height: u32| {
+            let stereo = Stereo {
+                format: Some(format),
+                ..Stereo::mono()
+            };
+            let (projection, lens) = (Projection::perspective(), Lens::circle());
+            new_stereo_camera(
+                &projection,
+                &lens,
+                &stereo,
+                &pose(90.0),
+                1.0,
+                width,
+                height,
+                0.0,
+                1.0,
+            )
+        };
+
+        assert!(stereo(StereoFormat::SideBySide, 3, 10).is_err());
+        assert!(stereo(StereoFormat::SideBySide, 4, 1).is_err());
+        assert!(stereo(StereoFormat::TopBottom, 10, 3).is_err());
+        assert!(stereo(StereoFormat::Ods, 10, 3).is_err());
+
+        // The smallest that work give every pixel a ray of its own eye
+        for (format, width, height) in [
+            (StereoFormat::SideBySide, 4, 2),
+            (StereoFormat::TopBottom, 2, 4),
+            (StereoFormat::Ods, 2, 4),
+        ] {
+            let cam = stereo(format, width, height).unwrap();
+            for (s, t) in [(0.0, 0.0), (1.0, 1.0), (0.34, 0.67)] {
+                let d = cam.get_ray(s, t).unwrap().direction();
+                assert!(d.length().is_finite(), "{:?} at {} {}", format, s, t);
+            }
+        }
+    }
+
+    #[test]
+    fn equirectangular_covers_every_direction() {
+        let cam = camera(ProjectionType::Equirectangular, 2.0);
+        for (s, t, expected) in [
+            (0.5, 0.5, Vec3::new(0.0, 0.0, -1.0)),
+            (0.75, 0.5, Vec3::new(1.0, 0.0, 0.0)),
+            (0.25, 0.5, Vec3::new(-1.0, 0.0, 0.0)),
+            (0.0, 0.5, Vec3::new(0.0, 0.0, 1.0)),
+            (0.5, 0.75, Vec3::new(0.0, 1.0, -1.0)),
+        ] {
+            assert_direction(&cam.get_ray(s, t).unwrap(), expected);
+        }
+
+        // The poles are straight up and down from any longitude
+        for s in [0.1, 0.6] {
+            assert!(cam.get_ray(s, 1.0).unwrap().direction().y() > 1.0 - 1e-9);
+            assert!(cam.get_ray(s, 0.0).unwrap().direction().y() < -1.0 + 1e-9);
+        }
+    }
 }
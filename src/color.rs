@@ -4,24 +4,151 @@ use image::{ImageBuffer, Rgb};
 use std::fmt;
 use std::io;
 
-fn convert_pixel(pixel: vec3::Color, sample_count: u32) -> (f64, f64, f64) {
-    let mut r = pixel.x();
-    let mut g = pixel.y();
-    let mut b = pixel.z();
+arg_enum! {
+    /**
+     * Public ToneMap enum to select how radiance is compressed into the displayable range.
+     *
+     * Clamp: leaves values as they are, so anything brighter than 1 is clipped to white
+     * Reinhard: L / (1 + L) on luminance, never quite reaching white
+     * ExtendedReinhard: Reinhard, scaled so that the white point luminance maps to white
+     * Aces: Narkowicz's fit of the ACES filmic curve, with a toe and a soft shoulder
+     * Uncharted2: Hable's filmic curve, normalized so that the white point maps to white
+     */
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum ToneMap {
+        Clamp,
+        Reinhard,
+        ExtendedReinhard,
+        Aces,
+        Uncharted2,
+    }
+}
 
-    let scale = 1.0 / sample_count as f64;
-    r = f64::sqrt(scale * r);
-    g = f64::sqrt(scale * g);
-    b = f64::sqrt(scale * b);
+arg_enum! {
+    /**
+     * Public Transfer enum to select the curve encoding tone mapped values for display.
+     *
+     * Srgb: the piecewise sRGB curve, matching how viewers decode 8 and 16 bit images
+     * Gamma2: square root, as the raytracer originally wrote images
+     * Linear: no encoding
+     */
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum Transfer {
+        Srgb,
+        Gamma2,
+        Linear,
+    }
+}
+
+/// How averaged radiance is turned into display values for 8 and 16 bit outputs
+#[derive(Debug, Clone, Copy)]
+pub struct ToneSettings {
+    pub tonemap: ToneMap,
+    /// Exposure adjustment in stops, each doubling the brightness
+    pub exposure: f64,
+    /// Luminance mapped to white by ExtendedReinhard and Uncharted2, or None for the operator's default
+    pub white_point: Option<f64>,
+    pub transfer: Transfer,
+}
+
+/// Relative luminance of a linear color, with Rec. 709 primaries
+pub fn luminance(c: vec3::Color) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+/// Scales the color so its luminance becomes mapped, keeping the hue
+fn scale_luminance((r, g, b): (f64, f64, f64), mapped: impl Fn(f64) -> f64) -> (f64, f64, f64) {
+    let l = luminance(vec3::Color::new(r, g, b));
+    if l <= 0.0 {
+        return (0.0, 0.0, 0.0);
+    }
+    let s = mapped(l) / l;
+    (r * s, g * s, b * s)
+}
 
-    (r, g, b)
+fn aces(x: f64) -> f64 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+fn uncharted2(x: f64) -> f64 {
+    const A: f64 = 0.15;
+    const B: f64 = 0.50;
+    const C: f64 = 0.10;
+    const D: f64 = 0.20;
+    const E: f64 = 0.02;
+    const F: f64 = 0.30;
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+fn srgb(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn tone_map(rgb: (f64, f64, f64), tone: &ToneSettings) -> (f64, f64, f64) {
+    let per_channel = |f: &dyn Fn(f64) -> f64| (f(rgb.0), f(rgb.1), f(rgb.2));
+
+    match tone.tonemap {
+        ToneMap::Clamp => rgb,
+        ToneMap::Reinhard => scale_luminance(rgb, |l| l / (1.0 + l)),
+        ToneMap::ExtendedReinhard => {
+            let w = tone.white_point.unwrap_or(4.0);
+            scale_luminance(rgb, |l| l * (1.0 + l / (w * w)) / (1.0 + l))
+        }
+        ToneMap::Aces => per_channel(&aces),
+        ToneMap::Uncharted2 => {
+            // Hable's exposure bias brings typical scenes into the curve's useful range. The white point is before
+            // the bias, so the default is his white of 11.2 halved
+            let w = tone.white_point.unwrap_or(5.6);
+            let white_scale = 1.0 / uncharted2(2.0 * w);
+            per_channel(&|c| uncharted2(2.0 * c) * white_scale)
+        }
+    }
+}
+
+/**
+ * Converts a pixel's summed samples to display values in [0, 1].
+ *
+ * The average is exposed, tone mapped, clamped and then encoded with the transfer curve.
+ */
+fn convert_pixel(pixel: vec3::Color, sample_count: u32, tone: &ToneSettings) -> (f64, f64, f64) {
+    let scale = f64::powf(2.0, tone.exposure) / sample_count as f64;
+
+    // NaN samples would otherwise compare unpredictably, so treat them as black
+    let avg = |c: f64| {
+        if c.is_nan() {
+            0.0
+        } else {
+            f64::max(scale * c, 0.0)
+        }
+    };
+    let (r, g, b) = tone_map((avg(pixel.x()), avg(pixel.y()), avg(pixel.z())), tone);
+
+    let encode = |c: f64| {
+        let c = clamp(c, 0.0, 1.0);
+        match tone.transfer {
+            Transfer::Srgb => srgb(c),
+            Transfer::Gamma2 => f64::sqrt(c),
+            Transfer::Linear => c,
+        }
+    };
+
+    (encode(r), encode(g), encode(b))
 }
 
 #[allow(dead_code)]
-pub fn write_color_ppm(out: &mut impl io::Write, pixel: vec3::Color, sample_count: u32) {
-    let (r, g, b) = convert_pixel(pixel, sample_count);
+pub fn write_color_ppm(
+    out: &mut impl io::Write,
+    pixel: vec3::Color,
+    sample_count: u32,
+    tone: &ToneSettings,
+) {
+    let (r, g, b) = convert_pixel(pixel, sample_count, tone);
 
-    let max_color = std::u8::MAX as f64 + 1.0;
+    let max_color = u8::MAX as f64 + 1.0;
 
     let _ = writeln!(
         out,
@@ -33,11 +160,16 @@ pub fn write_color_ppm(out: &mut impl io::Write, pixel: vec3::Color, sample_coun
 }
 
 #[allow(dead_code)]
-pub fn write_pixel_str_ppm<T: fmt::Write>(out: &mut T, pixel: vec3::Color, sample_count: u32) {
+pub fn write_pixel_str_ppm<T: fmt::Write>(
+    out: &mut T,
+    pixel: vec3::Color,
+    sample_count: u32,
+    tone: &ToneSettings,
+) {
     //-> T{
-    let (r, g, b) = convert_pixel(pixel, sample_count);
+    let (r, g, b) = convert_pixel(pixel, sample_count, tone);
 
-    let max_color = std::u8::MAX as f64 + 1.0;
+    let max_color = u8::MAX as f64 + 1.0;
 
     let _ = writeln!(
         out,
@@ -53,11 +185,12 @@ pub fn write_pixel_img_8bpc(
     y: u32,
     pixel: vec3::Color,
     sample_count: u32,
+    tone: &ToneSettings,
     img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
 ) {
-    let (r, g, b) = convert_pixel(pixel, sample_count);
+    let (r, g, b) = convert_pixel(pixel, sample_count, tone);
 
-    let max_color = std::u8::MAX as f64 + 1.0;
+    let max_color = u8::MAX as f64 + 1.0;
 
     img.put_pixel(
         x,
@@ -70,16 +203,44 @@ pub fn write_pixel_img_8bpc(
     )
 }
 
+/// Writes the average radiance of the pixel, without gamma correction or tone mapping
+pub fn write_pixel_img_f32(
+    x: u32,
+    y: u32,
+    pixel: vec3::Color,
+    sample_count: u32,
+    img: &mut ImageBuffer<Rgb<f32>, Vec<f32>>,
+) {
+    let scale = 1.0 / sample_count as f64;
+
+    // A NaN sample would spread through any later processing, so store it as black. Spectral samples can average
+    // to just below 0 where a color is at the edge of the gamut, and negative radiance means nothing, so is clamped
+    let linear = |c: f64| {
+        if c.is_nan() {
+            0.0
+        } else {
+            f64::max(scale * c, 0.0) as f32
+        }
+    };
+
+    img.put_pixel(
+        x,
+        y,
+        Rgb([linear(pixel.x()), linear(pixel.y()), linear(pixel.z())]),
+    )
+}
+
 pub fn write_pixel_img_16bpc(
     x: u32,
     y: u32,
     pixel: vec3::Color,
     sample_count: u32,
+    tone: &ToneSettings,
     img: &mut ImageBuffer<Rgb<u16>, Vec<u16>>,
 ) {
-    let (r, g, b) = convert_pixel(pixel, sample_count);
+    let (r, g, b) = convert_pixel(pixel, sample_count, tone);
 
-    let max_color = std::u16::MAX as f64 + 1.0;
+    let max_color = u16::MAX as f64 + 1.0;
 
     img.put_pixel(
         x,
@@ -91,3 +252,38 @@ pub fn write_pixel_img_16bpc(
         ]),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(tonemap: ToneMap, white_point: Option<f64>) -> ToneSettings {
+        ToneSettings {
+            tonemap,
+            exposure: 0.0,
+            white_point,
+            transfer: Transfer::Linear,
+        }
+    }
+
+    #[test]
+    fn white_point_maps_to_white() {
+        for tonemap in [ToneMap::ExtendedReinhard, ToneMap::Uncharted2] {
+            for w in [None, Some(2.0), Some(20.0)] {
+                let tone = settings(tonemap, w);
+                let w = w.unwrap_or(match tonemap {
+                    ToneMap::ExtendedReinhard => 4.0,
+                    _ => 5.6,
+                });
+                let (r, g, b) = tone_map((w, w, w), &tone);
+                for c in [r, g, b] {
+                    assert!((c - 1.0).abs() < 1e-9, "{:?} maps {} to {}", tonemap, w, c);
+                }
+
+                // Below the white point stays below white
+                let (r, _, _) = tone_map((0.5 * w, 0.5 * w, 0.5 * w), &tone);
+                assert!(r < 1.0);
+            }
+        }
+    }
+}
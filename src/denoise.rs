@@ -0,0 +1,465 @@
+//! Edge avoiding à-trous wavelet denoiser, guided by the auxiliary buffers of the first hit
+
+use crate::{accumulator::Accumulator, aov::Aovs, color::luminance, vec3::*};
+use rayon::prelude::*;
+
+// B3 spline weights of the 5 by 5 filter, applied at ever wider spacing
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+// Spacings of 1, 2, 4, 8 and 16 pixels, covering a 61 pixel wide footprint
+const ITERATIONS: u32 = 5;
+
+// How sharply each guide stops the filter at an edge
+const SIGMA_LUMINANCE: f64 = 4.0;
+const SIGMA_NORMAL: i32 = 128;
+const SIGMA_DEPTH: f64 = 1.0;
+const DEPTH_TOLERANCE: f64 = 0.005;
+const EMITTED_TOLERANCE: f64 = 0.01;
+
+// Albedo channels darker than this are left as they are, rather than dividing noise up into fireflies
+const MIN_ALBEDO: f64 = 0.01;
+
+/**
+ * Denoises the average radiance of every pixel in acc, returning the filtered colors in the same order.
+ *
+ * The radiance is divided by the albedo so texture detail isn't blurred, filtered, and multiplied back. Each
+ * iteration blends a pixel with neighbours on the same surface, judged by their normal, depth and emitted light, and
+ * with a similar brightness, judged against the pixel's estimated noise. As the image is smoothed its noise estimate
+ * shrinks, so later, wider iterations only blend pixels that still agree.
+ */
+pub fn denoise(acc: &Accumulator, aovs: &Aovs) -> Vec<Color> {
+    let (width, height) = (aovs.width(), aovs.height());
+
+    let demod: Vec<Color> = (0..acc.len())
+        .map(|i| {
+            let a = aovs.albedo(i);
+            Color::new(
+                demod_channel(a.x()),
+                demod_channel(a.y()),
+                demod_channel(a.z()),
+            )
+        })
+        .collect();
+
+    let mut color: Vec<Color> = (0..acc.len())
+        .map(|i| {
+            let c = acc.average(i);
+            let d = demod[i];
+            Color::new(c.x() / d.x(), c.y() / d.y(), c.z() / d.z())
+        })
+        .collect();
+    let mut variance: Vec<f64> = (0..acc.len())
+        .map(|i| acc.variance(i) / luminance(demod[i]).powi(2))
+        .collect();
+
+    let gradient = depth_gradient(aovs);
+
+    for iteration in 0..ITERATIONS {
+        let step = 1i64 << iteration;
+        let blurred = blur_variance(&variance, width, height);
+
+        let filtered: Vec<(Color, f64)> = (0..acc.len())
+            .into_par_iter()
+            .map(|p| {
+                let (px, py) = ((p as u32 % width) as i64, (p as u32 / width) as i64);
+                let lum_p = luminance(color[p]);
+                let sigma_l = SIGMA_LUMINANCE * f64::sqrt(blurred[p]) + 1e-10;
+
+                let mut sum = Color::new_e();
+                let mut sum_var = 0.0;
+                let mut sum_w = 0.0;
+
+                for (ky, hy) in KERNEL.iter().enumerate() {
+                    for (kx, hx) in KERNEL.iter().enumerate() {
+                        let qx = px + (kx as i64 - 2) * step;
+                        let qy = py + (ky as i64 - 2) * step;
+                        if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+                            continue;
+                        }
+                        let q = (qy * width as i64 + qx) as usize;
+
+                        let w_geometry = if p == q {
+                            1.0
+                        } else {
+                            geometry_weight(aovs, &gradient, p, q, step)
+                        };
+                        // Before a pixel has two samples nothing is known of its noise, so only geometry guides it
+                        let w_luminance = if sigma_l.is_finite() {
+                            f64::exp(-(lum_p - luminance(color[q])).abs() / sigma_l)
+                        } else {
+                            1.0
+                        };
+
+                        let w = hx * hy * w_geometry * w_luminance;
+                        if w > 0.0 {
+                            sum += w * color[q];
+                            sum_var += w * w * variance[q];
+                            sum_w += w;
+                        }
+                    }
+                }
+
+                (sum / sum_w, sum_var / (sum_w * sum_w))
+            })
+            .collect();
+
+        for (i, (c, v)) in filtered.into_iter().enumerate() {
+            color[i] = c;
+            variance[i] = v;
+        }
+    }
+
+    color.iter().zip(&demod).map(|(&c, &d)| c * d).collect()
+}
+
+fn demod_channel(albedo: f64) -> f64 {
+    if albedo > MIN_ALBEDO {
+        albedo
+    } else {
+        1.0
+    }
+}
+
+/// How much pixels p and q look to be on the same surface, from 0 to 1
+fn geometry_weight(aovs: &Aovs, gradient: &[f64], p: usize, q: usize, step: i64) -> f64 {
+    match (aovs.hit(p), aovs.hit(q)) {
+        (false, false) => 1.0,
+        (true, true) => {
+            let w_normal = f64::max(0.0, dot(aovs.normal(p), aovs.normal(q))).powi(SIGMA_NORMAL);
+
+            // Depth is expected to change along a slanted surface, so the difference is judged against its slope. The
+            // slope is near zero where a surface faces the camera, so some difference relative to depth is always allowed
+            let expected =
+                SIGMA_DEPTH * gradient[p] * step as f64 + DEPTH_TOLERANCE * aovs.depth(p) + 1e-10;
+            let w_depth = f64::exp(-(aovs.depth(p) - aovs.depth(q)).abs() / expected);
+
+            // Lights are seen without noise, so are kept apart from the surfaces around them, and from pixels only
+            // partly covering them
+            let (e_p, e_q) = (luminance(aovs.emitted(p)), luminance(aovs.emitted(q)));
+            let w_emitted =
+                f64::exp(-(e_p - e_q).abs() / (EMITTED_TOLERANCE * f64::max(e_p, e_q) + 1e-10));
+
+            w_normal * w_depth * w_emitted
+        }
+        _ => 0.0,
+    }
+}
+
+/**
+ * Change in depth per pixel at each pixel.
+ *
+ * Each direction takes the smaller of the differences to the neighbours either side, so a pixel at the edge of an
+ * object doesn't take the jump to the background as its surface's slope.
+ */
+fn depth_gradient(aovs: &Aovs) -> Vec<f64> {
+    let (width, height) = (aovs.width() as i64, aovs.height() as i64);
+    let depth = |x: i64, y: i64| -> Option<f64> {
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return None;
+        }
+        let i = (y * width + x) as usize;
+        if aovs.hit(i) {
+            Some(aovs.depth(i))
+        } else {
+            None
+        }
+    };
+
+    (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let z = match depth(x, y) {
+                Some(z) => z,
+                None => return 0.0,
+            };
+            let slope = |a: Option<f64>, b: Option<f64>| -> f64 {
+                [a, b]
+                    .iter()
+                    .flatten()
+                    .map(|n| (n - z).abs())
+                    .fold(f64::INFINITY, f64::min)
+            };
+
+            let dx = slope(depth(x - 1, y), depth(x + 1, y));
+            let dy = slope(depth(x, y - 1), depth(x, y + 1));
+            [dx, dy]
+                .iter()
+                .copied()
+                .filter(|d| d.is_finite())
+                .fold(0.0, f64::max)
+        })
+        .collect()
+}
+
+/// 3 by 3 Gaussian blur of the variance, steadying the estimate that guides the luminance weights
+fn blur_variance(variance: &[f64], width: u32, height: u32) -> Vec<f64> {
+    const WEIGHTS: [f64; 3] = [0.25, 0.5, 0.25];
+    let (width, height) = (width as i64, height as i64);
+
+    (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let mut sum = 0.0;
+            let mut sum_w = 0.0;
+            for (dy, wy) in WEIGHTS.iter().enumerate() {
+                for (dx, wx) in WEIGHTS.iter().enumerate() {
+                    let (qx, qy) = (x + dx as i64 - 1, y + dy as i64 - 1);
+                    if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                        continue;
+                    }
+                    sum += wx * wy * variance[(qy * width + qx) as usize];
+                    sum_w += wx * wy;
+                }
+            }
+            sum / sum_w
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aarect::XYRect,
+        animation::CameraKey,
+        camera::{self, Camera, Projection},
+        hittable_list::HittableList,
+        lens::Lens,
+        materials::{Lambertian, Material},
+        render::ray_color,
+        scene::{self, SceneData},
+        texture::{CheckerTexture, Texture},
+        util::*,
+    };
+    use std::sync::Arc;
+
+    const SIZE: u32 = 32;
+
+    fn camera(scene_dat: &SceneData) -> Box<dyn Camera + Sync + Send> {
+        let pose = CameraKey {
+            frame: 0.0,
+            lookfrom: scene_dat.lookfrom,
+            lookat: scene_dat.lookat,
+            vup: scene_dat.vup,
+            vfov: scene_dat.vfov,
+            aperture: 0.0,
+            dist_to_focus: scene_dat.dist_to_focus,
+        };
+        camera::new_camera(
+            &Projection::perspective(),
+            &Lens::circle(),
+            &pose,
+            1.0,
+            0.0,
+            1.0,
+        )
+    }
+
+    /// Renders the Cornell box at spp samples per pixel, along with its AOVs
+    fn cornell_box(spp: u32, seed: u64) -> (Accumulator, Aovs) {
+        let mut scene_dat = SceneData::new();
+        let world = scene::match_scene(scene::Scene::CornellBox, &mut scene_dat);
+        let cam = camera(&scene_dat);
+
+        let mut acc = Accumulator::new(SIZE, SIZE);
+        for row in 0..SIZE {
+            for col in 0..SIZE {
+                let index = acc.index(col, row);
+                seed_rng(stream_seed(seed, index as u64));
+                let (mut sum, mut sum_sq) = (Color::new_e(), 0.0);
+                for _ in 0..spp {
+                    let u = (col as f64 + random_double()) / (SIZE - 1) as f64;
+                    let v = ((SIZE - row - 1) as f64 + random_double()) / (SIZE - 1) as f64;
+                    let c = ray_color(
+                        &cam.get_ray(u, v).unwrap(),
+                        &scene_dat.background,
+                        None,
+                        &world,
+                        &scene_dat.lights,
+                        &scene_dat.punctual_lights,
+                        8,
+                    );
+                    sum += c;
+                    sum_sq += luminance(c) * luminance(c);
+                }
+                acc.add(index, sum, sum_sq, spp);
+            }
+        }
+
+        let aovs = Aovs::render(
+            SIZE,
+            SIZE,
+            cam.as_ref(),
+            &world,
+            &scene_dat.background,
+            None,
+            seed,
+        );
+        (acc, aovs)
+    }
+
+    fn rmse(image: &[Color], reference: &[Color]) -> f64 {
+        let sum: f64 = image
+            .iter()
+            .zip(reference)
+            .map(|(&a, &b)| (a - b).length_squared())
+            .sum();
+        f64::sqrt(sum / (3 * image.len()) as f64)
+    }
+
+    #[test]
+    fn brings_a_low_sample_cornell_box_closer_to_the_reference() {
+        let (reference, aovs) = cornell_box(256, 1);
+        let reference: Vec<Color> = (0..reference.len()).map(|i| reference.average(i)).collect();
+
+        // Pixels at the edge of the light may see some of it in a few samples that the AOVs' rays missed, and are
+        // left as noisy as they were, so only pixels clear of the light are compared
+        let seen = |col: i64, row: i64| -> bool {
+            let inside = (0..SIZE as i64).contains(&col) && (0..SIZE as i64).contains(&row);
+            inside && luminance(aovs.emitted((row * SIZE as i64 + col) as usize)) > 0.0
+        };
+        let compared: Vec<usize> = (0..reference.len())
+            .filter(|&i| {
+                let (col, row) = ((i as u32 % SIZE) as i64, (i as u32 / SIZE) as i64);
+                (-1..=1).all(|dy| (-1..=1).all(|dx| !seen(col + dx, row + dy)))
+            })
+            .collect();
+        let pick = |image: &[Color]| -> Vec<Color> { compared.iter().map(|&i| image[i]).collect() };
+
+        // A firefly on an edge has no neighbours to blend with, so the error is pooled over several renders
+        let (mut raw, mut denoised, mut expected) = (Vec::new(), Vec::new(), Vec::new());
+        for seed in 2..6 {
+            let (acc, aovs) = cornell_box(4, seed);
+            let average: Vec<Color> = (0..acc.len()).map(|i| acc.average(i)).collect();
+            raw.extend(pick(&average));
+            denoised.extend(pick(&denoise(&acc, &aovs)));
+            expected.extend(pick(&reference));
+        }
+
+        let (raw_error, denoised_error) = (rmse(&raw, &expected), rmse(&denoised, &expected));
+        assert!(
+            denoised_error < 0.5 * raw_error,
+            "raw RMSE {}, denoised RMSE {}",
+            raw_error,
+            denoised_error
+        );
+    }
+
+    #[test]
+    fn keeps_lights_as_they_are() {
+        let (acc, aovs) = cornell_box(4, 2);
+        let denoised = denoise(&acc, &aovs);
+
+        // Pixels entirely on the light aren't blended with the much darker ceiling around them
+        let light = Color::new(15.0, 15.0, 15.0);
+        let on_light: Vec<usize> = (0..acc.len())
+            .filter(|&i| (aovs.emitted(i) - light).length() < 1e-9)
+            .collect();
+        assert!(!on_light.is_empty());
+        for i in on_light {
+            assert!(
+                (denoised[i] - light).length() < 0.1 * light.length(),
+                "light pixel {} is {}",
+                i,
+                denoised[i]
+            );
+        }
+    }
+
+    /**
+     * Aovs of a camera looking down -z at two rects facing it, the left half of the view at depth 10 and the top
+     * right quarter at depth 5, with the bottom right quarter empty.
+     */
+    fn steps(material: Arc<dyn Material + Sync + Send>) -> Aovs {
+        let mut world = HittableList {
+            objects: Vec::new(),
+        };
+        world.add(Arc::new(XYRect::new(
+            -100.0,
+            0.0,
+            -100.0,
+            100.0,
+            0.0,
+            Arc::clone(&material),
+        )));
+        world.add(Arc::new(XYRect::new(0.0, 100.0, 0.0, 100.0, 5.0, material)));
+
+        let mut scene_dat = SceneData::new();
+        scene_dat.lookfrom = Point::new(0.0, 0.0, 10.0);
+        scene_dat.lookat = Point::new(0.0, 0.0, 0.0);
+        let cam = camera(&scene_dat);
+
+        Aovs::render(SIZE, SIZE, cam.as_ref(), &world, &Color::new_e(), None, 1)
+    }
+
+    #[test]
+    fn geometry_weights_stop_at_edges() {
+        let white: Arc<dyn Material + Sync + Send> =
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let aovs = steps(white);
+        let gradient = depth_gradient(&aovs);
+        let index = |col: u32, row: u32| (row * SIZE + col) as usize;
+
+        let (far, near) = (index(4, 4), index(28, 4));
+        let (far_edge, near_edge) = (index(14, 4), index(16, 4));
+        let (empty, empty_next) = (index(28, 28), index(27, 28));
+        assert!(aovs.hit(far) && aovs.hit(near) && aovs.hit(near_edge) && !aovs.hit(empty));
+
+        // Depth changes along the rect away from the view's center, which is allowed for at every spacing
+        assert!(gradient[far] > 0.0);
+        for step in [1, 2, 4, 8] {
+            let w = geometry_weight(&aovs, &gradient, far, index(4 + step as u32, 4), step);
+            assert!(w > 0.3, "weight {} at step {}", w, step);
+        }
+        assert_eq!(geometry_weight(&aovs, &gradient, empty, empty_next, 1), 1.0);
+
+        // A step in depth or the edge of the scene stops the filter
+        assert!(geometry_weight(&aovs, &gradient, far_edge, near_edge, 2) < 1e-6);
+        assert!(geometry_weight(&aovs, &gradient, far, near, 16) < 0.01);
+        assert_eq!(geometry_weight(&aovs, &gradient, near, empty, 1), 0.0);
+        assert_eq!(geometry_weight(&aovs, &gradient, empty, near, 1), 0.0);
+    }
+
+    #[test]
+    fn kernel_preserves_brightness() {
+        let sum: f64 = KERNEL.iter().sum();
+        assert!((sum - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn albedo_texture_survives_demodulation() {
+        let checker: Arc<dyn Texture + Sync + Send> = Arc::new(CheckerTexture::new_clr(
+            Color::new(0.9, 0.2, 0.1),
+            Color::new(0.1, 0.3, 0.8),
+        ));
+        let aovs = steps(Arc::new(Lambertian::new_txtr(&checker)));
+
+        // Evenly lit, so radiance follows the albedo, but with a noise estimate large enough that brightness alone
+        // wouldn't stop the texture blurring
+        let mut acc = Accumulator::new(SIZE, SIZE);
+        for i in 0..acc.len() {
+            let c = 0.5 * aovs.albedo(i);
+            let l = luminance(c);
+            acc.add(i, 4.0 * c, 4.0 * (l * l + 1.0), 4);
+        }
+        assert!(acc.variance(0) > 0.1);
+
+        let denoised = denoise(&acc, &aovs);
+        for (i, d) in denoised.iter().enumerate() {
+            let expected = acc.average(i);
+            assert!(
+                (*d - expected).length() < 1e-9,
+                "pixel {} is {} not {}",
+                i,
+                d,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn dark_albedo_is_not_divided_out() {
+        assert_eq!(demod_channel(0.5), 0.5);
+        assert_eq!(demod_channel(MIN_ALBEDO / 2.0), 1.0);
+        assert_eq!(demod_channel(0.0), 1.0);
+    }
+}
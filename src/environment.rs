@@ -0,0 +1,364 @@
+//! Distant lighting surrounding the scene, seen by rays that escape it
+
+use crate::{color::luminance, util::*, vec3::*};
+use image::codecs::hdr::HdrDecoder;
+use std::fs::File;
+use std::io::BufReader;
+
+#[derive(Debug)]
+pub enum EnvironmentErr {
+    ImgError { err: image::ImageError },
+    InvalidArgs { err: String },
+}
+
+impl std::fmt::Display for EnvironmentErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnvironmentErr::ImgError { err } => write!(f, "{}", err),
+            EnvironmentErr::InvalidArgs { err } => write!(f, "{}", err),
+        }
+    }
+}
+
+/**
+ * Radiance arriving from infinitely far away, in place of the constant background.
+ *
+ * Like a Hittable used as a light, an environment can be sampled directly, so scattered rays are sent towards its
+ * bright parts.
+ */
+pub trait Environment {
+    /// Radiance arriving along direction, which needn't be a unit vector
+    fn value(&self, direction: &Vec3) -> Color;
+
+    /// Density of random generating direction, with respect to solid angle
+    fn pdf_value(&self, direction: &Vec3) -> f64;
+
+    fn random(&self) -> Vec3;
+}
+
+/**
+ * Equirectangular image of the surroundings, such as a Radiance HDR or OpenEXR panorama.
+ *
+ * The middle of the image is towards -z, with +x to its right and +y at the top, and rotation turns it
+ * counter-clockwise about +y when seen from above. Directions are sampled in proportion to the brightness of the
+ * texel they fall in, so small bright light sources such as the sun are found quickly.
+ */
+pub struct EnvironmentMap {
+    width: u32,
+    height: u32,
+    texels: image::Rgb32FImage,
+    sin_rotation: f64,
+    cos_rotation: f64,
+    intensity: f64,
+    // Running total of each row's share of the weight, from the top
+    marginal: Vec<f64>,
+    // Running total of each texel's share of its row's weight, row by row
+    conditional: Vec<f64>,
+}
+
+impl EnvironmentMap {
+    /**
+     * Loads an equirectangular image, turned rotation degrees about +y and scaled by intensity.
+     *
+     * Pixel values are used as linear radiance, so 8 bit images are taken as they are, without undoing their gamma.
+     */
+    pub fn load(
+        fname: &str,
+        rotation: f64,
+        intensity: f64,
+    ) -> Result<EnvironmentMap, EnvironmentErr> {
+        let texels = match load_texels(fname) {
+            Ok(t) => t,
+            Err(e) => return Err(EnvironmentErr::ImgError { err: e }),
+        };
+        if texels.width() == 0 || texels.height() == 0 {
+            return Err(EnvironmentErr::InvalidArgs {
+                err: format!("environment map '{}' is empty", fname),
+            });
+        }
+
+        Ok(EnvironmentMap::from_texels(texels, rotation, intensity))
+    }
+
+    /// Map of texels, which must not be empty, turned rotation degrees about +y and scaled by intensity
+    fn from_texels(texels: image::Rgb32FImage, rotation: f64, intensity: f64) -> EnvironmentMap {
+        let (width, height) = texels.dimensions();
+
+        // Rows towards the poles cover less of the sphere, so are weighted by the sine of their angle from +y
+        let row_sin = |row: u32| f64::sin(PI * (row as f64 + 0.5) / height as f64);
+        let texel_lum = |col: u32, row: u32| {
+            let p = texels.get_pixel(col, row).0;
+            f64::max(
+                0.0,
+                luminance(Color::new(p[0] as f64, p[1] as f64, p[2] as f64)),
+            )
+        };
+        let any_light = (0..height).any(|row| (0..width).any(|col| texel_lum(col, row) > 0.0));
+        // A black map is sampled uniformly, as its samples find nothing anyway
+        let weight = |col: u32, row: u32| {
+            let lum = if any_light { texel_lum(col, row) } else { 1.0 };
+            lum * row_sin(row)
+        };
+
+        let mut conditional = Vec::with_capacity((width * height) as usize);
+        let mut marginal = Vec::with_capacity(height as usize);
+        let mut total = 0.0;
+        for row in 0..height {
+            let start = conditional.len();
+            let mut row_total = 0.0;
+            for col in 0..width {
+                row_total += weight(col, row);
+                conditional.push(row_total);
+            }
+            for c in &mut conditional[start..] {
+                *c = if row_total > 0.0 {
+                    *c / row_total
+                } else {
+                    // A row with no weight is never picked, so any distribution across it will do
+                    1.0
+                };
+            }
+            total += row_total;
+            marginal.push(total);
+        }
+        for m in &mut marginal {
+            *m /= total;
+        }
+
+        let rads = degs_to_rads(rotation);
+        EnvironmentMap {
+            width,
+            height,
+            texels,
+            sin_rotation: f64::sin(rads),
+            cos_rotation: f64::cos(rads),
+            intensity,
+            marginal,
+            conditional,
+        }
+    }
+
+    /// Turns direction about +y by the map's rotation, or back again if inverse
+    fn rotate(&self, d: &Vec3, inverse: bool) -> Vec3 {
+        let sin = if inverse {
+            -self.sin_rotation
+        } else {
+            self.sin_rotation
+        };
+        Vec3::new(
+            self.cos_rotation * d.x() + sin * d.z(),
+            d.y(),
+            -sin * d.x() + self.cos_rotation * d.z(),
+        )
+    }
+
+    /// Image position of a world direction, from 0 to 1 across and down the image
+    fn uv(&self, direction: &Vec3) -> (f64, f64) {
+        let d = unit_vector(self.rotate(direction, true));
+        let u = 0.5 + f64::atan2(d.x(), -d.z()) / (2.0 * PI);
+        let v = f64::acos(clamp(d.y(), -1.0, 1.0)) / PI;
+        (u, v)
+    }
+
+    fn texel(&self, u: f64, v: f64) -> (u32, u32) {
+        (
+            u32::min((u * self.width as f64) as u32, self.width - 1),
+            u32::min((v * self.height as f64) as u32, self.height - 1),
+        )
+    }
+
+    /// Share of the total weight in the texel at col and row
+    fn probability(&self, col: u32, row: u32) -> f64 {
+        let share = |cdf: &[f64], i: usize| {
+            if i == 0 {
+                cdf[0]
+            } else {
+                cdf[i] - cdf[i - 1]
+            }
+        };
+        let start = (row * self.width) as usize;
+        let row_cdf = &self.conditional[start..start + self.width as usize];
+        share(&self.marginal, row as usize) * share(row_cdf, col as usize)
+    }
+}
+
+/**
+ * Reads an image as linear floats.
+ *
+ * Radiance HDR files are decoded directly, as image::open converts them to 8 bits, clamping everything brighter
+ * than 1.
+ */
+fn load_texels(fname: &str) -> image::ImageResult<image::Rgb32FImage> {
+    if image::ImageFormat::from_path(fname).ok() != Some(image::ImageFormat::Hdr) {
+        return image::open(fname).map(|i| i.to_rgb32f());
+    }
+
+    let file = File::open(fname).map_err(image::ImageError::IoError)?;
+    let decoder = HdrDecoder::new(BufReader::new(file))?;
+    let meta = decoder.metadata();
+    let pixels = decoder.read_image_hdr()?;
+    let raw: Vec<f32> = pixels.iter().flat_map(|p| p.0).collect();
+
+    image::Rgb32FImage::from_raw(meta.width, meta.height, raw).ok_or_else(|| {
+        image::ImageError::Limits(image::error::LimitError::from_kind(
+            image::error::LimitErrorKind::DimensionError,
+        ))
+    })
+}
+
+impl Environment for EnvironmentMap {
+    fn value(&self, direction: &Vec3) -> Color {
+        let (u, v) = self.uv(direction);
+        let (col, row) = self.texel(u, v);
+        let p = self.texels.get_pixel(col, row).0;
+        self.intensity * Color::new(p[0] as f64, p[1] as f64, p[2] as f64)
+    }
+
+    fn pdf_value(&self, direction: &Vec3) -> f64 {
+        let (u, v) = self.uv(direction);
+        let sin_theta = f64::sin(PI * v);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        // Density is constant across each texel in image space, which stretches over 2 pi by pi radians
+        let (col, row) = self.texel(u, v);
+        let image_pdf = self.probability(col, row) * (self.width * self.height) as f64;
+        image_pdf / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn random(&self) -> Vec3 {
+        let r = random_double();
+        let row = usize::min(
+            self.marginal.partition_point(|&m| m <= r),
+            self.height as usize - 1,
+        );
+        let start = row * self.width as usize;
+        let row_cdf = &self.conditional[start..start + self.width as usize];
+        let r = random_double();
+        let col = usize::min(
+            row_cdf.partition_point(|&c| c <= r),
+            self.width as usize - 1,
+        );
+
+        let u = (col as f64 + random_double()) / self.width as f64;
+        let v = (row as f64 + random_double()) / self.height as f64;
+        let (phi, theta) = ((u - 0.5) * 2.0 * PI, v * PI);
+        let d = Vec3::new(
+            f64::sin(theta) * f64::sin(phi),
+            f64::cos(theta),
+            -f64::sin(theta) * f64::cos(phi),
+        );
+        self.rotate(&d, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dim map with a bright patch up and to the right of the middle, so sampling is far from uniform
+    fn patchy(rotation: f64) -> EnvironmentMap {
+        let texels = image::Rgb32FImage::from_fn(16, 8, |col, row| {
+            if (9..12).contains(&col) && (2..4).contains(&row) {
+                image::Rgb([50.0, 40.0, 30.0])
+            } else {
+                image::Rgb([0.2, 0.3, 0.5])
+            }
+        });
+        EnvironmentMap::from_texels(texels, rotation, 1.0)
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        for rotation in [0.0, 70.0] {
+            let map = patchy(rotation);
+            // Directions jittered over a grid of even y and azimuth, which have equal solid angle
+            const N: u32 = 400;
+            seed_rng(1);
+            let mut sum = 0.0;
+            for i in 0..N {
+                for j in 0..N {
+                    let y = 2.0 * (i as f64 + random_double()) / N as f64 - 1.0;
+                    let phi = 2.0 * PI * (j as f64 + random_double()) / N as f64;
+                    let r = f64::sqrt(f64::max(0.0, 1.0 - y * y));
+                    sum += map.pdf_value(&Vec3::new(r * phi.cos(), y, r * phi.sin()));
+                }
+            }
+            let integral = 4.0 * PI * sum / (N * N) as f64;
+            assert!((integral - 1.0).abs() < 0.01, "integral {}", integral);
+        }
+    }
+
+    #[test]
+    fn pdf_matches_sampled_directions() {
+        const N: usize = 200_000;
+        let map = patchy(30.0);
+        seed_rng(2);
+
+        let mut histogram = vec![0.0; (map.width * map.height) as usize];
+        let mut inverse_pdf = 0.0;
+        for _ in 0..N {
+            let d = map.random();
+            assert!((d.length() - 1.0).abs() < 1e-9);
+            let (u, v) = map.uv(&d);
+            let (col, row) = map.texel(u, v);
+            histogram[(row * map.width + col) as usize] += 1.0 / N as f64;
+            inverse_pdf += 1.0 / map.pdf_value(&d);
+        }
+
+        // The texels directions land in are drawn as often as their probability says
+        for row in 0..map.height {
+            for col in 0..map.width {
+                let (sampled, p) = (
+                    histogram[(row * map.width + col) as usize],
+                    map.probability(col, row),
+                );
+                assert!(
+                    (sampled - p).abs() < 0.001 + 0.05 * p,
+                    "texel {}, {} sampled {} but has probability {}",
+                    col,
+                    row,
+                    sampled,
+                    p
+                );
+            }
+        }
+
+        // Weighting each sample by its inverse density measures the whole sphere
+        let area = inverse_pdf / N as f64;
+        assert!((area - 4.0 * PI).abs() < 0.02 * 4.0 * PI, "area {}", area);
+    }
+
+    #[test]
+    fn rotation_round_trips() {
+        let map = patchy(70.0);
+        let plain = patchy(0.0);
+        seed_rng(3);
+        for _ in 0..1000 {
+            let d = random_unit_vector();
+            let back = map.rotate(&map.rotate(&d, false), true);
+            assert!((back - d).length() < 1e-12);
+
+            // The rotated map shows what the plain one does, turned
+            let turned = map.rotate(&d, false);
+            assert_eq!(map.value(&turned), plain.value(&d));
+            assert!(
+                (map.pdf_value(&turned) - plain.pdf_value(&d)).abs() < 1e-9 * plain.pdf_value(&d)
+            );
+        }
+
+        // The middle of the image faces -z, with the bright column just to its right towards +x. A quarter turn
+        // counter-clockwise seen from above takes -z to -x, and +x to -z
+        let quarter = EnvironmentMap::from_texels(
+            image::Rgb32FImage::from_fn(4, 2, |col, _| {
+                image::Rgb([if col == 2 { 1.0 } else { 0.0 }; 3])
+            }),
+            90.0,
+            1.0,
+        );
+        let toward = |x: f64, z: f64| quarter.value(&Vec3::new(x, 0.1, z)).x();
+        assert_eq!(toward(-1.0, -0.2), 1.0);
+        assert_eq!(toward(0.2, -1.0), 0.0);
+    }
+}
@@ -0,0 +1,258 @@
+//! Bounding volume hierarchy stored as a flat array of nodes, for cheaper traversal than the BvhNode tree
+
+use crate::{aabb::AABB, bvh::*, hittable::*, hittable_list::HittableList, ray::Ray, vec3::*};
+use std::sync::Arc;
+
+const MAX_LEAF: usize = 4;
+// Traversal keeps a fixed stack, so deeper subtrees are kept as a single, larger leaf
+const MAX_DEPTH: usize = 60;
+
+/**
+ * A node of a FlatBvh.
+ *
+ * Nodes are stored depth first, so an interior node's first child always follows it directly, and offset is the
+ * index of its second child. For a leaf, offset is the index of its first primitive and count is greater than zero.
+ */
+struct FlatNode {
+    bbox: AABB,
+    offset: u32,
+    count: u32,
+    axis: u8,
+}
+
+/**
+ * Linearized bounding volume hierarchy, built with the surface area heuristic.
+ *
+ * All nodes live in one contiguous Vec and leaves refer to ranges of a primitive Vec, so traversal is a loop over
+ * indices rather than a chain of virtual calls. The child nearer the ray origin along the node's split axis is
+ * visited first, letting closer hits cull the farther child.
+ */
+pub struct FlatBvh {
+    nodes: Vec<FlatNode>,
+    prims: Vec<Arc<dyn Hittable + Sync + Send>>,
+    // Objects without a bounding box, tested against every ray
+    unbounded: Vec<Arc<dyn Hittable + Sync + Send>>,
+}
+
+impl FlatBvh {
+    pub fn new(list: &HittableList, time0: f64, time1: f64) -> FlatBvh {
+        let mut bvh = FlatBvh {
+            nodes: Vec::new(),
+            prims: Vec::with_capacity(list.objects.len()),
+            unbounded: Vec::new(),
+        };
+
+        let mut bounded = Vec::with_capacity(list.objects.len());
+        for o in &list.objects {
+            if o.bounding_box(time0, time1).0 {
+                bounded.push(Arc::clone(o));
+            } else {
+                bvh.unbounded.push(Arc::clone(o));
+            }
+        }
+
+        let mut prims = build_prims(&bounded, time0, time1);
+        if !prims.is_empty() {
+            bvh.nodes.reserve(2 * prims.len());
+            bvh.build(&mut prims, 0);
+        }
+
+        bvh
+    }
+
+    /// Appends the subtree over prims in depth first order, returning the index of its root
+    fn build(&mut self, prims: &mut [BuildPrim], depth: usize) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(FlatNode {
+            bbox: prims_bbox(prims),
+            offset: 0,
+            count: 0,
+            axis: 0,
+        });
+
+        let split = if depth >= MAX_DEPTH {
+            None
+        } else {
+            sah_partition(prims, MAX_LEAF)
+        };
+
+        match split {
+            None => {
+                self.nodes[index].offset = self.prims.len() as u32;
+                self.nodes[index].count = prims.len() as u32;
+                self.prims.extend(prims.iter().map(|p| Arc::clone(&p.obj)));
+            }
+            Some((mid, axis)) => {
+                let (l, r) = prims.split_at_mut(mid);
+                self.build(l, depth + 1);
+                let second = self.build(r, depth + 1);
+                self.nodes[index].offset = second as u32;
+                self.nodes[index].axis = axis as u8;
+            }
+        }
+
+        index
+    }
+}
+
+/// Slab test against bbox, using the ray's precomputed inverse direction
+fn hit_box(bbox: &AABB, origin: &Point, inv_dir: &Vec3, t_min: f64, t_max: f64) -> bool {
+    let mut t_min = t_min;
+    let mut t_max = t_max;
+    let min = bbox.min();
+    let max = bbox.max();
+
+    for a in 0..3 {
+        let t0 = (min[a] - origin[a]) * inv_dir[a];
+        let t1 = (max[a] - origin[a]) * inv_dir[a];
+        let (t0, t1) = if inv_dir[a] < 0.0 { (t1, t0) } else { (t0, t1) };
+
+        t_min = f64::max(t0, t_min);
+        t_max = f64::min(t1, t_max);
+
+        if t_max <= t_min {
+            return false;
+        }
+    }
+
+    true
+}
+
+impl Hittable for FlatBvh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let mut hit_anything = false;
+        let mut closest_so_far = t_max;
+
+        for object in &self.unbounded {
+            if object.hit(r, t_min, closest_so_far, rec) {
+                hit_anything = true;
+                closest_so_far = rec.t;
+            }
+        }
+
+        if self.nodes.is_empty() {
+            return hit_anything;
+        }
+
+        let origin = r.origin();
+        let dir = r.direction();
+        let inv_dir = Vec3::new(1.0 / dir.x(), 1.0 / dir.y(), 1.0 / dir.z());
+        let dir_neg = [dir.x() < 0.0, dir.y() < 0.0, dir.z() < 0.0];
+
+        let mut stack = [0u32; MAX_DEPTH + 1];
+        let mut sp = 0;
+        let mut index = 0;
+
+        loop {
+            let node = &self.nodes[index];
+
+            if hit_box(&node.bbox, &origin, &inv_dir, t_min, closest_so_far) {
+                if node.count > 0 {
+                    let start = node.offset as usize;
+                    for object in &self.prims[start..start + node.count as usize] {
+                        if object.hit(r, t_min, closest_so_far, rec) {
+                            hit_anything = true;
+                            closest_so_far = rec.t;
+                        }
+                    }
+                } else {
+                    // Visit the nearer child first, deferring the other
+                    if dir_neg[node.axis as usize] {
+                        stack[sp] = (index + 1) as u32;
+                        index = node.offset as usize;
+                    } else {
+                        stack[sp] = node.offset;
+                        index += 1;
+                    }
+                    sp += 1;
+                    continue;
+                }
+            }
+
+            if sp == 0 {
+                break;
+            }
+            sp -= 1;
+            index = stack[sp] as usize;
+        }
+
+        hit_anything
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> (bool, AABB) {
+        if !self.unbounded.is_empty() || self.nodes.is_empty() {
+            return (false, AABB::new_e());
+        }
+
+        (true, self.nodes[0].bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        boxes, materials::Lambertian, moving_sphere::MovingSphere, sphere::Sphere,
+        triangle::Triangle, util::*,
+    };
+
+    /// Seeded jumble of overlapping spheres, moving spheres, triangles and boxes, each with its own material
+    fn jumble() -> HittableList {
+        seed_rng(7);
+        let mut list = HittableList {
+            objects: Vec::new(),
+        };
+        let spot = || random_range(-10.0, 10.0);
+        for i in 0..400 {
+            let mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+            let (c, r) = (spot(), random_double_range(0.2, 1.5));
+            let obj: Arc<dyn Hittable + Sync + Send> = match i % 4 {
+                0 => Arc::new(Sphere::new(c, r, mat)),
+                1 => Arc::new(MovingSphere::new(c, c + random(), 0.0, 1.0, r, mat)),
+                2 => Arc::new(Triangle::new(
+                    c,
+                    c + random_range(-2.0, 2.0),
+                    c + random_range(-2.0, 2.0),
+                    mat,
+                )),
+                _ => Arc::new(boxes::Box::new(&c, &(c + random_range(0.1, 2.0)), mat)),
+            };
+            list.add(obj);
+        }
+        list
+    }
+
+    #[test]
+    fn finds_the_same_closest_hit_as_testing_everything() {
+        let list = jumble();
+        let bvh = FlatBvh::new(&list, 0.0, 1.0);
+
+        seed_rng(8);
+        let mut hits = 0;
+        for i in 0..5000 {
+            let r = Ray::new(
+                &random_range(-15.0, 15.0),
+                &random_unit_vector(),
+                random_double(),
+            );
+            // Some rays stop short, so the nearest hit within the range is what counts
+            let t_max = if i % 3 == 0 { 5.0 } else { INFINITY };
+            let (mut expected, mut found) = (HitRecord::new(), HitRecord::new());
+            let hit = list.hit(&r, 0.001, t_max, &mut expected);
+            assert_eq!(bvh.hit(&r, 0.001, t_max, &mut found), hit, "ray {}", i);
+            if hit {
+                hits += 1;
+                assert_eq!(found.t, expected.t, "ray {}", i);
+                assert_eq!(
+                    Arc::as_ptr(&found.mat_ptr) as *const (),
+                    Arc::as_ptr(&expected.mat_ptr) as *const (),
+                    "ray {} hit another object",
+                    i
+                );
+                assert_eq!(found.normal, expected.normal);
+            }
+        }
+        // Enough of the rays find something for the comparison to mean something
+        assert!(hits > 1000, "{} hits", hits);
+    }
+}
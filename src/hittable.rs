@@ -1,5 +1,7 @@
 use crate::aabb::*;
+use crate::mat4::Mat4;
 use crate::materials::*;
+use crate::quat::Quat;
 use crate::ray::Ray;
 use crate::util::*;
 use crate::vec3::*;
@@ -52,64 +54,40 @@ impl HitRecord {
 pub trait Hittable {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
     fn bounding_box(&self, t0: f64, t1: f64) -> (bool, AABB);
-}
-
-pub struct Translate {
-    ptr: Arc<dyn Hittable + Sync + Send>,
-    offset: Vec3,
-}
 
-impl Translate {
-    pub fn new(p: Arc<dyn Hittable + Sync + Send>, displacement: &Vec3) -> Translate {
-        Translate {
-            ptr: p,
-            offset: *displacement,
-        }
+    /// Density, with respect to solid angle, of random(o) generating direction v. Only needed for Hittables used as lights.
+    fn pdf_value(&self, _o: &Point, _v: &Vec3) -> f64 {
+        0.0
     }
-}
-
-impl Hittable for Translate {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let moved_r = Ray::new(&(r.origin() - self.offset), &r.direction(), r.time());
-        if !self.ptr.hit(&moved_r, t_min, t_max, rec) {
-            return false;
-        }
-
-        rec.p += self.offset;
-        let n = rec.normal;
-        rec.set_face_normal(&moved_r, &n);
 
-        true
-    }
-
-    fn bounding_box(&self, t0: f64, t1: f64) -> (bool, AABB) {
-        let (b, outbox) = self.ptr.bounding_box(t0, t1);
-        if !b {
-            return (false, AABB::new_e());
-        }
-
-        let outbox = AABB::new(&(outbox.min() + self.offset), &(outbox.max() + self.offset));
-
-        (true, outbox)
+    /// Random direction from o towards this Hittable. Only needed for Hittables used as lights.
+    fn random(&self, _o: &Point) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
     }
 }
 
-pub struct RotateX {
+/**
+ * Places a Hittable in the scene through an affine transform, such as any combination of translation, rotation about
+ * any axis, non-uniform scaling and shearing.
+ *
+ * Rays are carried into the wrapped Hittable's space rather than the Hittable being copied, so a mesh or BVH can be
+ * placed many times by wrapping the same Arc in several Transforms.
+ */
+pub struct Transform {
     ptr: Arc<dyn Hittable + Sync + Send>,
-    sin_theta: f64,
-    cos_theta: f64,
+    to_world: Mat4,
+    to_object: Mat4,
+    // Inverse transpose, which keeps normals perpendicular to surfaces that are scaled or sheared
+    normal_to_world: Mat4,
     hasbox: bool,
     bbox: AABB,
 }
 
-impl RotateX {
-    pub fn new(p: Arc<dyn Hittable + Sync + Send>, angle: f64) -> RotateX {
-        let ptr = p;
-
-        let rads = degs_to_rads(angle);
-        let sin = f64::sin(rads);
-        let cos = f64::cos(rads);
-        let (hasbox, bbox) = ptr.bounding_box(0.0, 1.0);
+impl Transform {
+    /// Transforms p by m, which maps points in p's space to world space. Returns None if m is singular.
+    pub fn new(p: Arc<dyn Hittable + Sync + Send>, m: Mat4) -> Option<Transform> {
+        let to_object = m.inverse()?;
+        let (hasbox, bbox) = p.bounding_box(0.0, 1.0);
 
         let mut min = Point::new(INFINITY, INFINITY, INFINITY);
         let mut max = Point::new(-INFINITY, -INFINITY, -INFINITY);
@@ -121,10 +99,7 @@ impl RotateX {
                     let y = j as f64 * bbox.max().y() + (1 - j) as f64 * bbox.min().y();
                     let z = k as f64 * bbox.max().z() + (1 - k) as f64 * bbox.min().z();
 
-                    let newy = cos * y + sin * z;
-                    let newz = -sin * y + cos * z;
-
-                    let tester = Point::new(x, newy, newz);
+                    let tester = m.point(&Point::new(x, y, z));
 
                     for c in 0..3 {
                         min[c] = f64::min(min[c], tester[c]);
@@ -134,46 +109,55 @@ impl RotateX {
             }
         }
 
-        let bbox = AABB::new(&min, &max);
-
-        RotateX {
-            ptr,
-            sin_theta: sin,
-            cos_theta: cos,
+        Some(Transform {
+            ptr: p,
+            to_world: m,
+            to_object,
+            normal_to_world: to_object.transpose(),
             hasbox,
-            bbox,
-        }
+            bbox: AABB::new(&min, &max),
+        })
     }
 }
 
-impl Hittable for RotateX {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let mut origin = r.origin();
-        let mut dir = r.direction();
-
-        origin[1] = self.cos_theta * r.origin().y() - self.sin_theta * r.origin().z();
-        origin[2] = self.sin_theta * r.origin().y() + self.cos_theta * r.origin().z();
-
-        dir[1] = self.cos_theta * r.direction().y() - self.sin_theta * r.direction().z();
-        dir[2] = self.sin_theta * r.direction().y() + self.cos_theta * r.direction().z();
+/**
+ * Density, with respect to solid angle, of obj's random directions from o towards v, where to_object maps world space
+ * into obj's space.
+ *
+ * Scaling and shearing stretch solid angle unevenly, so obj's density is corrected by the Jacobian of the mapping
+ * between world and object directions.
+ */
+fn transformed_pdf(
+    obj: &(dyn Hittable + Sync + Send),
+    to_object: &Mat4,
+    o: &Point,
+    v: &Vec3,
+) -> f64 {
+    let pdf = obj.pdf_value(&to_object.point(o), &to_object.vector(v));
+    if pdf == 0.0 {
+        return 0.0;
+    }
 
-        let rotated_r = Ray::new(&origin, &dir, r.time());
+    let stretched = to_object.vector(&unit_vector(*v)).length();
+    pdf * to_object.determinant3().abs() / (stretched * stretched * stretched)
+}
 
-        if !self.ptr.hit(&rotated_r, t_min, t_max, rec) {
+impl Hittable for Transform {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        // The direction is left unnormalized, so t is the same in both spaces
+        let object_r = Ray::new(
+            &self.to_object.point(&r.origin()),
+            &self.to_object.vector(&r.direction()),
+            r.time(),
+        );
+
+        if !self.ptr.hit(&object_r, t_min, t_max, rec) {
             return false;
         }
 
-        let mut p = rec.p;
-        let mut n = rec.normal;
-
-        p[1] = self.cos_theta * rec.p.y() + self.sin_theta * rec.p.z();
-        p[2] = -self.sin_theta * rec.p.y() + self.cos_theta * rec.p.z();
-
-        n[1] = self.cos_theta * rec.normal.y() + self.sin_theta * rec.normal.z();
-        n[2] = -self.sin_theta * rec.normal.y() + self.cos_theta * rec.normal.z();
-
-        rec.p = p;
-        rec.set_face_normal(&rotated_r, &n);
+        // The normal already faces against the ray, which the transform preserves, so front_face still holds
+        rec.p = self.to_world.point(&rec.p);
+        rec.normal = unit_vector(self.normal_to_world.vector(&rec.normal));
 
         true
     }
@@ -181,182 +165,317 @@ impl Hittable for RotateX {
     fn bounding_box(&self, _t0: f64, _t1: f64) -> (bool, AABB) {
         (self.hasbox, self.bbox)
     }
-}
-
-pub struct RotateY {
-    ptr: Arc<dyn Hittable + Sync + Send>,
-    sin_theta: f64,
-    cos_theta: f64,
-    hasbox: bool,
-    bbox: AABB,
-}
 
-impl RotateY {
-    pub fn new(p: Arc<dyn Hittable + Sync + Send>, angle: f64) -> RotateY {
-        let ptr = p;
+    fn pdf_value(&self, o: &Point, v: &Vec3) -> f64 {
+        transformed_pdf(self.ptr.as_ref(), &self.to_object, o, v)
+    }
 
-        let rads = degs_to_rads(angle);
-        let sin = f64::sin(rads);
-        let cos = f64::cos(rads);
-        let (hasbox, bbox) = ptr.bounding_box(0.0, 1.0);
+    fn random(&self, o: &Point) -> Vec3 {
+        self.to_world
+            .vector(&self.ptr.random(&self.to_object.point(o)))
+    }
+}
 
-        let mut min = Point::new(INFINITY, INFINITY, INFINITY);
-        let mut max = Point::new(-INFINITY, -INFINITY, -INFINITY);
+// Steps each stretch between keyframes is sampled at when bounding an AnimatedTransform
+const MOTION_BOUND_STEPS: usize = 16;
 
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let x = i as f64 * bbox.max().x() + (1 - i) as f64 * bbox.min().x();
-                    let y = j as f64 * bbox.max().y() + (1 - j) as f64 * bbox.min().y();
-                    let z = k as f64 * bbox.max().z() + (1 - k) as f64 * bbox.min().z();
-
-                    let newx = cos * x + sin * z;
-                    let newz = -sin * x + cos * z;
+/// Pose of an AnimatedTransform at one moment, applied as scale, then rotation, then translation
+#[derive(Debug, Copy, Clone)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
 
-                    let tester = Point::new(newx, y, newz);
+/**
+ * Moves a Hittable through a series of keyframes, each ray seeing it posed at the ray's time.
+ *
+ * Between keyframes, translation and scale are interpolated linearly and rotation by quaternion slerp, so turns are
+ * taken at a constant rate along the shorter way round. Before the first keyframe and after the last the Hittable holds
+ * still.
+ */
+pub struct AnimatedTransform {
+    ptr: Arc<dyn Hittable + Sync + Send>,
+    keyframes: Vec<Keyframe>,
+}
 
-                    for c in 0..3 {
-                        min[c] = f64::min(min[c], tester[c]);
-                        max[c] = f64::max(max[c], tester[c]);
-                    }
-                }
-            }
+impl AnimatedTransform {
+    /**
+     * Animates p through keyframes, in any order. Returns None if there are none, any scale is zero, or a scale
+     * component changes sign from one keyframe to the next, as it would pass through zero on the way.
+     */
+    pub fn new(
+        p: Arc<dyn Hittable + Sync + Send>,
+        mut keyframes: Vec<Keyframe>,
+    ) -> Option<AnimatedTransform> {
+        if keyframes.is_empty()
+            || keyframes
+                .iter()
+                .any(|k| k.scale.x() * k.scale.y() * k.scale.z() == 0.0)
+        {
+            return None;
+        }
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        if keyframes
+            .windows(2)
+            .any(|k| (0..3).any(|c| k[0].scale[c] * k[1].scale[c] < 0.0))
+        {
+            return None;
         }
 
-        let bbox = AABB::new(&min, &max);
+        Some(AnimatedTransform { ptr: p, keyframes })
+    }
 
-        RotateY {
-            ptr,
-            sin_theta: sin,
-            cos_theta: cos,
-            hasbox,
-            bbox,
+    fn pose(&self, time: f64) -> (Vec3, Quat, Vec3) {
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        if next == 0 || next == self.keyframes.len() {
+            let k = &self.keyframes[next.saturating_sub(1)];
+            return (k.translation, k.rotation, k.scale);
         }
-    }
-}
 
-impl Hittable for RotateY {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let mut origin = r.origin();
-        let mut dir = r.direction();
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let f = (time - a.time) / (b.time - a.time);
+        (
+            a.translation + f * (b.translation - a.translation),
+            a.rotation.slerp(&b.rotation, f),
+            a.scale + f * (b.scale - a.scale),
+        )
+    }
 
-        origin[0] = self.cos_theta * r.origin().x() - self.sin_theta * r.origin().z();
-        origin[2] = self.sin_theta * r.origin().x() + self.cos_theta * r.origin().z();
+    /// Matrices to world space, to object space, and for normals to world space, at time
+    fn matrices(&self, time: f64) -> (Mat4, Mat4, Mat4) {
+        let (translation, rotation, scale) = self.pose(time);
+        let rotate = rotation.to_mat4();
+        let inv_scale = Vec3::new(1.0 / scale.x(), 1.0 / scale.y(), 1.0 / scale.z());
 
-        dir[0] = self.cos_theta * r.direction().x() - self.sin_theta * r.direction().z();
-        dir[2] = self.sin_theta * r.direction().x() + self.cos_theta * r.direction().z();
+        let to_world = Mat4::translate(&translation) * rotate * Mat4::scale(&scale);
+        let to_object =
+            Mat4::scale(&inv_scale) * rotate.transpose() * Mat4::translate(&-translation);
 
-        let rotated_r = Ray::new(&origin, &dir, r.time());
+        (to_world, to_object, to_object.transpose())
+    }
+}
 
-        if !self.ptr.hit(&rotated_r, t_min, t_max, rec) {
+impl Hittable for AnimatedTransform {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let (to_world, to_object, normal_to_world) = self.matrices(r.time());
+        let object_r = Ray::new(
+            &to_object.point(&r.origin()),
+            &to_object.vector(&r.direction()),
+            r.time(),
+        );
+
+        if !self.ptr.hit(&object_r, t_min, t_max, rec) {
             return false;
         }
 
-        let mut p = rec.p;
-        let mut n = rec.normal;
-
-        p[0] = self.cos_theta * rec.p.x() + self.sin_theta * rec.p.z();
-        p[2] = -self.sin_theta * rec.p.x() + self.cos_theta * rec.p.z();
-
-        n[0] = self.cos_theta * rec.normal.x() + self.sin_theta * rec.normal.z();
-        n[2] = -self.sin_theta * rec.normal.x() + self.cos_theta * rec.normal.z();
-
-        rec.p = p;
-        rec.set_face_normal(&rotated_r, &n);
+        rec.p = to_world.point(&rec.p);
+        rec.normal = unit_vector(normal_to_world.vector(&rec.normal));
 
         true
     }
 
-    fn bounding_box(&self, _t0: f64, _t1: f64) -> (bool, AABB) {
-        (self.hasbox, self.bbox)
-    }
-}
-
-pub struct RotateZ {
-    ptr: Arc<dyn Hittable + Sync + Send>,
-    sin_theta: f64,
-    cos_theta: f64,
-    hasbox: bool,
-    bbox: AABB,
-}
-
-impl RotateZ {
-    pub fn new(p: Arc<dyn Hittable + Sync + Send>, angle: f64) -> RotateZ {
-        let ptr = p;
+    /**
+     * Box around every pose between t0 and t1.
+     *
+     * Poses are sampled at each keyframe and at steps between them. Rotating corners sweep arcs that bow out past
+     * the straight lines between samples, so the box is padded by the most any arc could bow.
+     */
+    fn bounding_box(&self, t0: f64, t1: f64) -> (bool, AABB) {
+        let (b, bbox) = self.ptr.bounding_box(t0, t1);
+        if !b {
+            return (false, AABB::new_e());
+        }
 
-        let rads = degs_to_rads(angle);
-        let sin = f64::sin(rads);
-        let cos = f64::cos(rads);
-        let (hasbox, bbox) = ptr.bounding_box(0.0, 1.0);
+        let mut times = vec![t0];
+        times.extend(
+            self.keyframes
+                .iter()
+                .map(|k| k.time)
+                .filter(|&t| t > t0 && t < t1),
+        );
+        times.push(t1);
+
+        let corners: Vec<Point> = (0..8)
+            .map(|i| {
+                Point::new(
+                    if i & 1 == 0 {
+                        bbox.min().x()
+                    } else {
+                        bbox.max().x()
+                    },
+                    if i & 2 == 0 {
+                        bbox.min().y()
+                    } else {
+                        bbox.max().y()
+                    },
+                    if i & 4 == 0 {
+                        bbox.min().z()
+                    } else {
+                        bbox.max().z()
+                    },
+                )
+            })
+            .collect();
+        let max_scale = self
+            .keyframes
+            .iter()
+            .map(|k| {
+                f64::max(
+                    k.scale.x().abs(),
+                    f64::max(k.scale.y().abs(), k.scale.z().abs()),
+                )
+            })
+            .fold(0.0, f64::max);
+        let radius = max_scale * corners.iter().map(|c| c.length()).fold(0.0, f64::max);
 
         let mut min = Point::new(INFINITY, INFINITY, INFINITY);
         let mut max = Point::new(-INFINITY, -INFINITY, -INFINITY);
-
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let x = i as f64 * bbox.max().x() + (1 - i) as f64 * bbox.min().x();
-                    let y = j as f64 * bbox.max().y() + (1 - j) as f64 * bbox.min().y();
-                    let z = k as f64 * bbox.max().z() + (1 - k) as f64 * bbox.min().z();
-
-                    let newx = cos * x + sin * y;
-                    let newy = -sin * x + cos * y;
-
-                    let tester = Point::new(newx, newy, z);
-
-                    for c in 0..3 {
-                        min[c] = f64::min(min[c], tester[c]);
-                        max[c] = f64::max(max[c], tester[c]);
+        let mut pad: f64 = 0.0;
+
+        for span in times.windows(2) {
+            let mut prev = self.pose(span[0]).1;
+            for step in 0..=MOTION_BOUND_STEPS {
+                let time = span[0] + (span[1] - span[0]) * step as f64 / MOTION_BOUND_STEPS as f64;
+                let (to_world, _, _) = self.matrices(time);
+                for c in &corners {
+                    let p = to_world.point(c);
+                    for a in 0..3 {
+                        min[a] = f64::min(min[a], p[a]);
+                        max[a] = f64::max(max[a], p[a]);
                     }
                 }
+
+                let rotation = self.pose(time).1;
+                pad = f64::max(
+                    pad,
+                    radius * (1.0 - f64::cos(0.5 * prev.angle_to(&rotation))),
+                );
+                prev = rotation;
             }
         }
 
-        let bbox = AABB::new(&min, &max);
-
-        RotateZ {
-            ptr,
-            sin_theta: sin,
-            cos_theta: cos,
-            hasbox,
-            bbox,
-        }
+        let pad = Vec3::new(pad, pad, pad);
+        (true, AABB::new(&(min - pad), &(max + pad)))
     }
-}
 
-impl Hittable for RotateZ {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let mut origin = r.origin();
-        let mut dir = r.direction();
+    // Sampling directions has no time to go by, so as a light the Hittable is sampled where it is at time 0, when
+    // the camera's shutter opens
+    fn pdf_value(&self, o: &Point, v: &Vec3) -> f64 {
+        let (_, to_object, _) = self.matrices(0.0);
+        transformed_pdf(self.ptr.as_ref(), &to_object, o, v)
+    }
 
-        origin[0] = self.cos_theta * r.origin().x() - self.sin_theta * r.origin().y();
-        origin[1] = self.sin_theta * r.origin().x() + self.cos_theta * r.origin().y();
+    fn random(&self, o: &Point) -> Vec3 {
+        let (to_world, to_object, _) = self.matrices(0.0);
+        to_world.vector(&self.ptr.random(&to_object.point(o)))
+    }
+}
 
-        dir[0] = self.cos_theta * r.direction().x() - self.sin_theta * r.direction().y();
-        dir[1] = self.sin_theta * r.direction().x() + self.cos_theta * r.direction().y();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
 
-        let rotated_r = Ray::new(&origin, &dir, r.time());
+    fn unit_sphere() -> Arc<dyn Hittable + Sync + Send> {
+        let white = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        Arc::new(Sphere::new(Point::new(0.0, 0.0, 0.0), 1.0, white))
+    }
 
-        if !self.ptr.hit(&rotated_r, t_min, t_max, rec) {
-            return false;
+    #[test]
+    fn uniform_scale_matches_a_sphere_of_that_size() {
+        let center = Point::new(0.0, 1.0, 6.0);
+        let scaled = Transform::new(
+            unit_sphere(),
+            Mat4::translate(&center) * Mat4::scale(&Vec3::new(2.0, 2.0, 2.0)),
+        )
+        .unwrap();
+        let white = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let direct = Sphere::new(center, 2.0, white);
+
+        let o = Point::new(0.3, 0.0, 0.0);
+        for v in [
+            center - o,
+            center - o + Vec3::new(0.5, -0.7, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ] {
+            let (a, b) = (scaled.pdf_value(&o, &v), direct.pdf_value(&o, &v));
+            assert!(a > 0.0 && (a - b).abs() < 1e-9 * b, "{} is not {}", a, b);
         }
+        assert_eq!(scaled.pdf_value(&o, &Vec3::new(0.0, 0.0, -1.0)), 0.0);
+    }
 
-        let mut p = rec.p;
-        let mut n = rec.normal;
-
-        p[0] = self.cos_theta * rec.p.x() + self.sin_theta * rec.p.y();
-        p[1] = -self.sin_theta * rec.p.x() + self.cos_theta * rec.p.y();
+    /// Estimates the integral of pdf over every direction, which is 1 for a correct density
+    fn integrate(pdf: impl Fn(&Vec3) -> f64) -> f64 {
+        const N: u32 = 200_000;
+        seed_rng(1);
+        let sum: f64 = (0..N).map(|_| pdf(&random_unit_vector())).sum();
+        4.0 * PI * sum / N as f64
+    }
 
-        n[0] = self.cos_theta * rec.normal.x() + self.sin_theta * rec.normal.y();
-        n[1] = -self.sin_theta * rec.normal.x() + self.cos_theta * rec.normal.y();
+    #[test]
+    fn stretched_pdf_integrates_to_one() {
+        let o = Point::new(0.0, 0.0, 0.0);
+        let m = Mat4::translate(&Vec3::new(1.0, 0.5, 4.0))
+            * Mat4::rotate(&Vec3::new(1.0, 1.0, 0.0), 30.0)
+            * Mat4::scale(&Vec3::new(2.5, 1.0, 0.5))
+            * Mat4::shear(0.5, 0.0, 0.0, 0.0, 0.0, 0.0);
+        let ellipsoid = Transform::new(unit_sphere(), m).unwrap();
+        let integral = integrate(|v| ellipsoid.pdf_value(&o, v));
+        assert!((integral - 1.0).abs() < 0.02, "integral {}", integral);
+
+        // Without the Jacobian, the sphere's density in its own space is well off
+        let (sphere, to_object) = (unit_sphere(), m.inverse().unwrap());
+        let uncorrected =
+            integrate(|v| sphere.pdf_value(&to_object.point(&o), &to_object.vector(v)));
+        assert!(
+            (uncorrected - 1.0).abs() > 0.1,
+            "uncorrected {}",
+            uncorrected
+        );
+    }
 
-        rec.p = p;
-        rec.set_face_normal(&rotated_r, &n);
+    fn scaled(time: f64, scale: Vec3) -> Keyframe {
+        Keyframe {
+            time,
+            translation: Vec3::new_e(),
+            rotation: Quat::identity(),
+            scale,
+        }
+    }
 
-        true
+    #[test]
+    fn animated_scale_keeps_its_sign() {
+        let (plain, mirrored) = (Vec3::new(1.0, 1.0, 1.0), Vec3::new(-1.0, 1.0, 1.0));
+        let animated = |keys: Vec<Keyframe>| AnimatedTransform::new(unit_sphere(), keys);
+
+        assert!(animated(vec![scaled(0.0, plain), scaled(1.0, 2.0 * plain)]).is_some());
+        assert!(animated(vec![scaled(0.0, mirrored), scaled(1.0, 3.0 * mirrored)]).is_some());
+
+        // Flipping would pass through a zero scale between the keyframes, in whatever order they are given
+        assert!(animated(vec![scaled(0.0, plain), scaled(1.0, mirrored)]).is_none());
+        assert!(animated(vec![scaled(1.0, plain), scaled(0.0, mirrored)]).is_none());
+        assert!(animated(vec![
+            scaled(0.0, plain),
+            scaled(1.0, Vec3::new(1.0, 0.0, 1.0))
+        ])
+        .is_none());
+        assert!(animated(Vec::new()).is_none());
     }
 
-    fn bounding_box(&self, _t0: f64, _t1: f64) -> (bool, AABB) {
-        (self.hasbox, self.bbox)
+    #[test]
+    fn animated_pdf_integrates_to_one() {
+        let key = |time: f64, x: f64| Keyframe {
+            time,
+            translation: Vec3::new(x, 0.0, 5.0),
+            rotation: Quat::from_axis_angle(&Vec3::new(0.0, 0.0, 1.0), 40.0),
+            scale: Vec3::new(3.0, 1.0, 1.5),
+        };
+        let animated =
+            AnimatedTransform::new(unit_sphere(), vec![key(0.0, 0.0), key(1.0, 4.0)]).unwrap();
+        let o = Point::new(0.0, 0.0, 0.0);
+        let integral = integrate(|v| animated.pdf_value(&o, v));
+        assert!((integral - 1.0).abs() < 0.02, "integral {}", integral);
     }
 }
@@ -1,4 +1,4 @@
-use crate::{aabb::*, hittable::*, ray::Ray};
+use crate::{aabb::*, hittable::*, ray::Ray, util::*, vec3::*};
 
 use std::sync::Arc;
 
@@ -66,4 +66,19 @@ impl Hittable for HittableList {
 
         (true, out_box)
     }
+
+    /// Averages the pdf of every object, matching random picking an object uniformly
+    fn pdf_value(&self, o: &Point, v: &Vec3) -> f64 {
+        let weight = 1.0 / self.objects.len() as f64;
+
+        self.objects
+            .iter()
+            .map(|object| weight * object.pdf_value(o, v))
+            .sum()
+    }
+
+    fn random(&self, o: &Point) -> Vec3 {
+        let idx = random_int_range(0, self.objects.len() as i32 - 1) as usize;
+        self.objects[idx].random(o)
+    }
 }
@@ -0,0 +1,374 @@
+//! Lens apertures shaping the bokeh of the perspective camera, and the photographic exposure model
+
+use crate::{util::*, vec3::*};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum LensErr {
+    ImgError { err: image::ImageError },
+    InvalidArgs { err: String },
+}
+
+impl std::fmt::Display for LensErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LensErr::ImgError { err } => write!(f, "{}", err),
+            LensErr::InvalidArgs { err } => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Outline of the aperture, which out of focus highlights take the shape of
+#[derive(Clone)]
+pub enum ApertureShape {
+    Circle,
+    /// Regular polygon with a corner on each blade, inscribed in the aperture's circle
+    Polygon {
+        blades: u32,
+    },
+    Image(Arc<ApertureMask>),
+}
+
+/**
+ * Aperture drawn as a grey image, where brighter pixels let through more light.
+ *
+ * The longer side of the image spans the aperture's diameter, with the top of the image towards the camera's vup.
+ */
+pub struct ApertureMask {
+    width: u32,
+    height: u32,
+    // Running total of the pixels' brightness, in row order from the top left
+    cdf: Vec<f64>,
+}
+
+impl ApertureMask {
+    pub fn load(fname: &str) -> Result<ApertureMask, LensErr> {
+        let img = match image::open(fname) {
+            Ok(i) => i.to_luma32f(),
+            Err(e) => return Err(LensErr::ImgError { err: e }),
+        };
+
+        let mut total = 0.0;
+        let cdf: Vec<f64> = img
+            .pixels()
+            .map(|p| {
+                total += f64::max(0.0, p.0[0] as f64);
+                total
+            })
+            .collect();
+        if total <= 0.0 {
+            return Err(LensErr::InvalidArgs {
+                err: format!("aperture image '{}' is completely black", fname),
+            });
+        }
+
+        Ok(ApertureMask {
+            width: img.width(),
+            height: img.height(),
+            cdf,
+        })
+    }
+
+    /// Random point on the aperture, more likely where the image is brighter
+    fn sample(&self) -> Vec3 {
+        let total = self.cdf[self.cdf.len() - 1];
+        let target = random_double() * total;
+        let index = usize::min(
+            self.cdf.partition_point(|&c| c <= target),
+            self.cdf.len() - 1,
+        );
+
+        let (col, row) = (index as u32 % self.width, index as u32 / self.width);
+        let size = u32::max(self.width, self.height) as f64;
+        Vec3::new(
+            (2.0 * (col as f64 + random_double()) - self.width as f64) / size,
+            (self.height as f64 - 2.0 * (row as f64 + random_double())) / size,
+            0.0,
+        )
+    }
+}
+
+/// Shape of the perspective camera's aperture, and how the lens barrel clips it away from the middle of the image
+#[derive(Clone)]
+pub struct Lens {
+    pub shape: ApertureShape,
+    /// Counter-clockwise turn of the shape in degrees
+    pub rotation: f64,
+    /**
+     * How far the lens barrel's circle is offset from the aperture at the corners of the image, in aperture radii.
+     * Clipping the aperture to the barrel gives out of focus highlights a cat's eye shape towards the edges, and
+     * darkens them. 0 turns this off.
+     */
+    pub cat_eye: f64,
+}
+
+impl Lens {
+    pub fn circle() -> Lens {
+        Lens {
+            shape: ApertureShape::Circle,
+            rotation: 0.0,
+            cat_eye: 0.0,
+        }
+    }
+
+    /**
+     * Random point on an aperture of radius 1, in the plane z = 0, for the ray through image position (x, y).
+     *
+     * x and y run from -1 to 1 across the image's diagonal, so are 0 in the middle. Returns None if the lens barrel
+     * blocks the point.
+     */
+    pub fn sample(&self, x: f64, y: f64) -> Option<Vec3> {
+        let p = match &self.shape {
+            ApertureShape::Circle => random_in_unit_disk(),
+            ApertureShape::Polygon { blades } => random_in_polygon(*blades),
+            ApertureShape::Image(mask) => mask.sample(),
+        };
+
+        let rads = degs_to_rads(self.rotation);
+        let (sin, cos) = (f64::sin(rads), f64::cos(rads));
+        let p = Vec3::new(cos * p.x() - sin * p.y(), sin * p.x() + cos * p.y(), 0.0);
+
+        let barrel = Vec3::new(self.cat_eye * x, self.cat_eye * y, 0.0);
+        if self.cat_eye > 0.0 && (p - barrel).length_squared() > 1.0 {
+            return None;
+        }
+        Some(p)
+    }
+}
+
+/// Uniformly random point in a regular polygon of the given number of sides, with its corners on the unit circle
+fn random_in_polygon(sides: u32) -> Vec3 {
+    // Each side makes an equal triangle with the center, so picking one of them at random keeps the density uniform
+    let side = random_int_range(0, sides as i32 - 1) as f64;
+    let step = 2.0 * PI / sides as f64;
+    let a = Vec3::new(f64::cos(side * step), f64::sin(side * step), 0.0);
+    let b = Vec3::new(
+        f64::cos((side + 1.0) * step),
+        f64::sin((side + 1.0) * step),
+        0.0,
+    );
+
+    let (mut s, mut t) = (random_double(), random_double());
+    if s + t > 1.0 {
+        s = 1.0 - s;
+        t = 1.0 - t;
+    }
+    s * a + t * b
+}
+
+/**
+ * Camera settings of a photograph, tying depth of field, motion blur and brightness together.
+ *
+ * The aperture is the focal length over the f-stop, where the focal length is what gives the vertical field of
+ * view on a sensor_height tall sensor. The shutter is open from time 0 for shutter seconds, with each unit of scene
+ * time a second. Brightness follows the exposure value of the f-stop, shutter and ISO, taking radiance to be in
+ * nits (candela per square meter), and is applied to tone mapped images.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct Photographic {
+    pub f_stop: f64,
+    /// Seconds the shutter is open, at most 1, the length of a scene's motion
+    pub shutter: f64,
+    pub iso: f64,
+    /// Height of the sensor in millimeters
+    pub sensor_height: f64,
+    /// Length of one scene unit in meters
+    pub scene_scale: f64,
+}
+
+impl Photographic {
+    /// f/8 at ISO 100 on a full frame sensor, with the shutter open for the whole of a scene's motion
+    pub fn new() -> Photographic {
+        Photographic {
+            f_stop: 8.0,
+            shutter: 1.0,
+            iso: 100.0,
+            sensor_height: 24.0,
+            scene_scale: 1.0,
+        }
+    }
+
+    /// Diameter of the aperture in scene units, for a lens with vertical field of view vfov in degrees
+    pub fn aperture(&self, vfov: f64) -> f64 {
+        let sensor = 0.001 * self.sensor_height / self.scene_scale;
+        let focal_length = 0.5 * sensor / f64::tan(degs_to_rads(vfov) / 2.0);
+        focal_length / self.f_stop
+    }
+
+    /// Exposure value at ISO 100 of the settings, which is 0 for f/1 at 1 second and ISO 100
+    pub fn exposure_value(&self) -> f64 {
+        f64::log2(self.f_stop * self.f_stop / self.shutter) - f64::log2(self.iso / 100.0)
+    }
+
+    /**
+     * Change in brightness in stops from the settings, scaling radiance so the brightest that doesn't saturate the
+     * sensor is 1.
+     */
+    pub fn exposure_stops(&self) -> f64 {
+        // Saturation based sensitivity, where the sensor saturates at 1.2 times the luminance of the exposure value
+        -(self.exposure_value() + f64::log2(1.2))
+    }
+}
+
+/// Parses a time in seconds, given as a number such as 0.5, or as a fraction such as 1/60
+pub fn parse_seconds(s: &str) -> Result<f64, String> {
+    let number = |n: &str| -> Result<f64, String> {
+        n.trim()
+            .parse::<f64>()
+            .map_err(|_| format!("'{}' is not a valid number of seconds", s))
+    };
+
+    let t = match s.split_once('/') {
+        Some((a, b)) => number(a)? / number(b)?,
+        None => number(s)?,
+    };
+    if !(t > 0.0 && t <= 1.0) {
+        return Err(String::from(
+            "The time must be above 0 and at most 1 second",
+        ));
+    }
+    Ok(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path in the temporary directory, unique to this process and test
+    fn temp_path(name: &str) -> String {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("rust_raytracer_{}_{}", std::process::id(), name));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn polygon_samples_stay_inside_and_spread_evenly() {
+        seed_rng(1);
+        for sides in [3, 5, 6] {
+            let step = 2.0 * PI / sides as f64;
+            // Distance from the middle to each side
+            let apothem = f64::cos(step / 2.0);
+            let mut per_side = vec![0; sides as usize];
+            const N: usize = 60_000;
+            for _ in 0..N {
+                let p = random_in_polygon(sides);
+                assert_eq!(p.z(), 0.0);
+                let mut angle = f64::atan2(p.y(), p.x());
+                if angle < 0.0 {
+                    angle += 2.0 * PI;
+                }
+                let side = usize::min((angle / step) as usize, sides as usize - 1);
+                let mid = (side as f64 + 0.5) * step;
+                let along = p.x() * f64::cos(mid) + p.y() * f64::sin(mid);
+                assert!(
+                    along <= apothem + 1e-12,
+                    "{:?} is outside {} sides",
+                    p,
+                    sides
+                );
+                per_side[side] += 1;
+            }
+            for count in per_side {
+                let share = count as f64 / N as f64;
+                assert!(
+                    (share - 1.0 / sides as f64).abs() < 0.01,
+                    "{} of {} sides",
+                    share,
+                    sides
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn parses_seconds_as_numbers_and_fractions() {
+        assert_eq!(parse_seconds("1/125"), Ok(1.0 / 125.0));
+        assert_eq!(parse_seconds(" 1 / 60 "), Ok(1.0 / 60.0));
+        assert_eq!(parse_seconds("0.5"), Ok(0.5));
+        assert_eq!(parse_seconds("1"), Ok(1.0));
+        for longer in ["2", "3/2"] {
+            assert_eq!(
+                parse_seconds(longer),
+                Err("The time must be above 0 and at most 1 second".to_string())
+            );
+        }
+        for bad in ["0", "-1/60", "1/0"] {
+            assert!(parse_seconds(bad).is_err(), "{} parsed", bad);
+        }
+        assert_eq!(
+            parse_seconds("1/sixty"),
+            Err("'1/sixty' is not a valid number of seconds".to_string())
+        );
+        assert!(parse_seconds("").is_err());
+    }
+
+    #[test]
+    fn exposure_follows_the_settings() {
+        let mut p = Photographic::new();
+        p.f_stop = 1.0;
+        assert!(p.exposure_value().abs() < 1e-12);
+
+        // Sunny 16: f/16 at 1/125 is about EV 15, and each doubling of ISO lowers it a stop
+        p.f_stop = 16.0;
+        p.shutter = 1.0 / 125.0;
+        assert!((p.exposure_value() - 14.97).abs() < 0.01);
+        let ev = p.exposure_value();
+        p.iso = 400.0;
+        assert!((p.exposure_value() - (ev - 2.0)).abs() < 1e-12);
+        assert!((p.exposure_stops() + p.exposure_value() + f64::log2(1.2)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn aperture_is_the_focal_length_over_the_f_stop() {
+        // A 50mm lens on a full frame sensor, 24mm tall
+        let vfov = rads_to_degs(2.0 * f64::atan(12.0 / 50.0));
+        let mut p = Photographic::new();
+        p.f_stop = 2.0;
+        assert!((p.aperture(vfov) - 0.025).abs() < 1e-12);
+
+        // Measured in centimeters, the same lens is a hundred times wider
+        p.scene_scale = 0.01;
+        assert!((p.aperture(vfov) - 2.5).abs() < 1e-10);
+    }
+
+    #[test]
+    fn mask_samples_follow_its_brightness() {
+        // Four by two, with the top right pixel twice as bright as the bottom left and the rest black
+        let fname = temp_path("mask.png");
+        let mut img = image::GrayImage::new(4, 2);
+        img.put_pixel(3, 0, image::Luma([255]));
+        img.put_pixel(0, 1, image::Luma([128]));
+        img.save(&fname).unwrap();
+        let mask = ApertureMask::load(&fname).unwrap();
+        std::fs::remove_file(&fname).unwrap();
+
+        // The width spans -1 to 1, so each pixel is half a unit square
+        seed_rng(2);
+        const N: usize = 30_000;
+        let mut top_right = 0;
+        for _ in 0..N {
+            let p = mask.sample();
+            if p.x() >= 0.5 && p.y() >= 0.0 {
+                assert!(p.x() <= 1.0 && p.y() <= 0.5, "{:?}", p);
+                top_right += 1;
+            } else {
+                assert!(
+                    p.x() >= -1.0 && p.x() <= -0.5 && p.y() >= -0.5 && p.y() <= 0.0,
+                    "{:?}",
+                    p
+                );
+            }
+        }
+        let share = top_right as f64 / N as f64;
+        assert!((share - 255.0 / 383.0).abs() < 0.01, "{}", share);
+    }
+
+    #[test]
+    fn black_mask_is_rejected() {
+        let fname = temp_path("black_mask.png");
+        image::GrayImage::new(3, 3).save(&fname).unwrap();
+        let result = ApertureMask::load(&fname);
+        std::fs::remove_file(&fname).unwrap();
+        assert!(matches!(result, Err(LensErr::InvalidArgs { .. })));
+    }
+}
@@ -0,0 +1,59 @@
+//! Path tracer after Peter Shirley's Ray Tracing in One Weekend series, as a library shared by the rust_raytracer
+//! binary and its benchmarks
+
+#![warn(clippy::all)]
+#![allow(
+    dead_code,
+    clippy::too_many_arguments,
+    clippy::upper_case_acronyms,
+    clippy::suspicious_operation_groupings,
+    clippy::many_single_char_names,
+    clippy::enum_variant_names,
+    clippy::new_without_default,
+    clippy::len_without_is_empty
+)]
+
+#[macro_use]
+extern crate clap;
+
+use util::*;
+use vec3::*;
+
+pub mod aabb;
+pub mod aarect;
+pub mod accumulator;
+pub mod animation;
+pub mod aov;
+pub mod boxes;
+pub mod bvh;
+pub mod camera;
+pub mod color;
+pub mod constant_medium;
+pub mod denoise;
+pub mod environment;
+pub mod flat_bvh;
+pub mod hittable;
+pub mod hittable_list;
+pub mod lens;
+pub mod light;
+pub mod mat4;
+pub mod materials;
+pub mod mesh;
+pub mod microfacet;
+pub mod moving_sphere;
+pub mod onb;
+pub mod pdf;
+pub mod perlin;
+pub mod picture;
+pub mod quat;
+pub mod ray;
+pub mod render;
+pub mod scene;
+pub mod scene_file;
+pub mod sky;
+pub mod spectrum;
+pub mod sphere;
+pub mod texture;
+pub mod triangle;
+pub mod util;
+pub mod vec3;
@@ -0,0 +1,226 @@
+//! Point, spot and directional lights, which have no surface to hit, so are reached only by shadow rays
+
+use crate::{util::*, vec3::*};
+
+/// Light arriving at a point from one light
+pub struct LightSample {
+    /// Unit vector from the point towards the light
+    pub direction: Vec3,
+    /// Distance to the light, infinite for a directional light
+    pub distance: f64,
+    /// Light falling on a surface facing the light, before any shadowing
+    pub irradiance: Color,
+}
+
+/**
+ * Light source concentrated at a point or in a single direction.
+ *
+ * Rays can never hit these lights, so surfaces find them by sending a shadow ray towards each one. Every material
+ * that scatters light over a spread of directions is lit by them, diffuse, rough metal and rough glass alike, as are
+ * media. Mirrors and smooth glass reflect or refract them in just one direction, so are never lit by them.
+ */
+pub trait Light {
+    /// Light reaching point p, or None if p is outside the light's reach
+    fn sample(&self, p: &Point) -> Option<LightSample>;
+}
+
+/// Light shining equally in all directions from position, fading with the square of distance
+pub struct PointLight {
+    position: Point,
+    intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point, intensity: Color) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: &Point) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_squared();
+        if distance_squared <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction: unit_vector(to_light),
+            distance: distance_squared.sqrt(),
+            irradiance: self.intensity / distance_squared,
+        })
+    }
+}
+
+/**
+ * Point light shining only within a cone about direction.
+ *
+ * angle is the cone's half angle in degrees. Over the outer falloff degrees of the cone, the light fades smoothly to
+ * nothing at its edge, giving a soft edged pool of light.
+ */
+pub struct SpotLight {
+    position: Point,
+    direction: Vec3,
+    intensity: Color,
+    cos_angle: f64,
+    cos_full: f64,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Point,
+        direction: Vec3,
+        intensity: Color,
+        angle: f64,
+        falloff: f64,
+    ) -> SpotLight {
+        SpotLight {
+            position,
+            direction: unit_vector(direction),
+            intensity,
+            cos_angle: f64::cos(degs_to_rads(angle)),
+            cos_full: f64::cos(degs_to_rads(f64::max(0.0, angle - falloff))),
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: &Point) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_squared();
+        if distance_squared <= 0.0 {
+            return None;
+        }
+
+        let direction = unit_vector(to_light);
+        let cos = dot(-direction, self.direction);
+        if cos <= self.cos_angle {
+            return None;
+        }
+        let strength = if cos >= self.cos_full {
+            1.0
+        } else {
+            let t = (cos - self.cos_angle) / (self.cos_full - self.cos_angle);
+            t * t * (3.0 - 2.0 * t)
+        };
+
+        Some(LightSample {
+            direction,
+            distance: distance_squared.sqrt(),
+            irradiance: strength * self.intensity / distance_squared,
+        })
+    }
+}
+
+/// Parallel light travelling along direction from infinitely far away, like sunlight, the same everywhere
+pub struct DirectionalLight {
+    direction: Vec3,
+    irradiance: Color,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> DirectionalLight {
+        DirectionalLight {
+            direction: unit_vector(direction),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: &Point) -> Option<LightSample> {
+        Some(LightSample {
+            direction: -self.direction,
+            distance: INFINITY,
+            irradiance: self.irradiance,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn irradiance(light: &dyn Light, p: Point) -> f64 {
+        light.sample(&p).map_or(0.0, |s| s.irradiance.x())
+    }
+
+    #[test]
+    fn point_light_fades_with_the_square_of_distance() {
+        let light = PointLight::new(Point::new(1.0, 2.0, 3.0), Color::new(8.0, 8.0, 8.0));
+        for d in [0.5, 1.0, 2.0, 4.0] {
+            let p = Point::new(1.0, 2.0, 3.0) + d * unit_vector(Vec3::new(1.0, -2.0, 0.5));
+            let s = light.sample(&p).unwrap();
+            assert!((s.irradiance.x() - 8.0 / (d * d)).abs() < 1e-9);
+            assert!((s.distance - d).abs() < 1e-9);
+            assert!((p + s.distance * s.direction - Point::new(1.0, 2.0, 3.0)).length() < 1e-9);
+        }
+        assert!(light.sample(&Point::new(1.0, 2.0, 3.0)).is_none());
+    }
+
+    /// Point at distance from a spot light at the origin pointing down -y, off its axis by angle degrees
+    fn off_axis(angle: f64, distance: f64) -> Point {
+        let a = degs_to_rads(angle);
+        distance * Point::new(f64::sin(a), -f64::cos(a), 0.0)
+    }
+
+    #[test]
+    fn spot_light_fades_with_the_square_of_distance() {
+        let light = SpotLight::new(
+            Point::new_e(),
+            Vec3::new(0.0, -1.0, 0.0),
+            Color::new(8.0, 8.0, 8.0),
+            30.0,
+            10.0,
+        );
+        for d in [0.5, 1.0, 2.0, 4.0] {
+            assert!((irradiance(&light, off_axis(5.0, d)) - 8.0 / (d * d)).abs() < 1e-9);
+            let edge = irradiance(&light, off_axis(25.0, d)) * d * d;
+            assert!((edge - irradiance(&light, off_axis(25.0, 1.0))).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn spot_light_falls_off_smoothly_to_its_edge() {
+        let (angle, falloff) = (30.0, 10.0);
+        let light = SpotLight::new(
+            Point::new_e(),
+            Vec3::new(0.0, -1.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            angle,
+            falloff,
+        );
+        let strength = |a: f64| irradiance(&light, off_axis(a, 1.0));
+
+        // Full strength inside the inner cone, nothing outside the outer one
+        assert_eq!(strength(0.0), 1.0);
+        assert!((strength(angle - falloff - 0.01) - 1.0).abs() < 1e-12);
+        assert_eq!(strength(angle + 0.01), 0.0);
+        assert_eq!(strength(120.0), 0.0);
+
+        // Smoothstep in the cosine between the cones, so halfway in cosine is half strength
+        let (cos_outer, cos_inner) = (
+            f64::cos(degs_to_rads(angle)),
+            f64::cos(degs_to_rads(angle - falloff)),
+        );
+        let halfway = rads_to_degs(f64::acos(0.5 * (cos_outer + cos_inner)));
+        assert!((strength(halfway) - 0.5).abs() < 1e-9);
+        for t in [0.1, 0.25, 0.75, 0.9] {
+            let a = rads_to_degs(f64::acos(cos_outer + t * (cos_inner - cos_outer)));
+            assert!((strength(a) - t * t * (3.0 - 2.0 * t)).abs() < 1e-9);
+        }
+
+        // Fading steadily, and flattening out at both ends of the band
+        let mut last = 1.0;
+        for i in 0..=100 {
+            let s = strength(angle - falloff + falloff * i as f64 / 100.0);
+            assert!(s <= last + 1e-12);
+            last = s;
+        }
+        assert!(1.0 - strength(angle - falloff + 0.1) < 1e-3);
+        assert!(strength(angle - 0.1) < 1e-3);
+    }
+}
@@ -4,83 +4,25 @@
     clippy::too_many_arguments,
     clippy::upper_case_acronyms,
     clippy::suspicious_operation_groupings,
-    clippy::many_single_char_names
+    clippy::many_single_char_names,
+    clippy::enum_variant_names
 )]
 
-use rayon::prelude::*;
 use std::f64;
-use std::io::{stderr, Write};
-
-mod aabb;
-mod aarect;
-mod boxes;
-mod bvh;
-mod camera;
-mod color;
-mod constant_medium;
-mod hittable;
-mod hittable_list;
-mod materials;
-mod moving_sphere;
-mod perlin;
-mod picture;
-mod ray;
-mod scene;
-mod sphere;
-mod texture;
-mod util;
-mod vec3;
+use std::time::Duration;
+
+use rust_raytracer::*;
 
 use picture::Picture;
 use util::*;
-use vec3::*;
 
 #[macro_use]
 extern crate clap;
 use clap::{App, Arg};
 
-fn ray_color(
-    r: &ray::Ray,
-    background: &Color,
-    world: &dyn hittable::Hittable,
-    depth: u32,
-) -> Color {
-    let mut rec = hittable::HitRecord::new();
-
-    // Just return no light if past ray bounce limit
-    if depth == 0 {
-        return Color::new(0.0, 0.0, 0.0);
-    }
-
-    // If the ray hits nothing, return the background
-    if !world.hit(r, 0.0001, INFINITY, &mut rec) {
-        return *background;
-    }
-
-    let (success, scattered, attenuation) = rec.mat_ptr.as_ref().scatter(r, &rec);
-    let emitted = rec.mat_ptr.as_ref().emitted(rec.u, rec.v, &rec.p);
-
-    if !success {
-        return emitted;
-    }
-
-    emitted + attenuation * ray_color(&scattered, background, world, depth - 1)
-}
-
 fn main() {
     //Picture and Camera defaults - may be overridden by scene or by user
-    let mut scene_dat = scene::SceneData {
-        image_width: 1920,
-        background: Color::new(0.0, 0.0, 0.0),
-        lookfrom: Point::new_e(),
-        lookat: Point::new_e(),
-        vup: Point::new(0.0, 1.0, 0.0),
-        dist_to_focus: 10.0,
-        vfov: 40.0,
-        aperture: 0.0,
-        sample_per_pixel: 100,
-        aspect_ratio: 16.0 / 9.0,
-    };
+    let mut scene_dat = scene::SceneData::new();
     let mut max_depth = 50;
 
     let iw_str = format!("{}", scene_dat.image_width);
@@ -96,21 +38,34 @@ fn main() {
             .required(true))
         .arg(Arg::with_name("Out Type")
             .value_name("TYPE")
-            .possible_values(&picture::PictureType::variants())
+            .possible_values(picture::PictureType::variants())
             .case_insensitive(true)
             .required(true)
             .help("Sets the image type.\n\nFor ppm image type, FILE exension does not matter. \n\n\
             For rgb image types, FILE must be PNG or JPG, which will define output image type\n\
-            rgb8 and rgb16 set the number of bits per channel when preparing image to save\n")
+            rgb8 and rgb16 set the number of bits per channel when preparing image to save\n\n\
+            exr, hdr and pfm write linear, unclamped radiance as floats, regardless of FILE extension\n")
             .index(2))
         .arg(Arg::with_name("Scene Number")
             .value_name("SCENE")
             .long("scene")
             .short('s')
-            .possible_values(&scene::Scene::variants())
+            .possible_values(scene::Scene::variants())
             .default_value("CornellBox")
             .case_insensitive(true)
             .help("Scene to Display"))
+        .arg(Arg::with_name("Scene File")
+            .value_name("SCENE_FILE")
+            .long("scene-file")
+            .help("TOML scene description to render instead of one of the built-in scenes. Overrides --scene"))
+        .arg(Arg::with_name("BVH Method")
+            .value_name("METHOD")
+            .long("bvh")
+            .possible_values(bvh::BvhMethod::variants())
+            .default_value("Flat")
+            .case_insensitive(true)
+            .help("How bounding volume hierarchies are built. Flat gives the fastest renders, Random is the original builder.\n\n\
+            With Flat, the top level of the scene is also placed in a flat hierarchy"))
         .arg(Arg::with_name("Image Width")
             .value_name("WIDTH")
             .long("width")
@@ -141,14 +96,472 @@ fn main() {
                 Ok(y) => {if y== 0 {Err(String::from("The value must be non-zero"))} else {Ok(())}},
                 Err(_) => Err(String::from("The value is not a valid unsigned integer")),
             }))
+        .arg(Arg::with_name("Environment")
+            .value_name("IMAGE")
+            .long("environment")
+            .help("Light the scene from an equirectangular HDR or EXR image of its surroundings, in place of the background")
+            .long_help("Light the scene from an equirectangular HDR or EXR image of its surroundings, replacing the scene's \
+            background or environment. The middle of the image faces -z, and its top is +y.\n\n\
+            Bright parts of the image are sampled directly, so small light sources such as the sun converge quickly"))
+        .arg(Arg::with_name("Environment Rotation")
+            .value_name("DEGREES")
+            .long("environment-rotation")
+            .requires("Environment")
+            .allow_hyphen_values(true)
+            .default_value("0")
+            .help("Counter-clockwise turn of the environment image about +y, seen from above")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y.is_finite() {Ok(())} else {Err(String::from("The value must be finite"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Environment Intensity")
+            .value_name("SCALE")
+            .long("environment-intensity")
+            .requires("Environment")
+            .default_value("1")
+            .help("Factor to scale the environment image's radiance by")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y >= 0.0 && y.is_finite() {Ok(())} else {Err(String::from("The value must be 0 or more"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Sky")
+            .long("sky")
+            .conflicts_with("Environment")
+            .help("Light the scene from a daylight sky with the sun, in place of the background")
+            .long_help("Light the scene from a daylight sky with the sun, replacing the scene's background or \
+            environment. The sky follows Preetham's analytic model of clear daylight, with the ground below the \
+            horizon. The sun is sampled directly, casting shadows softened by its size.\n\n\
+            Radiance is --sky-intensity thousands of candela per square meter, so an intensity of 1000 suits the \
+            photographic exposure of --f-stop, --shutter and --iso"))
+        .arg(Arg::with_name("Sun Elevation")
+            .value_name("DEGREES")
+            .long("sun-elevation")
+            .requires("Sky")
+            .help("Height of the sun above the horizon, from 0 to 90. Defaults to 45")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if (0.0..=90.0).contains(&y) {Ok(())} else {Err(String::from("The value must be from 0 to 90"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Sun Azimuth")
+            .value_name("DEGREES")
+            .long("sun-azimuth")
+            .requires("Sky")
+            .allow_hyphen_values(true)
+            .help("Bearing of the sun, from -z towards +x. Defaults to 30")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y.is_finite() {Ok(())} else {Err(String::from("The value must be finite"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Turbidity")
+            .value_name("TURBIDITY")
+            .long("turbidity")
+            .requires("Sky")
+            .help("Haziness of the air, from 2 for a very clear sky to 10 for a hazy one. Defaults to 3")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if (2.0..=10.0).contains(&y) {Ok(())} else {Err(String::from("The value must be from 2 to 10"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Ground Albedo")
+            .value_names(&["R", "G", "B"])
+            .long("ground-albedo")
+            .requires("Sky")
+            .number_of_values(3)
+            .help("Color of the ground below the horizon. Defaults to 0.3 0.3 0.3")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if (0.0..=1.0).contains(&y) {Ok(())} else {Err(String::from("The value must be from 0 to 1"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Sun Radius")
+            .value_name("DEGREES")
+            .long("sun-radius")
+            .requires("Sky")
+            .help("Angular radius of the sun, up to 90. Larger suns give softer shadows with the same total light. Defaults to 0.27, the real sun's")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y > 0.0 && y <= 90.0 {Ok(())} else {Err(String::from("The value must be above 0 and at most 90"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Sky Intensity")
+            .value_name("SCALE")
+            .long("sky-intensity")
+            .requires("Sky")
+            .help("Factor to scale the sky's radiance by. Defaults to 0.1, which is about as bright as the built-in backgrounds")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y >= 0.0 && y.is_finite() {Ok(())} else {Err(String::from("The value must be 0 or more"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Spectral")
+            .long("spectral")
+            .help("Trace wavelengths of light rather than red, green and blue, so glass can split light into rainbows")
+            .long_help("Trace wavelengths of light rather than red, green and blue, so glass with an index of refraction \
+            varying with wavelength splits light into rainbows. Each sample follows a random hero wavelength and two \
+            more spaced evenly across the visible range, converted back to RGB through the CIE matching functions.\n\n\
+            Colors in the scene are turned into smooth spectra, so other materials look much as they do in RGB, though \
+            noisier"))
+        .arg(Arg::with_name("Projection")
+            .value_name("PROJECTION")
+            .long("projection")
+            .possible_values(camera::ProjectionType::variants())
+            .case_insensitive(true)
+            .help("Camera projection, overriding the scene's. Perspective unless the scene says otherwise")
+            .long_help("Camera projection, overriding the scene's. Perspective unless the scene says otherwise.\n\n\
+            Perspective: thin lens perspective, with depth of field from the aperture\n\
+            Orthographic: parallel rays, --view-width wide\n\
+            Fisheye: circular fisheye fitting the shorter side of the image, --fisheye-fov across\n\
+            Equirectangular: full 360 degree panorama, best at an aspect ratio of 2"))
+        .arg(Arg::with_name("View Width")
+            .value_name("WIDTH")
+            .long("view-width")
+            .help("Width of an orthographic view in scene units. Defaults to the width a perspective view has at the point looked at")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y > 0.0 && y.is_finite() {Ok(())} else {Err(String::from("The value must be positive"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Fisheye FOV")
+            .value_name("DEGREES")
+            .long("fisheye-fov")
+            .help("Field of view across a fisheye's image circle, up to 360 degrees. Defaults to 180")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y > 0.0 && y <= 360.0 {Ok(())} else {Err(String::from("The value must be above 0 and at most 360"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Fisheye Mapping")
+            .value_name("MAPPING")
+            .long("fisheye-mapping")
+            .possible_values(camera::FisheyeMapping::variants())
+            .case_insensitive(true)
+            .help("How a fisheye spaces angles: Equidistant in proportion to the angle, or Equisolid in equal solid angles"))
+        .arg(Arg::with_name("Stereo")
+            .value_name("FORMAT")
+            .long("stereo")
+            .possible_values(camera::StereoFormat::variants())
+            .case_insensitive(true)
+            .help("Render both eyes of a stereo pair into FILE, overriding the scene. WIDTH is the width of the whole image")
+            .long_help("Render both eyes of a stereo pair into FILE, overriding the scene. WIDTH is the width of the whole image, \
+            and each eye has the scene's aspect ratio.\n\n\
+            SideBySide: left eye in the left half, right eye in the right half\n\
+            TopBottom: left eye in the top half, right eye in the bottom half\n\
+            Ods: omni-directional stereo equirectangular panoramas, left eye on top, best at an aspect ratio of 2"))
+        .arg(Arg::with_name("Interocular")
+            .value_name("DISTANCE")
+            .long("interocular")
+            .help("Distance between the eyes of a stereo render in scene units. Defaults to a thirtieth of the convergence distance")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y > 0.0 && y.is_finite() {Ok(())} else {Err(String::from("The value must be positive"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Convergence")
+            .value_name("DISTANCE")
+            .long("convergence")
+            .help("Distance in front of the camera that a stereo render places at the depth of the screen. Defaults to the distance to the point looked at")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y > 0.0 && y.is_finite() {Ok(())} else {Err(String::from("The value must be positive"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Stereo Method")
+            .value_name("METHOD")
+            .long("stereo-method")
+            .possible_values(camera::StereoMethod::variants())
+            .case_insensitive(true)
+            .help("How the eyes converge: OffAxis shifts the images of parallel eyes, ToeIn turns the eyes in. Defaults to OffAxis"))
+        .arg(Arg::with_name("Aperture Blades")
+            .value_name("BLADES")
+            .long("aperture-blades")
+            .conflicts_with("Aperture Image")
+            .help("Give the aperture this many straight blades, so out of focus highlights are polygons rather than circles")
+            .validator(|x| match x.parse::<u32>(){
+                Ok(y) => {if y < 3 {Err(String::from("The value must be at least 3"))} else {Ok(())}},
+                Err(_) => Err(String::from("The value is not a valid unsigned integer")),
+            }))
+        .arg(Arg::with_name("Aperture Image")
+            .value_name("IMAGE")
+            .long("aperture-image")
+            .help("Grey image to shape the aperture with, brighter pixels letting through more light. The longer side spans the aperture"))
+        .arg(Arg::with_name("Aperture Rotation")
+            .value_name("DEGREES")
+            .long("aperture-rotation")
+            .allow_hyphen_values(true)
+            .help("Counter-clockwise turn of the aperture's shape")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y.is_finite() {Ok(())} else {Err(String::from("The value must be finite"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Cat Eye")
+            .value_name("AMOUNT")
+            .long("cat-eye")
+            .help("Clip the aperture to the lens barrel towards the edges of the image, giving cat's eye bokeh and vignetting. \
+            AMOUNT is how far the barrel is offset at the corners in aperture radii, such as 0.5")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y >= 0.0 && y.is_finite() {Ok(())} else {Err(String::from("The value must be 0 or more"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("F Stop")
+            .value_name("F_NUMBER")
+            .long("f-stop")
+            .help("Photograph at this f-stop, setting the aperture from the lens's focal length and the brightness from the exposure")
+            .long_help("Photograph at this f-stop. Giving any of --f-stop, --shutter, --iso, --sensor-height or --scene-scale \
+            switches to photographic exposure, with defaults of f/8, a 1 second shutter, ISO 100, a 24mm tall sensor and \
+            1 meter per scene unit.\n\n\
+            The aperture is the focal length giving the scene's field of view on the sensor, over the f-stop. The shutter \
+            is open from time 0, with a scene unit of time being a second, so shorter shutters blur less motion. The \
+            brightness of tone mapped images follows the exposure value of the f-stop, shutter and ISO, with radiance \
+            taken to be in candela per square meter, and --exposure adjusts it further")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y > 0.0 && y.is_finite() {Ok(())} else {Err(String::from("The value must be positive"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Shutter")
+            .value_name("SECONDS")
+            .long("shutter")
+            .help("Photograph with the shutter open this long, such as 1/60, up to 1 second")
+            .validator(|x| lens::parse_seconds(x).map(|_| ())))
+        .arg(Arg::with_name("ISO")
+            .value_name("ISO")
+            .long("iso")
+            .help("Photograph at this sensitivity, where doubling it doubles the brightness")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y > 0.0 && y.is_finite() {Ok(())} else {Err(String::from("The value must be positive"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Sensor Height")
+            .value_name("MM")
+            .long("sensor-height")
+            .help("Height of the sensor of a photograph in millimeters, 24 for full frame")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y > 0.0 && y.is_finite() {Ok(())} else {Err(String::from("The value must be positive"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Scene Scale")
+            .value_name("METERS")
+            .long("scene-scale")
+            .help("Length of one scene unit in meters, to size a photograph's aperture")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y > 0.0 && y.is_finite() {Ok(())} else {Err(String::from("The value must be positive"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Tone Map")
+            .value_name("OPERATOR")
+            .long("tonemap")
+            .possible_values(color::ToneMap::variants())
+            .default_value("Clamp")
+            .case_insensitive(true)
+            .help("Tone mapping operator used to fit radiance into ppm, rgb8 and rgb16 images"))
+        .arg(Arg::with_name("Exposure")
+            .value_name("STOPS")
+            .long("exposure")
+            .allow_hyphen_values(true)
+            .default_value("0")
+            .help("Exposure adjustment in stops applied before tone mapping. Each stop doubles the brightness")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y.is_finite() {Ok(())} else {Err(String::from("The value must be finite"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("White Point")
+            .value_name("LUMINANCE")
+            .long("white-point")
+            .help("Luminance mapped to white by the extendedreinhard and uncharted2 operators. Defaults to 4 and 5.6 respectively")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y > 0.0 && y.is_finite() {Ok(())} else {Err(String::from("The value must be positive"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Transfer")
+            .value_name("CURVE")
+            .long("transfer")
+            .possible_values(color::Transfer::variants())
+            .default_value("Srgb")
+            .case_insensitive(true)
+            .help("Transfer curve encoding tone mapped values in ppm, rgb8 and rgb16 images"))
+        .arg(Arg::with_name("Progressive")
+            .long("progressive")
+            .help("Render in passes that double the samples per pixel, rewriting FILE after each, until SAMPLES are taken")
+            .long_help("Render in passes that double the samples per pixel (1, 2, 4, ...), rewriting FILE after each pass.\n\n\
+            Stops at --max-samples, which defaults to SAMPLES unless --time-limit is given, or once --time-limit passes"))
+        .arg(Arg::with_name("Time Limit")
+            .value_name("SECONDS")
+            .long("time-limit")
+            .requires("Progressive")
+            .help("Stop a progressive render after this many seconds, saving the image as it is")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y > 0.0 && y.is_finite() {Ok(())} else {Err(String::from("The value must be positive"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Max Samples")
+            .value_name("SAMPLES")
+            .long("max-samples")
+            .requires("Progressive")
+            .help("Samples per pixel at which a progressive render stops")
+            .validator(|x| match x.parse::<u32>(){
+                Ok(y) => {if y == 0 {Err(String::from("The value must be non-zero"))} else {Ok(())}},
+                Err(_) => Err(String::from("The value is not a valid unsigned integer")),
+            }))
+        .arg(Arg::with_name("Adaptive")
+            .value_name("THRESHOLD")
+            .long("adaptive")
+            .help("Sample adaptively, stopping pixels once their estimated error relative to their brightness is below THRESHOLD, such as 0.01")
+            .long_help("Sample adaptively. Every pixel first gets --min-samples samples, then passes double the samples of pixels \
+            whose estimated error, relative to their brightness, is above THRESHOLD, such as 0.01.\n\n\
+            The total is kept to the budget SAMPLES would take over the whole image, so converged pixels give their share to noisy \
+            ones, up to 8 times SAMPLES for any one pixel")
+            .validator(|x| match x.parse::<f64>(){
+                Ok(y) => {if y > 0.0 && y.is_finite() {Ok(())} else {Err(String::from("The value must be positive"))}},
+                Err(_) => Err(String::from("The value is not a valid number")),
+            }))
+        .arg(Arg::with_name("Min Samples")
+            .value_name("SAMPLES")
+            .long("min-samples")
+            .requires("Adaptive")
+            .default_value("16")
+            .help("Samples every pixel gets before adaptive sampling judges its error")
+            .validator(|x| match x.parse::<u32>(){
+                Ok(y) => {if y < 2 {Err(String::from("The value must be at least 2"))} else {Ok(())}},
+                Err(_) => Err(String::from("The value is not a valid unsigned integer")),
+            }))
+        .arg(Arg::with_name("Sample Map")
+            .value_name("MAP_FILE")
+            .long("sample-map")
+            .help("Also save a grey PNG or JPG showing how many samples each pixel received, white being the most"))
+        .arg(Arg::with_name("AOV")
+            .value_name("AOV")
+            .long("aov")
+            .possible_values(aov::AovType::variants())
+            .case_insensitive(true)
+            .multiple_values(true)
+            .use_value_delimiter(true)
+            .help("Auxiliary images to save beside FILE, such as --aov normal,albedo. Each is saved as TYPE, named FILE with the AOV's name before the extension")
+            .long_help("Auxiliary images to save beside FILE, such as --aov normal,albedo. Each is saved as TYPE, named FILE with the AOV's name before the extension.\n\n\
+            normal: shading normal of the first surface hit\n\
+            albedo: base color of the first surface hit\n\
+            depth: distance from the camera to the first hit\n\
+            position: world position of the first hit\n\
+            id: number of the material first hit, from 1 in the order they first appear\n\n\
+            Pixels where nothing is hit are 0, except albedo, which is the background. \
+            Float types store the values unchanged, other types scale them to be viewable"))
+        .arg(Arg::with_name("AOV EXR")
+            .value_name("EXR_FILE")
+            .long("aov-exr")
+            .help("Save the image with each --aov, or every AOV if none are given, as layers of one OpenEXR file"))
+        .arg(Arg::with_name("Denoise")
+            .long("denoise")
+            .help("Denoise the image before saving it, guided by the normal, albedo and depth of what each pixel sees")
+            .long_help("Denoise the image before saving it, guided by the normal, albedo and depth of what each pixel sees. \
+            Makes low sample previews usable, at the cost of some fine detail. --aov images and the --aov-exr color layer are \
+            left as rendered"))
+        .arg(Arg::with_name("Frames")
+            .value_name("FRAMES")
+            .long("frames")
+            .help("Render an animation, such as --frames 0..120, saving each frame numbered like FILE_0001.png")
+            .long_help("Render an animation, saving each frame numbered like FILE_0001.png. FRAMES is START..END, which \
+            excludes END, START..=END, or a single frame number.\n\n\
+            The camera follows the keyframes of a scene file's [camera] table, and the scene is built once for every frame. \
+            Time limits, checkpoints and other outputs apply to each frame, and --resume continues from the first frame \
+            with a checkpoint, skipping frames already saved")
+            .validator(|x| animation::parse_frames(x).map(|_| ())))
+        .arg(Arg::with_name("Checkpoint")
+            .value_name("CHECKPOINT")
+            .long("checkpoint")
+            .help("File to periodically save render progress to. Defaults to FILE with .ckpt appended. Removed once the image is saved"))
+        .arg(Arg::with_name("Checkpoint Interval")
+            .value_name("SECONDS")
+            .long("checkpoint-interval")
+            .default_value("60")
+            .help("Seconds between checkpoint saves. 0 disables checkpoints")
+            .validator(|x| match x.parse::<u64>(){
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("The value is not a valid unsigned integer")),
+            }))
+        .arg(Arg::with_name("Resume")
+            .long("resume")
+            .help("Continue the render saved in the checkpoint. Scene options must match the interrupted render"))
+        .arg(Arg::with_name("Seed")
+            .value_name("SEED")
+            .long("seed")
+            .help("Seed for all randomness, making the scene and the rendered image reproducible. A random seed is used and printed if not given")
+            .validator(|x| match x.parse::<u64>(){
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("The value is not a valid unsigned integer")),
+            }))
         .get_matches();
 
     let scene = value_t!(matches, "Scene Number", scene::Scene).unwrap();
     let outname = matches.value_of("Out File").unwrap();
+
+    // Sequences save each frame, and its checkpoint, under its own name
+    let frames = matches
+        .value_of("Frames")
+        .map(|f| animation::parse_frames(f).unwrap());
+    let frame_fname = |fname: &str, frame: u32| -> String {
+        match frames {
+            Some(_) => animation::frame_fname(fname, frame),
+            None => fname.to_string(),
+        }
+    };
+    let ckpt_fname = |frame: u32| -> String {
+        match matches.value_of("Checkpoint") {
+            Some(c) => frame_fname(c, frame),
+            None => format!("{}.ckpt", frame_fname(outname, frame)),
+        }
+    };
+    let ckpt_interval = value_t!(matches, "Checkpoint Interval", u64).unwrap();
+
+    let load_checkpoint = |ckpt_name: &str| {
+        accumulator::Accumulator::load_checkpoint(ckpt_name).unwrap_or_else(|e| {
+            eprintln!("Error loading checkpoint '{}': {}", ckpt_name, e);
+            std::process::exit(1);
+        })
+    };
+
+    // A resumed render must continue with the seed it started with, which a sequence takes from the first frame
+    // with a checkpoint. A single image must have a checkpoint to resume.
+    let frame_list: Vec<u32> = frames.clone().unwrap_or(0..1).collect();
+    let resume = matches.is_present("Resume");
+    let mut resumed = None;
+    if resume {
+        let first = frame_list
+            .iter()
+            .copied()
+            .find(|&f| frames.is_none() || std::path::Path::new(&ckpt_fname(f)).exists());
+        if let Some(f) = first {
+            resumed = Some((f, load_checkpoint(&ckpt_fname(f))));
+        }
+    }
+
+    let seed = match (value_t!(matches, "Seed", u64), &resumed) {
+        (Ok(s), Some((f, (_, key)))) if s != key.seed => {
+            eprintln!(
+                "Seed {} does not match seed {} of checkpoint '{}'",
+                s,
+                key.seed,
+                ckpt_fname(*f)
+            );
+            std::process::exit(1);
+        }
+        (_, Some((_, (_, key)))) => key.seed,
+        (Ok(s), None) => s,
+        (Err(_), None) => {
+            let s = entropy_seed();
+            eprintln!("Seed: {}", s);
+            s
+        }
+    };
+    // Scene generation happens on this thread, so is covered by the seed
+    seed_rng(seed);
+
     let outtype = value_t!(matches, "Out Type", picture::PictureType).unwrap();
+    scene_dat.bvh_method = value_t!(matches, "BVH Method", bvh::BvhMethod).unwrap();
 
     // World
-    let world = scene::match_scene(scene, &mut scene_dat);
+    let world = match matches.value_of("Scene File") {
+        Some(fname) => scene_file::load_scene(fname, &mut scene_dat).unwrap_or_else(|e| {
+            eprintln!("Error loading scene file '{}': {}", fname, e);
+            std::process::exit(1);
+        }),
+        None => scene::match_scene(scene, &mut scene_dat),
+    };
+
+    // The top level list is tested object by object, so flatten it as well where asked to
+    let world: Box<dyn hittable::Hittable + Sync + Send> = match scene_dat.bvh_method {
+        bvh::BvhMethod::Flat => Box::new(flat_bvh::FlatBvh::new(&world, 0.0, 1.0)),
+        _ => Box::new(world),
+    };
 
     //Collect User Values and Override Scene if user provided
     if let Ok(y) = value_t!(matches, "Image Width", u32) {
@@ -167,60 +580,216 @@ fn main() {
         max_depth = y
     }
 
-    // Camera
-    let cam = camera::Camera::new(
-        &scene_dat.lookfrom,
-        &scene_dat.lookat,
-        &scene_dat.vup,
-        scene_dat.vfov,
-        scene_dat.aspect_ratio,
-        scene_dat.aperture,
-        scene_dat.dist_to_focus,
-        0.0,
-        1.0,
-    );
+    if let Some(fname) = matches.value_of("Environment") {
+        let env = environment::EnvironmentMap::load(
+            fname,
+            value_t!(matches, "Environment Rotation", f64).unwrap(),
+            value_t!(matches, "Environment Intensity", f64).unwrap(),
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("Error loading environment map '{}': {}", fname, e);
+            std::process::exit(1);
+        });
+        scene_dat.environment = Some(std::sync::Arc::new(env));
+    }
+
+    if matches.is_present("Sky") {
+        let mut settings = sky::SkySettings::new();
+        if let Ok(e) = value_t!(matches, "Sun Elevation", f64) {
+            settings.sun_elevation = e;
+        }
+        if let Ok(a) = value_t!(matches, "Sun Azimuth", f64) {
+            settings.sun_azimuth = a;
+        }
+        if let Ok(t) = value_t!(matches, "Turbidity", f64) {
+            settings.turbidity = t;
+        }
+        if let Ok(g) = values_t!(matches, "Ground Albedo", f64) {
+            settings.ground_albedo = vec3::Color::new(g[0], g[1], g[2]);
+        }
+        if let Ok(r) = value_t!(matches, "Sun Radius", f64) {
+            settings.sun_radius = r;
+        }
+        if let Ok(i) = value_t!(matches, "Sky Intensity", f64) {
+            settings.intensity = i;
+        }
+        scene_dat.environment = Some(std::sync::Arc::new(sky::PreethamSky::new(&settings)));
+    }
+
+    if matches.is_present("Spectral") {
+        scene_dat.spectral = true;
+    }
+
+    if let Ok(p) = value_t!(matches, "Projection", camera::ProjectionType) {
+        scene_dat.projection.kind = p;
+    }
+    if let Ok(w) = value_t!(matches, "View Width", f64) {
+        scene_dat.projection.view_width = Some(w);
+    }
+    if let Ok(f) = value_t!(matches, "Fisheye FOV", f64) {
+        scene_dat.projection.fisheye_fov = f;
+    }
+    if let Ok(m) = value_t!(matches, "Fisheye Mapping", camera::FisheyeMapping) {
+        scene_dat.projection.fisheye_mapping = m;
+    }
+    if let Ok(f) = value_t!(matches, "Stereo", camera::StereoFormat) {
+        scene_dat.stereo.format = Some(f);
+    }
+    if let Ok(d) = value_t!(matches, "Interocular", f64) {
+        scene_dat.stereo.interocular = Some(d);
+    }
+    if let Ok(d) = value_t!(matches, "Convergence", f64) {
+        scene_dat.stereo.convergence = Some(d);
+    }
+    if let Ok(m) = value_t!(matches, "Stereo Method", camera::StereoMethod) {
+        scene_dat.stereo.method = m;
+    }
+
+    if let Ok(b) = value_t!(matches, "Aperture Blades", u32) {
+        scene_dat.lens.shape = lens::ApertureShape::Polygon { blades: b };
+    }
+    if let Some(fname) = matches.value_of("Aperture Image") {
+        let mask = lens::ApertureMask::load(fname).unwrap_or_else(|e| {
+            eprintln!("Error loading aperture image '{}': {}", fname, e);
+            std::process::exit(1);
+        });
+        scene_dat.lens.shape = lens::ApertureShape::Image(std::sync::Arc::new(mask));
+    }
+    if let Ok(r) = value_t!(matches, "Aperture Rotation", f64) {
+        scene_dat.lens.rotation = r;
+    }
+    if let Ok(c) = value_t!(matches, "Cat Eye", f64) {
+        scene_dat.lens.cat_eye = c;
+    }
+
+    let photographic = ["F Stop", "Shutter", "ISO", "Sensor Height", "Scene Scale"];
+    if photographic.iter().any(|a| matches.is_present(a)) {
+        let p = scene_dat
+            .photographic
+            .get_or_insert_with(lens::Photographic::new);
+        if let Ok(f) = value_t!(matches, "F Stop", f64) {
+            p.f_stop = f;
+        }
+        if let Some(t) = matches.value_of("Shutter") {
+            p.shutter = lens::parse_seconds(t).unwrap();
+        }
+        if let Ok(s) = value_t!(matches, "ISO", f64) {
+            p.iso = s;
+        }
+        if let Ok(h) = value_t!(matches, "Sensor Height", f64) {
+            p.sensor_height = h;
+        }
+        if let Ok(s) = value_t!(matches, "Scene Scale", f64) {
+            p.scene_scale = s;
+        }
+    }
+    // The shutter opens at time 0, and closes after the whole of the scene's motion unless photographed
+    let shutter = scene_dat.photographic.map_or(1.0, |p| p.shutter);
+
+    let tone = color::ToneSettings {
+        tonemap: value_t!(matches, "Tone Map", color::ToneMap).unwrap(),
+        exposure: value_t!(matches, "Exposure", f64).unwrap()
+            + scene_dat.photographic.map_or(0.0, |p| p.exposure_stops()),
+        white_point: value_t!(matches, "White Point", f64).ok(),
+        transfer: value_t!(matches, "Transfer", color::Transfer).unwrap(),
+    };
+
+    // Progressive renders stop at the sample limit if given, run until the time limit if only that is given, and
+    // otherwise stop at the usual sample count
+    let progressive = matches.is_present("Progressive");
+    let time_limit = value_t!(matches, "Time Limit", f64)
+        .ok()
+        .map(Duration::from_secs_f64);
+    let spp = match value_t!(matches, "Max Samples", u32) {
+        Ok(m) => m,
+        Err(_) if time_limit.is_some() => u32::MAX,
+        Err(_) => scene_dat.sample_per_pixel,
+    };
+
+    let adaptive = value_t!(matches, "Adaptive", f64).ok();
+    let min_samples = u32::min(value_t!(matches, "Min Samples", u32).unwrap(), spp);
+    let sample_map = matches.value_of("Sample Map");
 
     //Image
-    let mut img = Picture::new(
+    // A stereo image holds both eyes, each with the scene's aspect ratio
+    let aspect_ratio = scene_dat.stereo.image_aspect_ratio(scene_dat.aspect_ratio);
+    let img = Picture::new(
         scene_dat.image_width,
-        scene_dat.aspect_ratio,
+        aspect_ratio,
         scene_dat.sample_per_pixel,
         &String::from(outname),
         outtype,
+        tone,
     )
     .expect("Error making image");
+    let (width, height) = (img.width(), img.height());
+    drop(img);
 
-    //Render
-    let err = stderr();
-    let mut errlock = err.lock();
-
-    let mut row: Vec<Color> = Vec::new();
+    // Beyond the camera, the world and every setting are shared by all frames
+    let settings = render::RenderSettings {
+        width,
+        height,
+        aspect_ratio,
+        max_depth,
+        shutter,
+        seed,
+        sampling: render::Sampling {
+            spp,
+            progressive,
+            adaptive,
+            min_samples,
+            time_limit,
+        },
+        outtype,
+        tone,
+        denoise: matches.is_present("Denoise"),
+        aov_types: values_t!(matches, "AOV", aov::AovType).unwrap_or_default(),
+        ckpt_interval,
+    };
 
-    for j in (0..img.height()).rev() {
-        let r = img.height() - j - 1;
-        write!(errlock, "\rScanlines remaining: {} ", j).expect("Fail to write to Err");
-        errlock.flush().expect("Fail to flush stderr");
+    for frame in frame_list {
+        let files = render::FrameFiles {
+            image: frame_fname(outname, frame),
+            checkpoint: ckpt_fname(frame),
+            sample_map: sample_map.map(|f| frame_fname(f, frame)),
+            aov_exr: matches.value_of("AOV EXR").map(|f| frame_fname(f, frame)),
+        };
 
-        (0..img.width())
-            .into_par_iter()
-            .map(|i| {
-                (0..img.samples_per_pixel())
-                    .into_iter()
-                    .map(|_| {
-                        let u = (i as f64 + random_double()) / (img.width() - 1) as f64;
-                        let v = (j as f64 + random_double()) / (img.height() - 1) as f64;
+        let resumed = if resumed.as_ref().is_some_and(|(f, _)| *f == frame) {
+            resumed.take().map(|(_, r)| r)
+        } else if resume && std::path::Path::new(&files.checkpoint).exists() {
+            Some(load_checkpoint(&files.checkpoint))
+        } else {
+            None
+        };
 
-                        let r = cam.get_ray(u, v);
+        if frames.is_some() {
+            // Without a checkpoint, a frame of a resumed sequence that already has its image is finished
+            if resume && resumed.is_none() && std::path::Path::new(&files.image).exists() {
+                eprintln!("Frame {} is already rendered", frame);
+                continue;
+            }
+            eprintln!("Frame {}", frame);
+        }
 
-                        ray_color(&r, &scene_dat.background, &world, max_depth)
-                    })
-                    .fold(Color::new_e(), |acc, x| acc + x)
-            })
-            .collect_into_vec(&mut row);
+        // Each frame of a sequence has its own random streams, so noise doesn't stay fixed to the screen
+        let frame_seed = match frames {
+            Some(_) => stream_seed(seed, frame as u64),
+            None => seed,
+        };
 
-        img.write_row(&row, r).expect("Error Writing Row");
+        if let Err(e) = render::render_frame(
+            world.as_ref(),
+            &scene_dat,
+            &settings,
+            frame,
+            frame_seed,
+            &files,
+            resumed,
+        ) {
+            eprintln!("\nError rendering '{}': {}", files.image, e);
+            std::process::exit(1);
+        }
+        println!();
     }
-
-    img.save().expect("Error Saving To File");
-    println!();
 }
@@ -0,0 +1,258 @@
+//! 4x4 matrices for affine transforms of points, directions and normals
+
+use crate::{util::*, vec3::*};
+use std::ops::Mul;
+
+/**
+ * Row major 4x4 matrix, applied to column vectors.
+ *
+ * A product a * b applies b first, then a, so transforms compose right to left as written.
+ */
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mat4 {
+    m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Mat4 {
+        Mat4 { m }
+    }
+
+    pub fn identity() -> Mat4 {
+        Mat4::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translate(offset: &Vec3) -> Mat4 {
+        Mat4::new([
+            [1.0, 0.0, 0.0, offset.x()],
+            [0.0, 1.0, 0.0, offset.y()],
+            [0.0, 0.0, 1.0, offset.z()],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Scales each axis by the matching component of factors, which may differ
+    pub fn scale(factors: &Vec3) -> Mat4 {
+        Mat4::new([
+            [factors.x(), 0.0, 0.0, 0.0],
+            [0.0, factors.y(), 0.0, 0.0],
+            [0.0, 0.0, factors.z(), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /**
+     * Rotates angle degrees about axis through the origin, counter-clockwise when looking down axis towards the origin.
+     */
+    pub fn rotate(axis: &Vec3, angle: f64) -> Mat4 {
+        let a = unit_vector(*axis);
+        let rads = degs_to_rads(angle);
+        let (sin, cos) = (f64::sin(rads), f64::cos(rads));
+        let t = 1.0 - cos;
+
+        Mat4::new([
+            [
+                t * a.x() * a.x() + cos,
+                t * a.x() * a.y() - sin * a.z(),
+                t * a.x() * a.z() + sin * a.y(),
+                0.0,
+            ],
+            [
+                t * a.x() * a.y() + sin * a.z(),
+                t * a.y() * a.y() + cos,
+                t * a.y() * a.z() - sin * a.x(),
+                0.0,
+            ],
+            [
+                t * a.x() * a.z() - sin * a.y(),
+                t * a.y() * a.z() + sin * a.x(),
+                t * a.z() * a.z() + cos,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn rotate_x(angle: f64) -> Mat4 {
+        Mat4::rotate(&Vec3::new(1.0, 0.0, 0.0), angle)
+    }
+
+    pub fn rotate_y(angle: f64) -> Mat4 {
+        Mat4::rotate(&Vec3::new(0.0, 1.0, 0.0), angle)
+    }
+
+    pub fn rotate_z(angle: f64) -> Mat4 {
+        Mat4::rotate(&Vec3::new(0.0, 0.0, 1.0), angle)
+    }
+
+    /**
+     * Shears each axis in proportion to the others.
+     *
+     * xy is how far x moves per unit of y, xz how far x moves per unit of z, and so on.
+     */
+    pub fn shear(xy: f64, xz: f64, yx: f64, yz: f64, zx: f64, zy: f64) -> Mat4 {
+        Mat4::new([
+            [1.0, xy, xz, 0.0],
+            [yx, 1.0, yz, 0.0],
+            [zx, zy, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut out = [[0.0; 4]; 4];
+        for (r, row) in out.iter_mut().enumerate() {
+            for (c, v) in row.iter_mut().enumerate() {
+                *v = self.m[c][r];
+            }
+        }
+        Mat4::new(out)
+    }
+
+    /// Inverse by Gauss-Jordan elimination, or None if the matrix is singular, as a zero scale makes it
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+
+        for col in 0..4 {
+            // Partial pivoting, swapping in the row with the largest value in this column
+            let pivot = (col..4)
+                .max_by(|&x, &y| a[x][col].abs().total_cmp(&a[y][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let p = a[col][col];
+            for c in 0..4 {
+                a[col][c] /= p;
+                inv[col][c] /= p;
+            }
+
+            for r in 0..4 {
+                if r != col {
+                    let f = a[r][col];
+                    for c in 0..4 {
+                        a[r][c] -= f * a[col][c];
+                        inv[r][c] -= f * inv[col][c];
+                    }
+                }
+            }
+        }
+
+        Some(Mat4::new(inv))
+    }
+
+    /// Determinant of the upper 3x3, the factor by which the transform scales volumes
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Transforms a position, including any translation
+    pub fn point(&self, p: &Point) -> Point {
+        let m = &self.m;
+        Point::new(
+            m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3],
+            m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3],
+            m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3],
+        )
+    }
+
+    /// Transforms a direction or offset, ignoring translation
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut out = [[0.0; 4]; 4];
+        for (r, row) in out.iter_mut().enumerate() {
+            for (c, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[r][k] * rhs.m[k][c]).sum();
+            }
+        }
+        Mat4::new(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: &Mat4, b: &Mat4) {
+        for r in 0..4 {
+            for c in 0..4 {
+                assert!(
+                    (a.m[r][c] - b.m[r][c]).abs() < 1e-9,
+                    "{:?} is not {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let m = Mat4::translate(&Vec3::new(1.0, -2.0, 3.0))
+            * Mat4::rotate(&Vec3::new(1.0, 2.0, -1.0), 37.0)
+            * Mat4::scale(&Vec3::new(2.0, 0.5, -3.0))
+            * Mat4::shear(0.3, 0.0, -0.2, 0.1, 0.0, 0.4);
+        let inv = m.inverse().unwrap();
+
+        assert_near(&(m * inv), &Mat4::identity());
+        assert_near(&(inv * m), &Mat4::identity());
+
+        let p = Point::new(0.5, -1.5, 2.0);
+        assert!((inv.point(&m.point(&p)) - p).length() < 1e-9);
+        let v = Vec3::new(-1.0, 0.25, 4.0);
+        assert!((inv.vector(&m.vector(&v)) - v).length() < 1e-9);
+    }
+
+    #[test]
+    fn inverse_needs_a_pivot_swap() {
+        // A zero on the diagonal, which only inverts by swapping rows
+        let m = Mat4::new([
+            [0.0, 1.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 2.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert_near(&(m * m.inverse().unwrap()), &Mat4::identity());
+    }
+
+    #[test]
+    fn singular_has_no_inverse() {
+        assert!(Mat4::scale(&Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+        assert!(Mat4::shear(1.0, 0.0, 1.0, 0.0, 0.0, 0.0)
+            .inverse()
+            .is_none());
+    }
+
+    #[test]
+    fn determinant_is_the_volume_scale() {
+        let scale = Mat4::scale(&Vec3::new(2.0, 3.0, -4.0));
+        assert!((scale.determinant3() + 24.0).abs() < 1e-9);
+
+        let rigid = Mat4::translate(&Vec3::new(5.0, 0.0, 0.0)) * Mat4::rotate_y(70.0);
+        assert!((rigid.determinant3() - 1.0).abs() < 1e-9);
+    }
+}
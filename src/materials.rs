@@ -1,16 +1,89 @@
-use crate::{hittable::*, ray::Ray, texture::*, vec3::*};
+use crate::{
+    hittable::*, microfacet::*, onb::ONB, pdf::*, ray::Ray, spectrum::Ior, texture::*, util::*,
+    vec3::*,
+};
 use std::fmt::Debug;
 use std::sync::Arc;
 
+/**
+ * Result of scattering a ray off a Material.
+ *
+ * Specular materials give the exact scattered ray in specular_ray, to be followed with attenuation applied.
+ * Other materials give a pdf to sample the scattered direction from, which the renderer may mix with light sampling,
+ * weighting the result by the Material's scattering.
+ */
+pub struct ScatterRecord {
+    pub specular_ray: Ray,
+    pub is_specular: bool,
+    pub attenuation: Color,
+    pub pdf_ptr: Option<Box<dyn Pdf>>,
+}
+
+impl ScatterRecord {
+    pub fn new() -> ScatterRecord {
+        ScatterRecord {
+            specular_ray: Ray::new(&Point::new_e(), &Vec3::new_e(), 0.0),
+            is_specular: false,
+            attenuation: Color::new_e(),
+            pdf_ptr: None,
+        }
+    }
+
+    pub fn new_specular(specular_ray: Ray, attenuation: Color) -> ScatterRecord {
+        ScatterRecord {
+            specular_ray,
+            is_specular: true,
+            attenuation,
+            pdf_ptr: None,
+        }
+    }
+
+    pub fn new_pdf(pdf: Box<dyn Pdf>, attenuation: Color) -> ScatterRecord {
+        ScatterRecord {
+            specular_ray: Ray::new(&Point::new_e(), &Vec3::new_e(), 0.0),
+            is_specular: false,
+            attenuation,
+            pdf_ptr: Some(pdf),
+        }
+    }
+}
+
 pub trait Material {
     /// Scatters an incoming ray according to the Material implementing it and the HitRecord.
     ///
-    /// Returns bool for success, Ray containing the scattered ray, and Color containing the attenuation
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Ray, Color);
+    /// Returns bool for success, and the ScatterRecord describing how the ray scatters
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (bool, ScatterRecord);
+
+    /// Density of scattering into scattered, for materials that scatter by pdf rather than specularly
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
+    /**
+     * Share of the light arriving along scattered that leaves towards r_in, in each color, on top of attenuation.
+     *
+     * Defaults to scattering_pdf, the same for every color. Materials whose color changes with direction override
+     * this instead.
+     */
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let s = self.scattering_pdf(r_in, rec, scattered);
+        Color::new(s, s, s)
+    }
 
     fn emitted(&self, _u: f64, _v: f64, _p: &Point) -> Color {
         Color::new(0.0, 0.0, 0.0)
     }
+
+    /// Base color of the surface at a hit, independent of lighting, as used for albedo output images
+    fn albedo(&self, _u: f64, _v: f64, _p: &Point) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// Whether the direction a ray scatters in depends on its wavelength, so in a spectral render only the ray's
+    /// hero wavelength can follow it
+    fn is_dispersive(&self) -> bool {
+        false
+    }
 }
 
 impl Debug for dyn Material {
@@ -30,12 +103,8 @@ impl NoHit {
 }
 
 impl Material for NoHit {
-    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> (bool, Ray, Color) {
-        (
-            false,
-            Ray::new(&Point::new_e(), &Vec3::new_e(), 0.0),
-            Color::new_e(),
-        )
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> (bool, ScatterRecord) {
+        (false, ScatterRecord::new())
     }
 }
 
@@ -59,52 +128,193 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Ray, Color) {
-        let scatter_dir = rec.normal + random_unit_vector();
-        let scat = Ray::new(&rec.p, &scatter_dir, r_in.time());
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> (bool, ScatterRecord) {
         let atten = self.albedo.value(rec.u, rec.v, &rec.p);
-        (true, scat, atten)
+        (
+            true,
+            ScatterRecord::new_pdf(Box::new(CosinePdf::new(&rec.normal)), atten),
+        )
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = dot(rec.normal, unit_vector(scattered.direction()));
+        if cosine < 0.0 {
+            0.0
+        } else {
+            cosine / PI
+        }
+    }
+
+    fn albedo(&self, u: f64, v: f64, p: &Point) -> Color {
+        self.albedo.value(u, v, p)
+    }
+}
+
+arg_enum! {
+    /// Public MetalPreset enum of metals with measured refractive indices
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum MetalPreset {
+        Gold,
+        Copper,
+        Silver,
+        Aluminum,
+    }
+}
+
+impl MetalPreset {
+    /// Real and imaginary parts of the metal's refractive index, at red, green and blue wavelengths
+    pub fn fresnel(self) -> MetalFresnel {
+        let (eta, k) = match self {
+            MetalPreset::Gold => (
+                Color::new(0.143, 0.374, 1.442),
+                Color::new(3.983, 2.385, 1.603),
+            ),
+            MetalPreset::Copper => (
+                Color::new(0.200, 0.924, 1.102),
+                Color::new(3.912, 2.452, 2.142),
+            ),
+            MetalPreset::Silver => (
+                Color::new(0.155, 0.117, 0.138),
+                Color::new(4.828, 3.122, 2.147),
+            ),
+            MetalPreset::Aluminum => (
+                Color::new(1.657, 0.880, 0.521),
+                Color::new(9.224, 6.270, 4.837),
+            ),
+        };
+        MetalFresnel::Conductor { eta, k }
     }
 }
 
+/// How much light a metal reflects, depending on the angle it's seen at
+#[derive(Debug, Clone, Copy)]
+pub enum MetalFresnel {
+    /// Schlick's approximation, from the color reflected head on to white at grazing angles
+    Schlick(Color),
+    /// The Fresnel equations of a conductor with complex refractive index eta + ik in each color
+    Conductor { eta: Color, k: Color },
+}
+
+impl MetalFresnel {
+    /// Fraction of light reflected, where cos is the cosine of the angle between the light and the reflecting normal
+    pub fn value(&self, cos: f64) -> Color {
+        match self {
+            MetalFresnel::Schlick(f0) => {
+                let f = (1.0 - clamp(cos, 0.0, 1.0)).powf(5.0);
+                *f0 + (Color::new(1.0, 1.0, 1.0) - *f0) * f
+            }
+            MetalFresnel::Conductor { eta, k } => Color::new(
+                fresnel_conductor(cos, eta.x(), k.x()),
+                fresnel_conductor(cos, eta.y(), k.y()),
+                fresnel_conductor(cos, eta.z(), k.z()),
+            ),
+        }
+    }
+}
+
+// Roughness below which a metal is treated as a perfect mirror
+const MIN_ROUGHNESS: f64 = 1e-3;
+
+/**
+ * Conductor with a GGX microfacet surface.
+ *
+ * Roughness is GGX's alpha, from 0 for a perfect mirror to 1 for a very rough surface. An anisotropic metal, with
+ * different roughnesses, is brushed in the direction of the first one, which runs around the y axis like lines of
+ * latitude, so it streaks highlights along the second. Scattered rays sample the facet normals visible from the
+ * incoming ray, and masking and shadowing between facets follows Smith's height correlated model.
+ */
 pub struct Metal {
-    pub albedo: Color,
-    pub fuzz: f64,
+    pub fresnel: MetalFresnel,
+    pub ggx: Ggx,
 }
 
 impl Metal {
+    /// Metal reflecting color a head on, with fuzz as its roughness, at most 1
     pub fn new(a: Color, f: f64) -> Metal {
+        Metal::new_conductor(MetalFresnel::Schlick(a), f, f)
+    }
+
+    pub fn new_conductor(fresnel: MetalFresnel, roughness_u: f64, roughness_v: f64) -> Metal {
         Metal {
-            albedo: a,
-            fuzz: if f < 1.0 { f } else { 1.0 },
+            fresnel,
+            ggx: Ggx {
+                alpha_x: clamp(roughness_u, 0.0, 1.0),
+                alpha_y: clamp(roughness_v, 0.0, 1.0),
+            },
         }
     }
+
+    fn is_mirror(&self) -> bool {
+        self.ggx.alpha_x < MIN_ROUGHNESS && self.ggx.alpha_y < MIN_ROUGHNESS
+    }
+}
+
+/// Frame at a microfacet surface with w along the normal and u along the direction of the first roughness
+fn microfacet_frame(normal: &Vec3) -> ONB {
+    let around_y = cross(Vec3::new(0.0, 1.0, 0.0), *normal);
+    if around_y.length_squared() > 1e-12 {
+        ONB::build_from_w_u(normal, &around_y)
+    } else {
+        ONB::build_from_w_u(normal, &Vec3::new(1.0, 0.0, 0.0))
+    }
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Ray, Color) {
-        let reflected = reflect(&unit_vector(r_in.direction()), &rec.normal);
-        let scattered = Ray::new(
-            &rec.p,
-            &(reflected + self.fuzz * random_in_unit_sphere()),
-            r_in.time(),
-        );
-        let atten = self.albedo;
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (bool, ScatterRecord) {
+        let unit_dir = unit_vector(r_in.direction());
+        if self.is_mirror() {
+            let reflected = reflect(&unit_dir, &rec.normal);
+            let scattered = Ray::new(&rec.p, &reflected, r_in.time());
+            let atten = self.fresnel.value(dot(-unit_dir, rec.normal));
+            return (
+                dot(scattered.direction(), rec.normal) > 0.0,
+                ScatterRecord::new_specular(scattered, atten),
+            );
+        }
+
+        if dot(-unit_dir, rec.normal) <= 0.0 {
+            return (false, ScatterRecord::new());
+        }
+        let pdf = GgxPdf::new(microfacet_frame(&rec.normal), &-unit_dir, self.ggx);
         (
-            dot(scattered.direction(), rec.normal) > 0.0,
-            scattered,
-            atten,
+            true,
+            ScatterRecord::new_pdf(Box::new(pdf), Color::new(1.0, 1.0, 1.0)),
         )
     }
+
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let uvw = microfacet_frame(&rec.normal);
+        let wo = uvw.world_to_local(&-unit_vector(r_in.direction()));
+        let wi = uvw.world_to_local(&unit_vector(scattered.direction()));
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color::new_e();
+        }
+
+        // Reflectance times the cosine at the surface, which cancels the cosine in the denominator
+        let h = unit_vector(wo + wi);
+        self.fresnel.value(dot(wo, h)) * (self.ggx.d(&h) * self.ggx.g2(&wo, &wi) / (4.0 * wo.z()))
+    }
+
+    fn albedo(&self, _u: f64, _v: f64, _p: &Point) -> Color {
+        self.fresnel.value(1.0)
+    }
 }
 
+/// Smooth glass or liquid. An index of refraction varying with wavelength splits light into rainbows in spectral
+/// renders
 pub struct Dialectric {
-    pub ref_idx: f64,
+    pub ior: Ior,
 }
 
 impl Dialectric {
     pub fn new(ri: f64) -> Dialectric {
-        Dialectric { ref_idx: ri }
+        Dialectric {
+            ior: Ior::Constant(ri),
+        }
+    }
+
+    pub fn new_ior(ior: Ior) -> Dialectric {
+        Dialectric { ior }
     }
 
     fn schlick(cos: f64, ref_idx: f64) -> f64 {
@@ -115,12 +325,13 @@ impl Dialectric {
 }
 
 impl Material for Dialectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Ray, Color) {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (bool, ScatterRecord) {
         let aten = Color::new(1.0, 1.0, 1.0);
+        let ref_idx = self.ior.at(r_in.wavelength());
         let etai_over_etat: f64 = if rec.front_face {
-            1.0 / self.ref_idx
+            1.0 / ref_idx
         } else {
-            self.ref_idx
+            ref_idx
         };
 
         let unit_dir = unit_vector(r_in.direction());
@@ -131,20 +342,123 @@ impl Material for Dialectric {
             // Must reflect
             let refl = reflect(&unit_dir, &rec.normal);
             let scattered = Ray::new(&rec.p, &refl, r_in.time());
-            return (true, scattered, aten);
+            return (true, ScatterRecord::new_specular(scattered, aten));
         }
         // Can refract
         let reflect_prob = Dialectric::schlick(cos_theta, etai_over_etat);
-        if crate::util::random_double() < reflect_prob {
+        if random_double() < reflect_prob {
             let refl = reflect(&unit_dir, &rec.normal);
             let scattered = Ray::new(&rec.p, &refl, r_in.time());
-            return (true, scattered, aten);
+            return (true, ScatterRecord::new_specular(scattered, aten));
         }
 
         let refr = refract(&unit_dir, &rec.normal, etai_over_etat);
         let scattered = Ray::new(&rec.p, &refr, r_in.time());
 
-        (true, scattered, aten)
+        (true, ScatterRecord::new_specular(scattered, aten))
+    }
+
+    fn albedo(&self, _u: f64, _v: f64, _p: &Point) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.ior.is_dispersive()
+    }
+}
+
+/**
+ * Glass or liquid with a GGX microfacet surface, absorbing light as it passes through.
+ *
+ * Roughness is GGX's alpha as for Metal, from 0 for a smooth surface like Dialectric's to frosted glass that blurs
+ * what's behind it. Light travelling inside keeps exp(-absorption * distance) of each color, following the
+ * Beer-Lambert law, so thicker parts are more deeply tinted. This is applied as a ray leaves through the surface, so
+ * objects must be closed.
+ */
+pub struct RoughDielectric {
+    pub ior: Ior,
+    pub ggx: Ggx,
+    pub absorption: Color,
+}
+
+impl RoughDielectric {
+    pub fn new(ior: Ior, roughness_u: f64, roughness_v: f64, absorption: Color) -> RoughDielectric {
+        RoughDielectric {
+            ior,
+            ggx: Ggx {
+                alpha_x: clamp(roughness_u, 0.0, 1.0),
+                alpha_y: clamp(roughness_v, 0.0, 1.0),
+            },
+            absorption,
+        }
+    }
+
+    fn is_smooth(&self) -> bool {
+        self.ggx.alpha_x < MIN_ROUGHNESS && self.ggx.alpha_y < MIN_ROUGHNESS
+    }
+
+    /// Refractive index behind the surface relative to the side ray r_in comes from, at its wavelength
+    fn eta(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        let ref_idx = self.ior.at(r_in.wavelength());
+        if rec.front_face {
+            ref_idx
+        } else {
+            1.0 / ref_idx
+        }
+    }
+
+    /// Share of the light surviving the ray's path to the surface, which is inside if it hit the back face
+    fn transmittance(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        if rec.front_face {
+            return Color::new(1.0, 1.0, 1.0);
+        }
+        let distance = rec.t * r_in.direction().length();
+        Color::new(
+            f64::exp(-self.absorption.x() * distance),
+            f64::exp(-self.absorption.y() * distance),
+            f64::exp(-self.absorption.z() * distance),
+        )
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (bool, ScatterRecord) {
+        let atten = self.transmittance(r_in, rec);
+        let eta = self.eta(r_in, rec);
+        let unit_dir = unit_vector(r_in.direction());
+
+        if self.is_smooth() {
+            let cos = f64::min(dot(-unit_dir, rec.normal), 1.0);
+            let dir = if random_double() < fresnel_dielectric(cos, eta) {
+                reflect(&unit_dir, &rec.normal)
+            } else {
+                refract(&unit_dir, &rec.normal, 1.0 / eta)
+            };
+            let scattered = Ray::new(&rec.p, &dir, r_in.time());
+            return (true, ScatterRecord::new_specular(scattered, atten));
+        }
+
+        if dot(-unit_dir, rec.normal) <= 0.0 {
+            return (false, ScatterRecord::new());
+        }
+        let pdf = RoughDielectricPdf::new(microfacet_frame(&rec.normal), &-unit_dir, self.ggx, eta);
+        (true, ScatterRecord::new_pdf(Box::new(pdf), atten))
+    }
+
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let uvw = microfacet_frame(&rec.normal);
+        let wo = uvw.world_to_local(&-unit_vector(r_in.direction()));
+        let wi = uvw.world_to_local(&unit_vector(scattered.direction()));
+        let (s, _) = self.ggx.dielectric(&wo, &wi, self.eta(r_in, rec));
+        Color::new(s, s, s)
+    }
+
+    fn albedo(&self, _u: f64, _v: f64, _p: &Point) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.ior.is_dispersive()
     }
 }
 
@@ -165,17 +479,17 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> (bool, Ray, Color) {
-        (
-            false,
-            Ray::new(&Point::new_e(), &Vec3::new_e(), 0.0),
-            Color::new_e(),
-        )
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> (bool, ScatterRecord) {
+        (false, ScatterRecord::new())
     }
 
     fn emitted(&self, u: f64, v: f64, p: &Point) -> Color {
         self.emit.value(u, v, p)
     }
+
+    fn albedo(&self, u: f64, v: f64, p: &Point) -> Color {
+        self.emit.value(u, v, p)
+    }
 }
 
 pub struct Isotropic {
@@ -195,9 +509,19 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Ray, Color) {
-        let scattered = Ray::new(&rec.p, &random_in_unit_sphere(), r_in.time());
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> (bool, ScatterRecord) {
         let atten = self.albedo.value(rec.u, rec.v, &rec.p);
-        (true, scattered, atten)
+        (
+            true,
+            ScatterRecord::new_pdf(Box::new(SpherePdf::new()), atten),
+        )
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn albedo(&self, u: f64, v: f64, p: &Point) -> Color {
+        self.albedo.value(u, v, p)
     }
 }
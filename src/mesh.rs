@@ -0,0 +1,401 @@
+//! Wavefront OBJ loading, producing a BVH of triangles
+
+use crate::{
+    bvh::{self, BvhMethod},
+    hittable::Hittable,
+    hittable_list::HittableList,
+    materials::*,
+    texture::*,
+    triangle::Triangle,
+    vec3::*,
+};
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum MeshErr {
+    LoadError { err: tobj::LoadError },
+    MtlLoadError { err: tobj::LoadError },
+    InvalidArgs { err: String },
+}
+
+impl std::fmt::Display for MeshErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeshErr::LoadError { err } => write!(f, "{}", err),
+            MeshErr::MtlLoadError { err } => write!(f, "could not load materials: {}", err),
+            MeshErr::InvalidArgs { err } => write!(f, "{}", err),
+        }
+    }
+}
+
+fn color_of(c: [f32; 3]) -> Color {
+    Color::new(c[0] as f64, c[1] as f64, c[2] as f64)
+}
+
+/// Parses an `r g b` MTL value that tobj does not understand itself, such as Ke
+fn parse_color(s: &str) -> Option<Color> {
+    let c: Vec<f64> = s
+        .split_whitespace()
+        .filter_map(|x| x.parse::<f64>().ok())
+        .collect();
+    match c.len() {
+        1 => Some(Color::new(c[0], c[0], c[0])),
+        3 => Some(Color::new(c[0], c[1], c[2])),
+        _ => None,
+    }
+}
+
+/**
+ * Maps an MTL material onto the closest material this raytracer has.
+ *
+ * Emissive (Ke) materials become DiffuseLight, transparent ones (dissolve below 1, or a refraction illum model)
+ * become Dialectric with the Ni index of refraction, mirror illum models become Metal with Ks as the albedo and
+ * the fuzz derived from Ns, and anything else becomes Lambertian using map_Kd if present, otherwise Kd.
+ */
+fn convert_material(m: &tobj::Material, dir: &Path) -> Arc<dyn Material + Sync + Send> {
+    if let Some(ke) = m.unknown_param.get("Ke").and_then(|s| parse_color(s)) {
+        if ke.length_squared() > 0.0 {
+            return Arc::new(DiffuseLight::new(ke));
+        }
+    }
+
+    let illum = m.illumination_model.unwrap_or(2);
+
+    if m.dissolve < 1.0 || matches!(illum, 4 | 6 | 7 | 9) {
+        let ir = if m.optical_density > 0.0 {
+            m.optical_density as f64
+        } else {
+            1.5
+        };
+        return Arc::new(Dialectric::new(ir));
+    }
+
+    if matches!(illum, 3 | 5 | 8) {
+        let fuzz = f64::sqrt(2.0 / (m.shininess as f64 + 2.0));
+        return Arc::new(Metal::new(color_of(m.specular), fuzz));
+    }
+
+    if !m.diffuse_texture.is_empty() {
+        let path = dir.join(&m.diffuse_texture);
+        let txtr: Arc<dyn Texture + Sync + Send> =
+            Arc::new(ImageTexture::new(&path.to_string_lossy()));
+        return Arc::new(Lambertian::new_txtr(&txtr));
+    }
+
+    Arc::new(Lambertian::new(color_of(m.diffuse)))
+}
+
+/**
+ * Loads an OBJ file into a list of triangles, triangulating any larger polygons.
+ *
+ * Faces use the material from the OBJ's MTL library where one is assigned, and default_mat otherwise. An MTL
+ * library named by the OBJ that cannot be read is an error. Vertex normals give smooth shading, and texture coordinates are used for u, v.
+ */
+pub fn load_obj_list(
+    fname: &str,
+    default_mat: Arc<dyn Material + Sync + Send>,
+) -> Result<HittableList, MeshErr> {
+    let opts = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ignore_points: true,
+        ignore_lines: true,
+    };
+
+    let (models, mtls) = match tobj::load_obj(fname, &opts) {
+        Ok(r) => r,
+        Err(e) => return Err(MeshErr::LoadError { err: e }),
+    };
+
+    let dir = Path::new(fname).parent().unwrap_or_else(|| Path::new(""));
+
+    let mats: Vec<Arc<dyn Material + Sync + Send>> = match mtls {
+        Ok(m) => m.iter().map(|m| convert_material(m, dir)).collect(),
+        Err(e) => return Err(MeshErr::MtlLoadError { err: e }),
+    };
+
+    let mut list = HittableList {
+        objects: Vec::new(),
+    };
+
+    for model in &models {
+        let mesh = &model.mesh;
+        let mat = match mesh.material_id.and_then(|i| mats.get(i)) {
+            Some(m) => Arc::clone(m),
+            None => Arc::clone(&default_mat),
+        };
+
+        let pos = |i: usize| {
+            Point::new(
+                mesh.positions[3 * i] as f64,
+                mesh.positions[3 * i + 1] as f64,
+                mesh.positions[3 * i + 2] as f64,
+            )
+        };
+        let norm = |i: usize| {
+            Vec3::new(
+                mesh.normals[3 * i] as f64,
+                mesh.normals[3 * i + 1] as f64,
+                mesh.normals[3 * i + 2] as f64,
+            )
+        };
+        let uv = |i: usize| {
+            (
+                mesh.texcoords[2 * i] as f64,
+                mesh.texcoords[2 * i + 1] as f64,
+            )
+        };
+
+        let has_normals = !mesh.normals.is_empty();
+        let has_uvs = !mesh.texcoords.is_empty();
+
+        for face in mesh.indices.chunks_exact(3) {
+            let (a, b, c) = (face[0] as usize, face[1] as usize, face[2] as usize);
+
+            let normals = if has_normals {
+                Some([norm(a), norm(b), norm(c)])
+            } else {
+                None
+            };
+            let uvs = if has_uvs {
+                Some([uv(a), uv(b), uv(c)])
+            } else {
+                None
+            };
+
+            list.add(Arc::new(Triangle::new_full(
+                [pos(a), pos(b), pos(c)],
+                normals,
+                uvs,
+                Arc::clone(&mat),
+            )));
+        }
+    }
+
+    if list.objects.is_empty() {
+        return Err(MeshErr::InvalidArgs {
+            err: format!("'{}' contains no faces", fname),
+        });
+    }
+
+    Ok(list)
+}
+
+/// Loads an OBJ file as with load_obj_list, wrapped in a BVH so it can be used as a single Hittable
+pub fn load_obj(
+    fname: &str,
+    default_mat: Arc<dyn Material + Sync + Send>,
+    time0: f64,
+    time1: f64,
+    method: BvhMethod,
+) -> Result<Arc<dyn Hittable + Sync + Send>, MeshErr> {
+    let mut list = load_obj_list(fname, default_mat)?;
+    Ok(bvh::build(&mut list, time0, time1, method))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::HitRecord, ray::Ray, util::INFINITY};
+
+    const MTL: &str = "\
+newmtl light
+Kd 0 0 0
+Ke 4 4 4
+
+newmtl glass
+Kd 1 1 1
+d 0.5
+Ni 1.5
+
+newmtl mirror
+illum 3
+Kd 0.2 0.2 0.2
+Ks 0.9 0.8 0.7
+Ns 1000
+
+newmtl red
+Kd 0.8 0.1 0.1
+";
+
+    /// Faces side by side along x in the z = 0 plane, facing +z, the first with no material
+    const OBJ: &str = "\
+mtllib scene.mtl
+v 0 0 0
+v 1 0 0
+v 0 1 0
+f 1 2 3
+
+usemtl light
+v 2 0 0
+v 3 0 0
+v 2 1 0
+f 4 5 6
+
+usemtl glass
+v 4 0 0
+v 5 0 0
+v 5 1 0
+v 4 1 0
+f 7 8 9 10
+
+usemtl mirror
+v 6 0 0
+v 7 0 0
+v 7.5 0.5 0
+v 7 1 0
+v 6 1 0
+f 11 12 13 14 15
+
+usemtl red
+v 8 0 0
+v 9 0 0
+v 8 1 0
+f 16 17 18
+";
+
+    /// Writes the given files to a fresh directory, returning the path of the first
+    fn write(name: &str, files: &[(&str, &str)]) -> String {
+        let dir =
+            std::env::temp_dir().join(format!("rust_raytracer_{}_{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (fname, contents) in files {
+            std::fs::write(dir.join(fname), contents).unwrap();
+        }
+        dir.join(files[0].0).to_string_lossy().into_owned()
+    }
+
+    fn grey() -> Arc<dyn Material + Sync + Send> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    /// Hit looking straight down on the point (x, y) of the z = 0 plane
+    fn hit(list: &HittableList, x: f64, y: f64) -> Option<HitRecord> {
+        let r = Ray::new(&Point::new(x, y, 5.0), &Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let mut rec = HitRecord::new();
+        if list.hit(&r, 0.001, INFINITY, &mut rec) {
+            Some(rec)
+        } else {
+            None
+        }
+    }
+
+    /// MTL values are single precision, so come back only close to what was written
+    fn close(a: Color, b: Color) -> bool {
+        (a - b).length() < 1e-6
+    }
+
+    fn is_specular(rec: &HitRecord) -> bool {
+        let r = Ray::new(
+            &(rec.p + Vec3::new(0.0, 0.0, 1.0)),
+            &Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        rec.mat_ptr.scatter(&r, rec).1.is_specular
+    }
+
+    #[test]
+    fn mtl_materials_map_onto_the_closest_material() {
+        let default_mat = grey();
+        let fname = write("mtl_mapping", &[("scene.obj", OBJ), ("scene.mtl", MTL)]);
+        let list = load_obj_list(&fname, Arc::clone(&default_mat)).unwrap();
+        let origin = Point::new_e();
+
+        let plain = hit(&list, 0.2, 0.2).unwrap();
+        assert!(std::ptr::eq(
+            Arc::as_ptr(&plain.mat_ptr) as *const (),
+            Arc::as_ptr(&default_mat) as *const ()
+        ));
+
+        let light = hit(&list, 2.2, 0.2).unwrap();
+        assert!(close(
+            light.mat_ptr.emitted(0.0, 0.0, &origin),
+            Color::new(4.0, 4.0, 4.0)
+        ));
+
+        let glass = hit(&list, 4.5, 0.5).unwrap();
+        assert!(is_specular(&glass));
+        assert!(close(
+            glass.mat_ptr.albedo(0.0, 0.0, &origin),
+            Color::new(1.0, 1.0, 1.0)
+        ));
+        assert!(close(
+            glass.mat_ptr.emitted(0.0, 0.0, &origin),
+            Color::new(0.0, 0.0, 0.0)
+        ));
+
+        // A metal, so taking its color from Ks rather than Kd
+        let mirror = hit(&list, 6.5, 0.5).unwrap();
+        assert!(close(
+            mirror.mat_ptr.albedo(0.0, 0.0, &origin),
+            Color::new(0.9, 0.8, 0.7)
+        ));
+
+        let red = hit(&list, 8.2, 0.2).unwrap();
+        assert!(!is_specular(&red));
+        assert!(close(
+            red.mat_ptr.albedo(0.0, 0.0, &origin),
+            Color::new(0.8, 0.1, 0.1)
+        ));
+    }
+
+    #[test]
+    fn polygons_are_split_into_triangles() {
+        let fname = write("triangulate", &[("scene.obj", OBJ), ("scene.mtl", MTL)]);
+        let list = load_obj_list(&fname, grey()).unwrap();
+
+        // The three triangles as they are, two for the quad and three for the pentagon
+        assert_eq!(list.objects.len(), 3 + 2 + 3);
+
+        // Together they cover each polygon, and nothing outside them
+        for (x, y) in [(4.05, 0.05), (4.95, 0.95), (4.05, 0.95), (4.95, 0.05)] {
+            assert!(hit(&list, x, y).is_some(), "quad missing at ({}, {})", x, y);
+        }
+        for (x, y) in [
+            (6.05, 0.05),
+            (6.05, 0.95),
+            (7.4, 0.5),
+            (7.05, 0.1),
+            (7.05, 0.9),
+        ] {
+            assert!(
+                hit(&list, x, y).is_some(),
+                "pentagon missing at ({}, {})",
+                x,
+                y
+            );
+        }
+        for (x, y) in [(0.9, 0.9), (5.5, 0.5), (7.4, 0.05), (7.4, 0.95)] {
+            assert!(
+                hit(&list, x, y).is_none(),
+                "unexpected hit at ({}, {})",
+                x,
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn missing_mtl_library_is_an_error() {
+        let fname = write("missing_mtl", &[("scene.obj", OBJ)]);
+        let e = load_obj_list(&fname, grey()).err().unwrap();
+        assert!(matches!(e, MeshErr::MtlLoadError { .. }), "{}", e);
+        assert!(e.to_string().starts_with("could not load materials"));
+
+        // Without an MTL library every face uses the default material
+        let fname = write(
+            "no_mtl",
+            &[("scene.obj", &OBJ.replace("mtllib scene.mtl\n", ""))],
+        );
+        let default_mat = grey();
+        let list = load_obj_list(&fname, Arc::clone(&default_mat)).unwrap();
+        for x in [0.2, 2.2, 4.2, 6.2, 8.2] {
+            let rec = hit(&list, x, 0.2).unwrap();
+            assert!(std::ptr::eq(
+                Arc::as_ptr(&rec.mat_ptr) as *const (),
+                Arc::as_ptr(&default_mat) as *const ()
+            ));
+        }
+    }
+}
@@ -0,0 +1,431 @@
+//! GGX (Trowbridge-Reitz) microfacet distribution and the Fresnel reflectance of conductors and dielectrics
+//!
+//! Directions are in a local frame with the surface normal along +z, and the first and second roughnesses along x
+//! and y. Vectors are unit length and point away from the surface.
+
+use crate::{util::*, vec3::*};
+
+/// Distribution of the normals of a surface's microscopic facets, with roughness alpha along x and y
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+    /// Density of facets with normal h, per unit area of the surface and solid angle of h
+    pub fn d(&self, h: &Vec3) -> f64 {
+        if h.z() <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = (h.x() / self.alpha_x, h.y() / self.alpha_y);
+        let t = x * x + y * y + h.z() * h.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * t * t)
+    }
+
+    /// Smith's Lambda, from which the masking of facets seen from w follows
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let (x, y) = (self.alpha_x * w.x(), self.alpha_y * w.y());
+        let tan2 = (x * x + y * y) / (w.z() * w.z());
+        0.5 * (f64::sqrt(1.0 + tan2) - 1.0)
+    }
+
+    /// Fraction of facets facing w that are visible from w
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        if w.z() <= 0.0 {
+            return 0.0;
+        }
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of facets visible from both wo and wi, allowing for facets high enough to be seen from one being
+    /// likely seen from the other
+    pub fn g2(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /**
+     * Random facet normal among those visible from wo, after Heitz's "Sampling the GGX Distribution of Visible
+     * Normals".
+     *
+     * Stretching the view so the surface is a hemisphere of roughness 1, the visible normals are the projection of a
+     * disk, with the half of it hidden behind the hemisphere folded over.
+     */
+    pub fn sample_visible(&self, wo: &Vec3) -> Vec3 {
+        let vh = unit_vector(Vec3::new(
+            self.alpha_x * wo.x(),
+            self.alpha_y * wo.y(),
+            wo.z(),
+        ));
+        let len_sq = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if len_sq > 0.0 {
+            Vec3::new(-vh.y(), vh.x(), 0.0) / len_sq.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = cross(vh, t1);
+
+        let r = f64::sqrt(random_double());
+        let phi = 2.0 * PI * random_double();
+        let p1 = r * f64::cos(phi);
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * f64::sqrt(1.0 - p1 * p1) + s * r * f64::sin(phi);
+
+        let nh = p1 * t1 + p2 * t2 + f64::sqrt(f64::max(0.0, 1.0 - p1 * p1 - p2 * p2)) * vh;
+        unit_vector(Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            f64::max(1e-6, nh.z()),
+        ))
+    }
+
+    /// Density of sample_visible's facet normal reflecting wo into wi, with respect to the solid angle of wi
+    pub fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let h = unit_vector(*wo + *wi);
+        self.g1(wo) * self.d(&h) / (4.0 * wo.z())
+    }
+}
+
+/**
+ * Fraction of light reflected by a conductor with complex refractive index eta + ik, for light meeting it at an
+ * angle with cosine cos.
+ *
+ * The exact Fresnel equations, averaging the two polarizations, as in PBRT's FrConductor.
+ */
+pub fn fresnel_conductor(cos: f64, eta: f64, k: f64) -> f64 {
+    let cos = clamp(cos, 0.0, 1.0);
+    let cos2 = cos * cos;
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = f64::sqrt(t0 * t0 + 4.0 * eta2 * k2);
+    let t1 = a2_plus_b2 + cos2;
+    let a = f64::sqrt(f64::max(0.0, 0.5 * (a2_plus_b2 + t0)));
+    let t2 = 2.0 * cos * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
+
+/**
+ * Fraction of light reflected at a smooth interface into a medium of relative refractive index eta, for light
+ * meeting it at an angle with cosine cos from the side it comes from. Total internal reflection gives 1.
+ */
+pub fn fresnel_dielectric(cos: f64, eta: f64) -> f64 {
+    let cos = clamp(cos, 0.0, 1.0);
+    let sin2_t = (1.0 - cos * cos) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_t = f64::sqrt(1.0 - sin2_t);
+    let r_parallel = (eta * cos - cos_t) / (eta * cos + cos_t);
+    let r_perpendicular = (cos - eta * cos_t) / (cos + eta * cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+/**
+ * Rough interface between two dielectrics, after Walter et al.'s "Microfacet Models for Refraction through Rough
+ * Surfaces".
+ *
+ * wo is on the +z side, and eta is the refractive index of the -z side relative to it. Light is reflected or
+ * refracted by each facet as by a smooth interface, so a facet normal sampled for wo reflects with the Fresnel
+ * reflectance's probability, and otherwise refracts.
+ */
+impl Ggx {
+    /// Random direction scattered from wo, reflected or refracted by a facet visible from wo
+    pub fn sample_dielectric(&self, wo: &Vec3, eta: f64) -> Vec3 {
+        let h = self.sample_visible(wo);
+        let cos = dot(*wo, h);
+        if random_double() < fresnel_dielectric(cos, eta) {
+            return 2.0 * cos * h - *wo;
+        }
+
+        // Total internal reflection always reflects above, so the refracted direction exists here
+        let sin2_t = (1.0 - cos * cos) / (eta * eta);
+        let cos_t = f64::sqrt(f64::max(0.0, 1.0 - sin2_t));
+        -*wo / eta + (cos / eta - cos_t) * h
+    }
+
+    /**
+     * Scattering from wo into wi, as the share of light arriving along wi leaving along wo times the cosine at wi,
+     * and the density of sample_dielectric choosing wi.
+     *
+     * Refraction doesn't scale radiance by the square of eta, matching the smooth Dialectric. A sampled facet may
+     * reflect wo below the surface, or refract it above, where it scatters no light, so the density counts both the
+     * facet reflecting wo into wi and the one refracting it, whichever side wi is on.
+     */
+    pub fn dielectric(&self, wo: &Vec3, wi: &Vec3, eta: f64) -> (f64, f64) {
+        if wo.z() <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let g2 = 1.0 / (1.0 + self.lambda(wo) + self.lambda(wi));
+        // Density of facet normal h among those visible from wo
+        let visible = |h: &Vec3| self.g1(wo) * dot(*wo, *h) * self.d(h) / wo.z();
+        let (mut scattering, mut pdf) = (0.0, 0.0);
+
+        let h = *wo + *wi;
+        if h.length_squared() > 0.0 {
+            let h = unit_vector(h);
+            let (cos_o, cos_i) = (dot(*wo, h), dot(*wi, h));
+            if h.z() > 0.0 && cos_o > 0.0 && cos_i > 0.0 {
+                let f = fresnel_dielectric(cos_o, eta);
+                pdf += f * visible(&h) / (4.0 * cos_o);
+                if wi.z() > 0.0 {
+                    scattering = f * self.d(&h) * g2 / (4.0 * wo.z());
+                }
+            }
+        }
+
+        let h = -(*wo + eta * *wi);
+        if h.length_squared() > 0.0 {
+            let h = unit_vector(h);
+            let h = if h.z() < 0.0 { -h } else { h };
+            let (cos_o, cos_i) = (dot(*wo, h), dot(*wi, h));
+            if cos_o > 0.0 && cos_i < 0.0 {
+                let f = fresnel_dielectric(cos_o, eta);
+                let denom = cos_o + eta * cos_i;
+                let jacobian = eta * eta * -cos_i / (denom * denom);
+                pdf += (1.0 - f) * visible(&h) * jacobian;
+                if wi.z() < 0.0 {
+                    scattering = (1.0 - f) * self.d(&h) * g2 * cos_o * jacobian / wo.z();
+                }
+            }
+        }
+
+        (scattering, pdf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Estimates the integral of pdf over every direction, which is 1 for a density of directions that always exist.
+    /// Directions are jittered over a grid of even z and azimuth, which have equal solid angle, to keep the estimate
+    /// of peaked densities steady.
+    fn integrate(pdf: impl Fn(&Vec3) -> f64) -> f64 {
+        const N: u32 = 500;
+        seed_rng(1);
+        let mut sum = 0.0;
+        for i in 0..N {
+            for j in 0..N {
+                let z = 2.0 * (i as f64 + random_double()) / N as f64 - 1.0;
+                let phi = 2.0 * PI * (j as f64 + random_double()) / N as f64;
+                let r = f64::sqrt(f64::max(0.0, 1.0 - z * z));
+                sum += pdf(&Vec3::new(r * phi.cos(), r * phi.sin(), z));
+            }
+        }
+        4.0 * PI * sum / (N * N) as f64
+    }
+
+    fn reflect(wo: &Vec3, h: &Vec3) -> Vec3 {
+        2.0 * dot(*wo, *h) * *h - *wo
+    }
+
+    #[test]
+    fn visible_normal_pdf_integrates_to_at_most_one() {
+        for (alpha_x, alpha_y) in [(0.3, 0.3), (0.6, 0.6), (0.2, 0.7)] {
+            let ggx = Ggx { alpha_x, alpha_y };
+            for wo in [
+                Vec3::new(0.0, 0.0, 1.0),
+                unit_vector(Vec3::new(1.0, 0.5, 1.0)),
+                unit_vector(Vec3::new(0.2, 1.0, 0.3)),
+            ] {
+                // Facets can reflect wo below the surface, where the density has nothing, so the integral falls
+                // short of 1 by the share of sampled reflections that go below
+                let integral = integrate(|wi| ggx.pdf(&wo, wi));
+                let above = (0..100_000)
+                    .filter(|_| reflect(&wo, &ggx.sample_visible(&wo)).z() > 0.0)
+                    .count() as f64
+                    / 100_000.0;
+                assert!(
+                    integral <= 1.02,
+                    "{:?} from {:?} integrates to {}",
+                    ggx,
+                    wo,
+                    integral
+                );
+                assert!(
+                    (integral - above).abs() < 0.02,
+                    "{:?} from {:?} integrates to {}, but {} of samples are above",
+                    ggx,
+                    wo,
+                    integral,
+                    above
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pdf_matches_sampled_reflections() {
+        const Z_BINS: usize = 8;
+        const PHI_BINS: usize = 8;
+        const N: usize = 400_000;
+        let ggx = Ggx {
+            alpha_x: 0.25,
+            alpha_y: 0.5,
+        };
+        let wo = unit_vector(Vec3::new(0.6, 0.3, 1.0));
+        let bin = |w: &Vec3| {
+            let phi = f64::atan2(w.y(), w.x()) + PI;
+            let z = (w.z() * Z_BINS as f64) as usize;
+            let p = (phi / (2.0 * PI) * PHI_BINS as f64) as usize;
+            z.min(Z_BINS - 1) * PHI_BINS + p.min(PHI_BINS - 1)
+        };
+
+        seed_rng(2);
+        let mut histogram = vec![0.0; Z_BINS * PHI_BINS];
+        for _ in 0..N {
+            let wi = reflect(&wo, &ggx.sample_visible(&wo));
+            if wi.z() > 0.0 {
+                histogram[bin(&wi)] += 1.0 / N as f64;
+            }
+        }
+
+        // Bins of even z and azimuth have equal solid angle, so the pdf's average over a bin gives its probability
+        let area = 2.0 * PI / (Z_BINS * PHI_BINS) as f64;
+        for (i, &sampled) in histogram.iter().enumerate() {
+            let (zi, pi) = (i / PHI_BINS, i % PHI_BINS);
+            let mut sum = 0.0;
+            for s in 0..50 {
+                for t in 0..50 {
+                    let z = (zi as f64 + (s as f64 + 0.5) / 50.0) / Z_BINS as f64;
+                    let phi =
+                        (pi as f64 + (t as f64 + 0.5) / 50.0) / PHI_BINS as f64 * 2.0 * PI - PI;
+                    let r = f64::sqrt(1.0 - z * z);
+                    sum += ggx.pdf(&wo, &Vec3::new(r * phi.cos(), r * phi.sin(), z));
+                }
+            }
+            let expected = area * sum / 2500.0;
+            assert!(
+                (sampled - expected).abs() < 0.002 + 0.05 * expected,
+                "bin {} sampled {} but pdf gives {}",
+                i,
+                sampled,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn conductor_reflectance_head_on() {
+        // Gold and copper's indices at red, green and blue, and their reflectance head on as tabulated in Real-Time
+        // Rendering, which measures across each band rather than at one wavelength
+        for (eta, k, expected) in [
+            (
+                [0.143, 0.374, 1.442],
+                [3.983, 2.385, 1.603],
+                [1.000, 0.766, 0.336],
+            ),
+            (
+                [0.200, 0.924, 1.102],
+                [3.912, 2.452, 2.142],
+                [0.955, 0.638, 0.538],
+            ),
+        ] {
+            for c in 0..3 {
+                let r = fresnel_conductor(1.0, eta[c], k[c]);
+                let closed_form = ((eta[c] - 1.0) * (eta[c] - 1.0) + k[c] * k[c])
+                    / ((eta[c] + 1.0) * (eta[c] + 1.0) + k[c] * k[c]);
+                assert!(
+                    (r - closed_form).abs() < 1e-9,
+                    "{} is not {}",
+                    r,
+                    closed_form
+                );
+                assert!(
+                    (r - expected[c]).abs() < 0.04,
+                    "{} is not {}",
+                    r,
+                    expected[c]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn conductor_without_absorption_is_a_dielectric() {
+        for eta in [1.33, 1.5, 2.4] {
+            for i in 0..=10 {
+                let cos = i as f64 / 10.0;
+                let (c, d) = (
+                    fresnel_conductor(cos, eta, 0.0),
+                    fresnel_dielectric(cos, eta),
+                );
+                assert!(
+                    (c - d).abs() < 1e-9,
+                    "{} at {}: {} is not {}",
+                    eta,
+                    cos,
+                    c,
+                    d
+                );
+            }
+        }
+        assert!((fresnel_conductor(0.0, 0.143, 3.983) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn dielectric_reflects_everything_past_the_critical_angle() {
+        // Leaving glass for air, the critical angle has sine 1 / 1.5
+        let eta = 1.0 / 1.5;
+        let critical = f64::sqrt(1.0 - eta * eta);
+        for cos in [0.0, 0.3, 0.6, critical - 1e-6] {
+            assert_eq!(fresnel_dielectric(cos, eta), 1.0, "at cos {}", cos);
+        }
+        assert!(fresnel_dielectric(critical + 0.01, eta) < 1.0);
+    }
+
+    #[test]
+    fn dielectric_reflectance_matches_known_values() {
+        // Head on, the exact equations and Schlick's approximation agree
+        for eta in [1.33, 1.5, 2.4] {
+            let r0 = ((eta - 1.0) / (eta + 1.0)) * ((eta - 1.0) / (eta + 1.0));
+            assert!((fresnel_dielectric(1.0, eta) - r0).abs() < 1e-12);
+            assert!((fresnel_dielectric(1.0, 1.0 / eta) - r0).abs() < 1e-12);
+        }
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-12);
+
+        // Glass at 45 degrees reflects 9.2% of s and 0.85% of p polarized light
+        let r = fresnel_dielectric(f64::sqrt(0.5), 1.5);
+        assert!((r - 0.0502).abs() < 1e-4, "{}", r);
+        assert_eq!(fresnel_dielectric(0.0, 1.5), 1.0);
+    }
+
+    #[test]
+    fn dielectric_pdf_integrates_to_one() {
+        // Every sampled direction is somewhere, reflected or refracted, so none of the density is lost
+        for eta in [1.5, 1.0 / 1.5] {
+            for (alpha_x, alpha_y) in [(0.3, 0.3), (0.6, 0.6), (0.2, 0.5)] {
+                let ggx = Ggx { alpha_x, alpha_y };
+                for wo in [
+                    Vec3::new(0.0, 0.0, 1.0),
+                    unit_vector(Vec3::new(1.0, 0.5, 1.0)),
+                ] {
+                    let integral = integrate(|wi| ggx.dielectric(&wo, wi, eta).1);
+                    assert!(
+                        (integral - 1.0).abs() < 0.02,
+                        "{:?} from {:?} into {} integrates to {}",
+                        ggx,
+                        wo,
+                        eta,
+                        integral
+                    );
+                }
+            }
+        }
+    }
+}
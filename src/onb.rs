@@ -0,0 +1,58 @@
+//Orthonormal basis
+
+use crate::vec3::*;
+
+pub struct ONB {
+    axis: [Vec3; 3],
+}
+
+impl ONB {
+    /// Builds a basis whose w axis is along n. n need not be a unit vector.
+    pub fn build_from_w(n: &Vec3) -> ONB {
+        let w = unit_vector(*n);
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = unit_vector(cross(w, a));
+        let u = cross(w, v);
+
+        ONB { axis: [u, v, w] }
+    }
+
+    /// Builds a basis whose w axis is along n, and whose u axis is as close to t as it can be while square to w
+    pub fn build_from_w_u(n: &Vec3, t: &Vec3) -> ONB {
+        let w = unit_vector(*n);
+        let v = unit_vector(cross(w, *t));
+        let u = cross(v, w);
+
+        ONB { axis: [u, v, w] }
+    }
+
+    /// Converts world coordinates to coordinates in this basis
+    pub fn world_to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(dot(*a, self.u()), dot(*a, self.v()), dot(*a, self.w()))
+    }
+
+    pub fn u(&self) -> Vec3 {
+        self.axis[0]
+    }
+
+    pub fn v(&self) -> Vec3 {
+        self.axis[1]
+    }
+
+    pub fn w(&self) -> Vec3 {
+        self.axis[2]
+    }
+
+    /// Converts coordinates in this basis to world coordinates
+    pub fn local(&self, a: f64, b: f64, c: f64) -> Vec3 {
+        a * self.u() + b * self.v() + c * self.w()
+    }
+
+    pub fn local_v(&self, a: &Vec3) -> Vec3 {
+        self.local(a.x(), a.y(), a.z())
+    }
+}
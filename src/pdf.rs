@@ -0,0 +1,301 @@
+use crate::{
+    environment::Environment, hittable::Hittable, microfacet::Ggx, onb::ONB, util::*, vec3::*,
+};
+
+/// Probability density over directions, used to importance sample scattered rays
+pub trait Pdf {
+    /// Density of generating direction, with respect to solid angle
+    fn value(&self, direction: &Vec3) -> f64;
+
+    fn generate(&self) -> Vec3;
+}
+
+/// Cosine weighted hemisphere about a normal, matching a Lambertian surface
+pub struct CosinePdf {
+    uvw: ONB,
+}
+
+impl CosinePdf {
+    pub fn new(w: &Vec3) -> CosinePdf {
+        CosinePdf {
+            uvw: ONB::build_from_w(w),
+        }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let cosine = dot(unit_vector(*direction), self.uvw.w());
+        if cosine <= 0.0 {
+            0.0
+        } else {
+            cosine / PI
+        }
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.uvw.local_v(&random_cosine_direction())
+    }
+}
+
+/// Uniform over the whole sphere of directions, matching an isotropic medium
+pub struct SpherePdf {}
+
+impl SpherePdf {
+    pub fn new() -> SpherePdf {
+        SpherePdf {}
+    }
+}
+
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn generate(&self) -> Vec3 {
+        random_unit_vector()
+    }
+}
+
+/// Mirror reflections off the GGX facets visible from direction wo, matching a rough metal
+pub struct GgxPdf {
+    uvw: ONB,
+    wo: Vec3,
+    ggx: Ggx,
+}
+
+impl GgxPdf {
+    /// uvw is the surface's frame, with w along its normal, and wo the unit direction away from it towards the viewer
+    pub fn new(uvw: ONB, wo: &Vec3, ggx: Ggx) -> GgxPdf {
+        GgxPdf {
+            wo: uvw.world_to_local(wo),
+            uvw,
+            ggx,
+        }
+    }
+}
+
+impl Pdf for GgxPdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let wi = self.uvw.world_to_local(&unit_vector(*direction));
+        self.ggx.pdf(&self.wo, &wi)
+    }
+
+    fn generate(&self) -> Vec3 {
+        let h = self.ggx.sample_visible(&self.wo);
+        let wi = 2.0 * dot(self.wo, h) * h - self.wo;
+        self.uvw.local_v(&wi)
+    }
+}
+
+/// Reflections and refractions through the GGX facets visible from direction wo, matching a rough dielectric
+pub struct RoughDielectricPdf {
+    uvw: ONB,
+    wo: Vec3,
+    ggx: Ggx,
+    eta: f64,
+}
+
+impl RoughDielectricPdf {
+    /// As GgxPdf, with eta the refractive index behind the surface relative to the side of wo
+    pub fn new(uvw: ONB, wo: &Vec3, ggx: Ggx, eta: f64) -> RoughDielectricPdf {
+        RoughDielectricPdf {
+            wo: uvw.world_to_local(wo),
+            uvw,
+            ggx,
+            eta,
+        }
+    }
+}
+
+impl Pdf for RoughDielectricPdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let wi = self.uvw.world_to_local(&unit_vector(*direction));
+        self.ggx.dielectric(&self.wo, &wi, self.eta).1
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.uvw
+            .local_v(&self.ggx.sample_dielectric(&self.wo, self.eta))
+    }
+}
+
+/// Directions from origin towards a Hittable, using the Hittable's own pdf_value and random
+pub struct HittablePdf<'a> {
+    origin: Point,
+    ptr: &'a dyn Hittable,
+}
+
+impl<'a> HittablePdf<'a> {
+    pub fn new(ptr: &'a dyn Hittable, origin: &Point) -> HittablePdf<'a> {
+        HittablePdf {
+            origin: *origin,
+            ptr,
+        }
+    }
+}
+
+impl<'a> Pdf for HittablePdf<'a> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.ptr.pdf_value(&self.origin, direction)
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.ptr.random(&self.origin)
+    }
+}
+
+/// Even mix of two pdfs
+pub struct MixturePdf<'a> {
+    p: [&'a dyn Pdf; 2],
+}
+
+impl<'a> MixturePdf<'a> {
+    pub fn new(p0: &'a dyn Pdf, p1: &'a dyn Pdf) -> MixturePdf<'a> {
+        MixturePdf { p: [p0, p1] }
+    }
+}
+
+impl<'a> Pdf for MixturePdf<'a> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        0.5 * self.p[0].value(direction) + 0.5 * self.p[1].value(direction)
+    }
+
+    fn generate(&self) -> Vec3 {
+        if random_double() < 0.5 {
+            self.p[0].generate()
+        } else {
+            self.p[1].generate()
+        }
+    }
+}
+
+/// Directions towards the bright parts of an Environment, using the Environment's own pdf_value and random
+pub struct EnvironmentPdf<'a> {
+    ptr: &'a dyn Environment,
+}
+
+impl<'a> EnvironmentPdf<'a> {
+    pub fn new(ptr: &'a dyn Environment) -> EnvironmentPdf<'a> {
+        EnvironmentPdf { ptr }
+    }
+}
+
+impl<'a> Pdf for EnvironmentPdf<'a> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.ptr.pdf_value(direction)
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.ptr.random()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aarect::XZRect, hittable_list::HittableList, materials::Lambertian, sphere::Sphere,
+    };
+    use std::sync::Arc;
+
+    const BINS: usize = 8;
+
+    /// Integral of pdf over each of a grid of bins even in z and azimuth, using a jittered grid of directions
+    fn binned(pdf: impl Fn(&Vec3) -> f64) -> [[f64; BINS]; BINS] {
+        const N: usize = 60 * BINS;
+        seed_rng(1);
+        let mut bins = [[0.0; BINS]; BINS];
+        for i in 0..N {
+            for j in 0..N {
+                let z = 2.0 * (i as f64 + random_double()) / N as f64 - 1.0;
+                let phi = 2.0 * PI * (j as f64 + random_double()) / N as f64;
+                let r = f64::sqrt(f64::max(0.0, 1.0 - z * z));
+                bins[i * BINS / N][j * BINS / N] +=
+                    4.0 * PI * pdf(&Vec3::new(r * phi.cos(), r * phi.sin(), z)) / (N * N) as f64;
+            }
+        }
+        bins
+    }
+
+    /// Share of directions generated by pdf falling in each of the bins used by binned
+    fn sampled(pdf: &dyn Pdf) -> [[f64; BINS]; BINS] {
+        const SAMPLES: usize = 200_000;
+        seed_rng(2);
+        let mut bins = [[0.0; BINS]; BINS];
+        for _ in 0..SAMPLES {
+            let v = unit_vector(pdf.generate());
+            let i = ((0.5 * (v.z() + 1.0) * BINS as f64) as usize).min(BINS - 1);
+            let phi = f64::atan2(v.y(), v.x()).rem_euclid(2.0 * PI);
+            let j = ((phi / (2.0 * PI) * BINS as f64) as usize).min(BINS - 1);
+            bins[i][j] += 1.0 / SAMPLES as f64;
+        }
+        bins
+    }
+
+    fn integral(pdf: &dyn Pdf) -> f64 {
+        binned(|v| pdf.value(v)).iter().flatten().sum()
+    }
+
+    fn assert_matches_generate(pdf: &dyn Pdf) {
+        let expected = binned(|v| pdf.value(v));
+        let got = sampled(pdf);
+        for (e, g) in expected.iter().flatten().zip(got.iter().flatten()) {
+            assert!(
+                (e - g).abs() < 0.003 + 0.03 * e,
+                "{} sampled, {} expected",
+                g,
+                e
+            );
+        }
+    }
+
+    /// A sphere off to one side of the origin and a rectangle above it
+    fn lights() -> HittableList {
+        let mat = Arc::new(Lambertian::new(Color::new(1.0, 1.0, 1.0)));
+        HittableList {
+            objects: vec![
+                Arc::new(Sphere::new(Point::new(0.0, 0.0, -4.0), 1.0, mat.clone())),
+                Arc::new(XZRect::new(-1.0, 1.0, -1.0, 1.0, 3.0, mat)),
+            ],
+        }
+    }
+
+    #[test]
+    fn cosine_pdf_integrates_to_one_and_matches_generate() {
+        for w in [Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, -2.0, 0.5)] {
+            let pdf = CosinePdf::new(&w);
+            assert!((integral(&pdf) - 1.0).abs() < 0.005);
+            assert_matches_generate(&pdf);
+            assert_eq!(pdf.value(&-w), 0.0);
+        }
+    }
+
+    #[test]
+    fn hittable_pdf_integrates_to_one_and_matches_generate() {
+        let lights = lights();
+        for light in lights.objects.iter() {
+            let pdf = HittablePdf::new(light.as_ref(), &Point::new_e());
+            assert!((integral(&pdf) - 1.0).abs() < 0.01);
+            assert_matches_generate(&pdf);
+        }
+
+        let pdf = HittablePdf::new(&lights, &Point::new_e());
+        assert!((integral(&pdf) - 1.0).abs() < 0.01);
+        assert_matches_generate(&pdf);
+        assert_eq!(pdf.value(&Vec3::new(1.0, 0.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn mixture_pdf_integrates_to_one_and_matches_generate() {
+        let lights = lights();
+        let light_pdf = HittablePdf::new(&lights, &Point::new_e());
+        let cosine_pdf = CosinePdf::new(&Vec3::new(0.3, 1.0, -0.2));
+        let pdf = MixturePdf::new(&light_pdf, &cosine_pdf);
+        assert!((integral(&pdf) - 1.0).abs() < 0.01);
+        assert_matches_generate(&pdf);
+
+        let v = Vec3::new(0.0, 1.0, 0.0);
+        assert!((pdf.value(&v) - 0.5 * (light_pdf.value(&v) + cosine_pdf.value(&v))).abs() < 1e-12);
+    }
+}
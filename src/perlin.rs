@@ -76,7 +76,7 @@ fn perlin_generate_perm() -> [i32; POINT_COUNT] {
         *pi = i as i32;
     }
 
-    permute(&mut p, POINT_COUNT as usize);
+    permute(&mut p, POINT_COUNT);
 
     p
 }
@@ -1,8 +1,9 @@
 use crate::color;
 use crate::vec3::Color;
-use image::{ImageBuffer, Rgb};
+use image::codecs::hdr::HdrEncoder;
+use image::{ImageBuffer, ImageFormat, Rgb};
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
 
 arg_enum! {
     /**
@@ -11,12 +12,18 @@ arg_enum! {
      * Ppm: 8 bit per channel PPM file, must be writen strictly in order
      * Rgb8: 8 bit per channel PNG or JPG, depending on file name
      * Rgb16: 16 bit per channel PNG or JPG, depending on file name
+     * Exr: 32 bit float per channel OpenEXR file of linear radiance
+     * Hdr: Radiance RGBE file of linear radiance
+     * Pfm: 32 bit float per channel Portable Float Map of linear radiance
      */
-    #[derive(Debug, PartialEq)]
+    #[derive(Debug, PartialEq, Clone, Copy)]
     pub enum PictureType {
         Ppm,
         Rgb8,
         Rgb16,
+        Exr,
+        Hdr,
+        Pfm,
     }
 }
 
@@ -42,6 +49,15 @@ enum PictureBuf {
     Rgb16 {
         buf: ImageBuffer<Rgb<u16>, Vec<u16>>,
     },
+    Exr {
+        buf: ImageBuffer<Rgb<f32>, Vec<f32>>,
+    },
+    Hdr {
+        buf: ImageBuffer<Rgb<f32>, Vec<f32>>,
+    },
+    Pfm {
+        buf: ImageBuffer<Rgb<f32>, Vec<f32>>,
+    },
 }
 
 pub struct Picture {
@@ -49,6 +65,7 @@ pub struct Picture {
     aspect_ratio: f64,
     samples: u32,
     fname: String,
+    tone: color::ToneSettings,
     img: PictureBuf,
 }
 
@@ -60,6 +77,8 @@ impl Picture {
      * samples_per_pixel must be non-zero
      *
      * If outtype is Rgb8 or Rgb16, fname will determine image output type, either jpg or png.
+     * If outtype is Exr, Hdr or Pfm, the average radiance of each pixel is stored without gamma or clamping, regardless of fname.
+     * tone sets how the other types map radiance to display values, and is ignored by the float types.
      * If outtype is Ppm, will write header of file in constructor, and must be written directly in order, and cannot be backtracked. Writing a pixel or row to a Ppm type Picture is writing to disk directly.
      */
     pub fn new(
@@ -68,6 +87,7 @@ impl Picture {
         samples_per_pixel: u32,
         fname: &str,
         outtype: PictureType,
+        tone: color::ToneSettings,
     ) -> Result<Picture, PictureErr> {
         let height = (width as f64 / aspect_ratio) as u32;
 
@@ -109,6 +129,18 @@ impl Picture {
             PictureType::Rgb16 => PictureBuf::Rgb16 {
                 buf: ImageBuffer::new(width, height),
             },
+
+            PictureType::Exr => PictureBuf::Exr {
+                buf: ImageBuffer::new(width, height),
+            },
+
+            PictureType::Hdr => PictureBuf::Hdr {
+                buf: ImageBuffer::new(width, height),
+            },
+
+            PictureType::Pfm => PictureBuf::Pfm {
+                buf: ImageBuffer::new(width, height),
+            },
         };
 
         Ok(Picture {
@@ -116,6 +148,7 @@ impl Picture {
             aspect_ratio,
             samples: samples_per_pixel,
             fname: fname.to_string(),
+            tone,
             img,
         })
     }
@@ -158,7 +191,7 @@ impl Picture {
                 }
 
                 for p in row {
-                    color::write_color_ppm(file, *p, self.samples);
+                    color::write_color_ppm(file, *p, self.samples, &self.tone);
                 }
 
                 *y += 1;
@@ -167,14 +200,37 @@ impl Picture {
             //Write Rgb8 row
             PictureBuf::Rgb8 { ref mut buf } => {
                 for (col, p) in row.iter().enumerate() {
-                    color::write_pixel_img_8bpc(col as u32, row_num, *p, self.samples, buf);
+                    color::write_pixel_img_8bpc(
+                        col as u32,
+                        row_num,
+                        *p,
+                        self.samples,
+                        &self.tone,
+                        buf,
+                    );
                 }
             }
 
             //Write Rgb16 row
             PictureBuf::Rgb16 { ref mut buf } => {
                 for (col, p) in row.iter().enumerate() {
-                    color::write_pixel_img_16bpc(col as u32, row_num, *p, self.samples, buf);
+                    color::write_pixel_img_16bpc(
+                        col as u32,
+                        row_num,
+                        *p,
+                        self.samples,
+                        &self.tone,
+                        buf,
+                    );
+                }
+            }
+
+            //Write float row
+            PictureBuf::Exr { ref mut buf }
+            | PictureBuf::Hdr { ref mut buf }
+            | PictureBuf::Pfm { ref mut buf } => {
+                for (col, p) in row.iter().enumerate() {
+                    color::write_pixel_img_f32(col as u32, row_num, *p, self.samples, buf);
                 }
             }
         };
@@ -218,7 +274,7 @@ impl Picture {
                     });
                 }
 
-                color::write_color_ppm(file, *pixel, self.samples);
+                color::write_color_ppm(file, *pixel, self.samples, &self.tone);
 
                 *x += 1;
                 if *x == self.width {
@@ -228,11 +284,17 @@ impl Picture {
             }
 
             PictureBuf::Rgb8 { ref mut buf } => {
-                color::write_pixel_img_8bpc(col, row, *pixel, self.samples, buf);
+                color::write_pixel_img_8bpc(col, row, *pixel, self.samples, &self.tone, buf);
             }
 
             PictureBuf::Rgb16 { ref mut buf } => {
-                color::write_pixel_img_16bpc(col, row, *pixel, self.samples, buf);
+                color::write_pixel_img_16bpc(col, row, *pixel, self.samples, &self.tone, buf);
+            }
+
+            PictureBuf::Exr { ref mut buf }
+            | PictureBuf::Hdr { ref mut buf }
+            | PictureBuf::Pfm { ref mut buf } => {
+                color::write_pixel_img_f32(col, row, *pixel, self.samples, buf);
             }
         }
 
@@ -240,7 +302,7 @@ impl Picture {
     }
 
     /**
-     * Saves data to file for Rgb8 and Rgb16 image types. Ppm is actively saving to disk at pixel writing time and this will simply flush the buffers.
+     * Saves data to file for Rgb8, Rgb16 and float image types. Ppm is actively saving to disk at pixel writing time and this will simply flush the buffers.
      */
     pub fn save(&mut self) -> Result<(), PictureErr> {
         match &mut self.img {
@@ -268,6 +330,37 @@ impl Picture {
                     return Err(PictureErr::ImgError { err: e });
                 }
             },
+
+            PictureBuf::Exr { ref mut buf } => {
+                match buf.save_with_format(&self.fname, ImageFormat::OpenExr) {
+                    Ok(_) => {}
+
+                    Err(e) => return Err(PictureErr::ImgError { err: e }),
+                }
+            }
+
+            PictureBuf::Hdr { ref mut buf } => {
+                let file = match File::create(&self.fname) {
+                    Ok(f) => f,
+
+                    Err(e) => return Err(PictureErr::IoError { err: e }),
+                };
+
+                let (w, h) = buf.dimensions();
+                let pixels: Vec<Rgb<f32>> = buf.pixels().copied().collect();
+                match HdrEncoder::new(BufWriter::new(file)).encode(&pixels, w as usize, h as usize)
+                {
+                    Ok(_) => {}
+
+                    Err(e) => return Err(PictureErr::ImgError { err: e }),
+                }
+            }
+
+            PictureBuf::Pfm { ref mut buf } => match write_pfm(&self.fname, buf) {
+                Ok(_) => {}
+
+                Err(e) => return Err(PictureErr::IoError { err: e }),
+            },
         };
 
         Ok(())
@@ -306,3 +399,72 @@ impl Picture {
         }
     }
 }
+
+/**
+ * Writes buf as a little endian Portable Float Map.
+ *
+ * PFM stores rows from the bottom of the image up, the reverse of buf.
+ */
+fn write_pfm(fname: &str, buf: &ImageBuffer<Rgb<f32>, Vec<f32>>) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(fname)?);
+    let (w, h) = buf.dimensions();
+
+    // A negative scale marks the data as little endian
+    write!(file, "PF\n{} {}\n-1.0\n", w, h)?;
+
+    for y in (0..h).rev() {
+        for x in 0..w {
+            for c in buf.get_pixel(x, y).0 {
+                file.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pfm_round_trips_bottom_row_first() {
+        // Three by two, with every channel of every pixel different so any flip or swap shows
+        let (w, h) = (3, 2);
+        let buf = ImageBuffer::from_fn(w, h, |x, y| {
+            let base = (10 * y + x) as f32;
+            Rgb([base + 0.25, -base - 0.5, base * 1000.0 + 0.125])
+        });
+        let fname = std::env::temp_dir()
+            .join(format!(
+                "rust_raytracer_{}_round_trip.pfm",
+                std::process::id()
+            ))
+            .to_str()
+            .unwrap()
+            .to_string();
+        write_pfm(&fname, &buf).unwrap();
+        let bytes = std::fs::read(&fname).unwrap();
+        std::fs::remove_file(&fname).unwrap();
+
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let data = &bytes[header.len()..];
+        assert_eq!(data.len(), (w * h * 3 * 4) as usize);
+
+        let floats: Vec<f32> = data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        for (i, rgb) in floats.chunks_exact(3).enumerate() {
+            let (x, row) = (i as u32 % w, i as u32 / w);
+            // The file's first row is the bottom of the image
+            assert_eq!(
+                rgb,
+                buf.get_pixel(x, h - 1 - row).0,
+                "pixel {} of the file",
+                i
+            );
+        }
+    }
+}
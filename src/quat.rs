@@ -0,0 +1,139 @@
+//! Unit quaternions for rotations that interpolate smoothly
+
+use crate::{mat4::Mat4, util::*, vec3::*};
+
+/// Rotation stored as a unit quaternion, with w the real part
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quat {
+    w: f64,
+    v: Vec3,
+}
+
+impl Quat {
+    pub fn identity() -> Quat {
+        Quat {
+            w: 1.0,
+            v: Vec3::new_e(),
+        }
+    }
+
+    /// Rotation of angle degrees about axis, turning the same way as Mat4::rotate
+    pub fn from_axis_angle(axis: &Vec3, angle: f64) -> Quat {
+        let half = 0.5 * degs_to_rads(angle);
+        Quat {
+            w: f64::cos(half),
+            v: f64::sin(half) * unit_vector(*axis),
+        }
+    }
+
+    fn dot(&self, q: &Quat) -> f64 {
+        self.w * q.w + dot(self.v, q.v)
+    }
+
+    /// Angle in radians turned by the rotation from self to q, taking the shorter way round
+    pub fn angle_to(&self, q: &Quat) -> f64 {
+        2.0 * f64::acos(clamp(self.dot(q).abs(), 0.0, 1.0))
+    }
+
+    /**
+     * Spherical linear interpolation from self at t = 0 to q at t = 1, turning at a constant rate along the shorter
+     * way round.
+     */
+    pub fn slerp(&self, q: &Quat, t: f64) -> Quat {
+        let mut cos = self.dot(q);
+        let mut q = *q;
+        // q and -q are the same rotation, and the one nearer self takes the shorter path
+        if cos < 0.0 {
+            cos = -cos;
+            q = Quat { w: -q.w, v: -q.v };
+        }
+
+        let (a, b) = if cos > 0.9995 {
+            // Nearly parallel, where lerping is accurate and the sine below would divide by almost zero
+            (1.0 - t, t)
+        } else {
+            let theta = f64::acos(cos);
+            let sin = f64::sin(theta);
+            (f64::sin((1.0 - t) * theta) / sin, f64::sin(t * theta) / sin)
+        };
+
+        let w = a * self.w + b * q.w;
+        let v = a * self.v + b * q.v;
+        let len = f64::sqrt(w * w + v.length_squared());
+        Quat {
+            w: w / len,
+            v: v / len,
+        }
+    }
+
+    pub fn to_mat4(self) -> Mat4 {
+        let (w, x, y, z) = (self.w, self.v.x(), self.v.y(), self.v.z());
+        Mat4::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn z_turn(angle: f64) -> Quat {
+        Quat::from_axis_angle(&Vec3::new(0.0, 0.0, 1.0), angle)
+    }
+
+    fn assert_same_rotation(a: &Quat, b: &Quat) {
+        assert!(a.angle_to(b) < 1e-6, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn slerp_starts_and_ends_at_the_keys() {
+        let a = Quat::from_axis_angle(&Vec3::new(1.0, 2.0, 0.5), 30.0);
+        let b = Quat::from_axis_angle(&Vec3::new(-1.0, 0.0, 1.0), 120.0);
+
+        assert_same_rotation(&a.slerp(&b, 0.0), &a);
+        assert_same_rotation(&a.slerp(&b, 1.0), &b);
+        assert_eq!(a.slerp(&b, 0.0), a);
+    }
+
+    #[test]
+    fn slerp_turns_at_a_constant_rate() {
+        let (a, b) = (z_turn(10.0), z_turn(90.0));
+        for t in [0.25, 0.5, 0.75] {
+            assert_same_rotation(&a.slerp(&b, t), &z_turn(10.0 + 80.0 * t));
+        }
+    }
+
+    #[test]
+    fn slerp_takes_the_shorter_way_round() {
+        // 350 degrees one way is 10 the other
+        let halfway = Quat::identity().slerp(&z_turn(350.0), 0.5);
+        assert_same_rotation(&halfway, &z_turn(-5.0));
+    }
+
+    #[test]
+    fn slerp_between_nearly_equal_keys() {
+        let (a, b) = (z_turn(20.0), z_turn(20.5));
+        let halfway = a.slerp(&b, 0.5);
+        assert_same_rotation(&halfway, &z_turn(20.25));
+        assert!((halfway.dot(&halfway) - 1.0).abs() < 1e-12);
+    }
+}
@@ -1,3 +1,4 @@
+use super::spectrum::Wavelengths;
 use super::vec3::{Point, Vec3};
 
 #[derive(Debug, Copy, Clone)]
@@ -5,6 +6,7 @@ pub struct Ray {
     orig: Point,
     dir: Vec3,
     tm: f64,
+    wavelengths: Option<Wavelengths>,
 }
 
 impl Ray {
@@ -13,6 +15,15 @@ impl Ray {
             orig: *origin,
             dir: *direction,
             tm: time,
+            wavelengths: None,
+        }
+    }
+
+    /// The same ray, carrying wavelengths in a spectral render, or none for RGB
+    pub fn with_wavelengths(self, wavelengths: Option<Wavelengths>) -> Ray {
+        Ray {
+            wavelengths,
+            ..self
         }
     }
 
@@ -28,6 +39,15 @@ impl Ray {
         self.tm
     }
 
+    pub fn wavelengths(&self) -> Option<Wavelengths> {
+        self.wavelengths
+    }
+
+    /// Wavelength in nanometers that decides the ray's path where that depends on it, or None when rendering in RGB
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelengths.map(|w| w.hero())
+    }
+
     pub fn at(&self, t: f64) -> Point {
         self.orig + t * self.dir
     }
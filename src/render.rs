@@ -0,0 +1,835 @@
+//! Rendering frames: tracing each sample's path, planning passes over the image and refining it tile by tile
+
+use crate::{
+    accumulator::{Accumulator, RenderKey},
+    animation, aov, camera, color, denoise, environment, hittable, hittable_list, light, pdf,
+    picture::{self, Picture},
+    ray,
+    scene::SceneData,
+    spectrum,
+    util::*,
+    vec3::*,
+};
+use rayon::prelude::*;
+use std::io::{stderr, Write};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub enum RenderErr {
+    PictureError { err: picture::PictureErr },
+    CheckpointMismatch { err: String },
+    AovError { err: exr::error::Error },
+    CameraError { err: String },
+}
+
+impl std::fmt::Display for RenderErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderErr::PictureError { err } => write!(f, "{:?}", err),
+            RenderErr::CheckpointMismatch { err } => write!(f, "{}", err),
+            RenderErr::AovError { err } => write!(f, "{}", err),
+            RenderErr::CameraError { err } => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<picture::PictureErr> for RenderErr {
+    fn from(err: picture::PictureErr) -> Self {
+        RenderErr::PictureError { err }
+    }
+}
+
+/// Radiance arriving along r, following it for up to depth bounces
+pub fn ray_color(
+    r: &ray::Ray,
+    background: &Color,
+    environment: Option<&(dyn environment::Environment + Sync + Send)>,
+    world: &dyn hittable::Hittable,
+    lights: &hittable_list::HittableList,
+    punctual_lights: &[std::sync::Arc<dyn light::Light + Sync + Send>],
+    depth: u32,
+) -> Color {
+    let mut rec = hittable::HitRecord::new();
+
+    // Just return no light if past ray bounce limit
+    if depth == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    // Scene colors are RGB, so a spectral render takes their values at the ray's wavelengths. Each color is
+    // upsampled on its own and the spectra multiplied, as the spectrum of a product of RGB colors is not the product
+    // of their spectra
+    let wavelengths = r.wavelengths();
+    let spectral = |c: Color| match &wavelengths {
+        Some(w) => w.spectrum_of(&c),
+        None => c,
+    };
+
+    // If the ray hits nothing, return the background, or the environment in that direction
+    if !world.hit(r, 0.0001, INFINITY, &mut rec) {
+        return spectral(match environment {
+            Some(env) => env.value(&r.direction()),
+            None => *background,
+        });
+    }
+
+    let (success, srec) = rec.mat_ptr.as_ref().scatter(r, &rec);
+    let emitted = spectral(rec.mat_ptr.as_ref().emitted(rec.u, rec.v, &rec.p));
+
+    // Light scattered by a dispersive material came by a path only the hero wavelength takes, so the others are
+    // dropped the first time the path meets one
+    let dispersive =
+        rec.mat_ptr.is_dispersive() && wavelengths.is_some_and(|w| !w.secondary_terminated());
+    let wavelengths = if dispersive {
+        wavelengths.map(|w| w.terminate_secondary())
+    } else {
+        wavelengths
+    };
+    let hero_only = |c: Color| {
+        if dispersive {
+            spectrum::Wavelengths::hero_only(&c)
+        } else {
+            c
+        }
+    };
+
+    if !success {
+        return emitted;
+    }
+
+    if srec.is_specular {
+        return emitted
+            + hero_only(
+                spectral(srec.attenuation)
+                    * ray_color(
+                        &srec.specular_ray.with_wavelengths(wavelengths),
+                        background,
+                        environment,
+                        world,
+                        lights,
+                        punctual_lights,
+                        depth - 1,
+                    ),
+            );
+    }
+
+    // Sample half of the scattered rays towards the lights and environment, if the scene has either, splitting
+    // those evenly between them when it has both
+    let mat_pdf = srec.pdf_ptr.expect("Non-specular scatter without a pdf");
+    let light_pdf = pdf::HittablePdf::new(lights, &rec.p);
+    let env_pdf = environment.map(|e| pdf::EnvironmentPdf::new(e));
+    let both_pdf;
+    let direct_pdf: Option<&dyn pdf::Pdf> = match (&env_pdf, lights.objects.is_empty()) {
+        (None, true) => None,
+        (None, false) => Some(&light_pdf),
+        (Some(e), true) => Some(e),
+        (Some(e), false) => {
+            both_pdf = pdf::MixturePdf::new(&light_pdf, e);
+            Some(&both_pdf)
+        }
+    };
+    let mixed_pdf;
+    let scatter_pdf: &dyn pdf::Pdf = match direct_pdf {
+        Some(d) => {
+            mixed_pdf = pdf::MixturePdf::new(d, mat_pdf.as_ref());
+            &mixed_pdf
+        }
+        None => mat_pdf.as_ref(),
+    };
+
+    // Point, spot and directional lights can't be hit by scattered rays, so are added by shadow rays
+    let direct = punctual_lights
+        .iter()
+        .filter_map(|l| l.sample(&rec.p))
+        .fold(Color::new_e(), |sum, s| {
+            let shadow = ray::Ray::new(&rec.p, &s.direction, r.time());
+            if world.hit(
+                &shadow,
+                0.0001,
+                s.distance - 0.0001,
+                &mut hittable::HitRecord::new(),
+            ) {
+                return sum;
+            }
+            sum + spectral(srec.attenuation)
+                * spectral(rec.mat_ptr.scattering(r, &rec, &shadow))
+                * spectral(s.irradiance)
+        });
+    let direct = hero_only(direct);
+
+    let scattered =
+        ray::Ray::new(&rec.p, &scatter_pdf.generate(), r.time()).with_wavelengths(wavelengths);
+    let pdf_val = scatter_pdf.value(&scattered.direction());
+
+    if pdf_val <= 0.0 {
+        return emitted + direct;
+    }
+
+    emitted
+        + direct
+        + hero_only(
+            spectral(srec.attenuation)
+                * spectral(rec.mat_ptr.scattering(r, &rec, &scattered))
+                * ray_color(
+                    &scattered,
+                    background,
+                    environment,
+                    world,
+                    lights,
+                    punctual_lights,
+                    depth - 1,
+                )
+                / pdf_val,
+        )
+}
+
+/// How many samples each pixel is rendered with, and when rendering a frame stops
+#[derive(Debug, Clone, Copy)]
+pub struct Sampling {
+    /// Samples per pixel, which an adaptive render spends as a budget across the image
+    pub spp: u32,
+    /// Render in passes that double the samples, saving the image after each
+    pub progressive: bool,
+    /// Relative error an adaptive render refines pixels until, or None to sample every pixel alike
+    pub adaptive: Option<f64>,
+    /// Samples every pixel gets before an adaptive render judges its noise
+    pub min_samples: u32,
+    /// Longest a frame may render for, or None for no limit
+    pub time_limit: Option<Duration>,
+}
+
+/**
+ * Sets the sample count each pixel is rendered up to in the next pass, returning false if there is nothing left to
+ * do. Passes are only planned once the previous one is complete, and the targets are checkpointed, so an
+ * interrupted render picks up where it left off.
+ */
+pub fn plan_pass(acc: &mut Accumulator, sampling: &Sampling) -> bool {
+    let Sampling {
+        spp,
+        progressive,
+        min_samples,
+        ..
+    } = *sampling;
+    let mut any = false;
+
+    let threshold = match sampling.adaptive {
+        Some(t) => t,
+        None => {
+            for i in 0..acc.len() {
+                let count = acc.count(i);
+                let target = if progressive {
+                    u32::min(u32::max(1, count.saturating_mul(2)), spp)
+                } else {
+                    spp
+                };
+                any |= target > count;
+                acc.set_target(i, target);
+            }
+            return any;
+        }
+    };
+
+    let budget = spp as u64 * acc.len() as u64;
+    let used: u64 = (0..acc.len()).map(|i| acc.count(i) as u64).sum();
+    let cap = spp.saturating_mul(8);
+
+    // Unsampled pixels are brought up to the minimum, and noisy ones have their samples doubled
+    let wanted: Vec<u32> = (0..acc.len())
+        .map(|i| {
+            let count = acc.count(i);
+            if count < min_samples {
+                min_samples - count
+            } else if count < cap && acc.relative_error(i) > threshold {
+                u32::min(count, cap - count)
+            } else {
+                0
+            }
+        })
+        .collect();
+
+    let total: u64 = wanted.iter().map(|&w| w as u64).sum();
+    let remaining = budget.saturating_sub(used);
+    if total == 0 || remaining == 0 {
+        return false;
+    }
+
+    // Share out what is left of the budget in proportion to what each pixel wants
+    let scale = f64::min(1.0, remaining as f64 / total as f64);
+    for (i, &w) in wanted.iter().enumerate() {
+        let extra = if w == 0 {
+            0
+        } else {
+            u32::max(1, (w as f64 * scale) as u32)
+        };
+        any |= extra > 0;
+        acc.set_target(i, acc.count(i) + extra);
+    }
+
+    any
+}
+
+/// Everything beyond the camera that is shared by all frames of a render
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    /// Aspect ratio of the whole image, which for a stereo render holds both eyes
+    pub aspect_ratio: f64,
+    pub max_depth: u32,
+    /// Time the shutter closes, having opened at 0
+    pub shutter: f64,
+    pub seed: u64,
+    pub sampling: Sampling,
+    pub outtype: picture::PictureType,
+    pub tone: color::ToneSettings,
+    pub denoise: bool,
+    pub aov_types: Vec<aov::AovType>,
+    /// Seconds between checkpoint saves, with 0 disabling them
+    pub ckpt_interval: u64,
+}
+
+/// Where one frame and its other outputs are saved
+pub struct FrameFiles {
+    pub image: String,
+    pub checkpoint: String,
+    pub sample_map: Option<String>,
+    pub aov_exr: Option<String>,
+}
+
+/// Builds the camera for frame, following the scene's camera path if it has one
+pub fn frame_camera(
+    scene_dat: &SceneData,
+    settings: &RenderSettings,
+    frame: u32,
+) -> Result<Box<dyn camera::Camera + Sync + Send>, RenderErr> {
+    let mut pose = match &scene_dat.camera_path {
+        Some(path) => path.at(frame as f64),
+        None => animation::CameraKey {
+            frame: frame as f64,
+            lookfrom: scene_dat.lookfrom,
+            lookat: scene_dat.lookat,
+            vup: scene_dat.vup,
+            vfov: scene_dat.vfov,
+            aperture: scene_dat.aperture,
+            dist_to_focus: scene_dat.dist_to_focus,
+        },
+    };
+    if let Some(p) = &scene_dat.photographic {
+        pose.aperture = p.aperture(pose.vfov);
+    }
+
+    camera::new_stereo_camera(
+        &scene_dat.projection,
+        &scene_dat.lens,
+        &scene_dat.stereo,
+        &pose,
+        scene_dat.aspect_ratio,
+        settings.width,
+        settings.height,
+        0.0,
+        settings.shutter,
+    )
+    .map_err(|err| RenderErr::CameraError { err })
+}
+
+/**
+ * Renders frame of the world, seeding every pixel's random stream from frame_seed, and saves it to files.
+ *
+ * A resumed render continues from its checkpoint, which must be for the same image size, sample count and seed. If
+ * the time limit is reached, the checkpoint is saved to continue from, and otherwise it is removed.
+ */
+pub fn render_frame(
+    world: &(dyn hittable::Hittable + Sync + Send),
+    scene_dat: &SceneData,
+    settings: &RenderSettings,
+    frame: u32,
+    frame_seed: u64,
+    files: &FrameFiles,
+    resumed: Option<(Accumulator, RenderKey)>,
+) -> Result<(), RenderErr> {
+    let (width, height) = (settings.width, settings.height);
+    let cam = frame_camera(scene_dat, settings, frame)?;
+
+    // The auxiliary buffers don't depend on the samples, so are rendered once for the denoiser and any AOV outputs
+    let aovs = if settings.denoise || !settings.aov_types.is_empty() || files.aov_exr.is_some() {
+        Some(aov::Aovs::render(
+            width,
+            height,
+            cam.as_ref(),
+            world,
+            &scene_dat.background,
+            scene_dat.environment.as_deref(),
+            frame_seed,
+        ))
+    } else {
+        None
+    };
+
+    let key = RenderKey {
+        width,
+        height,
+        samples_per_pixel: settings.sampling.spp,
+        seed: settings.seed,
+    };
+
+    let mut acc = match resumed {
+        Some((acc, ckpt_key)) => {
+            if ckpt_key != key {
+                return Err(RenderErr::CheckpointMismatch {
+                    err: format!(
+                        "Checkpoint '{}' is for a {}x{} render at {} samples per pixel, not {}x{} at {}",
+                        files.checkpoint,
+                        ckpt_key.width,
+                        ckpt_key.height,
+                        ckpt_key.samples_per_pixel,
+                        key.width,
+                        key.height,
+                        key.samples_per_pixel
+                    ),
+                });
+            }
+            acc
+        }
+        None => Accumulator::new(width, height),
+    };
+
+    /*
+     * Sums count samples of the pixel at col and row from the top, starting at sample number first. Also returns the
+     * sum of the samples' squared luminances.
+     */
+    let sample_pixel = |col: u32, row: u32, first: u32, count: u32| -> (Color, f64) {
+        // Each pixel has its own random stream, so results don't depend on which thread renders it, or when
+        let pixel = row as u64 * width as u64 + col as u64;
+        seed_rng(stream_seed(stream_seed(frame_seed, pixel), first as u64));
+
+        let j = height - row - 1;
+        (0..count)
+            .map(|_| {
+                let u = (col as f64 + random_double()) / (width - 1) as f64;
+                let v = (j as f64 + random_double()) / (height - 1) as f64;
+
+                match cam.get_ray(u, v) {
+                    Some(r) => {
+                        // A spectral sample traces random wavelengths, and is turned back into RGB
+                        let wavelengths = scene_dat.spectral.then(spectrum::Wavelengths::sample);
+                        let c = ray_color(
+                            &r.with_wavelengths(wavelengths),
+                            &scene_dat.background,
+                            scene_dat.environment.as_deref(),
+                            world,
+                            &scene_dat.lights,
+                            &scene_dat.punctual_lights,
+                            settings.max_depth,
+                        );
+                        match wavelengths {
+                            Some(w) => w.rgb_of(&c),
+                            None => c,
+                        }
+                    }
+                    None => Color::new_e(),
+                }
+            })
+            .fold((Color::new_e(), 0.0), |(sum, sum_sq), x| {
+                let l = color::luminance(x);
+                (sum + x, sum_sq + l * l)
+            })
+    };
+
+    let save = |acc: &Accumulator| save_image(acc, aovs.as_ref(), settings, files);
+    let out_of_time = render_passes(&mut acc, &sample_pixel, settings, files, &key, &save)?;
+
+    if !settings.sampling.progressive {
+        save(&acc)?;
+    }
+
+    if let Some(aovs) = &aovs {
+        save_aovs(aovs, &acc, settings, files)?;
+    }
+
+    if settings.sampling.adaptive.is_some() {
+        let total: u64 = (0..acc.len()).map(|i| acc.count(i) as u64).sum();
+        eprintln!(
+            "\nAverage samples per pixel: {:.1}",
+            total as f64 / acc.len() as f64
+        );
+    }
+
+    let ckpt_name = &files.checkpoint;
+    if !out_of_time {
+        // Only the checkpoint of an unfinished render is worth keeping
+        if std::path::Path::new(ckpt_name).exists() {
+            if let Err(e) = std::fs::remove_file(ckpt_name) {
+                eprintln!("\nError removing checkpoint '{}': {}", ckpt_name, e);
+            }
+        }
+    } else if settings.ckpt_interval > 0 {
+        match acc.save_checkpoint(ckpt_name, &key) {
+            Ok(_) => eprintln!(
+                "\nTime limit reached, continue with --resume from checkpoint '{}'",
+                ckpt_name
+            ),
+            Err(e) => eprintln!("\nError saving checkpoint '{}': {}", ckpt_name, e),
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Refines acc pass by pass until no pass is left to plan, saving checkpoints as it goes, and progressive images
+ * after each pass. Returns true if the time limit cut the render short.
+ */
+fn render_passes(
+    acc: &mut Accumulator,
+    sample_pixel: &(dyn Fn(u32, u32, u32, u32) -> (Color, f64) + Sync),
+    settings: &RenderSettings,
+    files: &FrameFiles,
+    key: &RenderKey,
+    save: &dyn Fn(&Accumulator) -> Result<(), RenderErr>,
+) -> Result<bool, RenderErr> {
+    let sampling = &settings.sampling;
+
+    // Pixels are only ever added to the accumulator a whole tile at a time, at the end of their pass
+    let tiles = tiles(settings.width, settings.height, TILE_SIZE);
+
+    let err = stderr();
+    let mut errlock = err.lock();
+
+    // Enough tiles to keep every thread busy between checks for a due checkpoint
+    let batch = 4 * rayon::current_num_threads();
+    let start = Instant::now();
+    let mut last_ckpt = Instant::now();
+    let mut out_of_time = false;
+    let mut pass = 0;
+
+    while !out_of_time {
+        // A pass cut short by an interruption is finished before the next is planned
+        let unfinished = |acc: &Accumulator, i: usize| acc.count(i) < acc.target(i);
+        if !(0..acc.len()).any(|i| unfinished(acc, i)) && !plan_pass(acc, sampling) {
+            break;
+        }
+        pass += 1;
+
+        let todo: Vec<&Tile> = tiles
+            .iter()
+            .filter(|t| {
+                t.pixels()
+                    .any(|(col, row)| unfinished(acc, acc.index(col, row)))
+            })
+            .collect();
+        let mut remaining = todo.len();
+
+        for chunk in todo.chunks(batch) {
+            if sampling.progressive || sampling.adaptive.is_some() {
+                write!(errlock, "\rPass {}, tiles remaining: {} ", pass, remaining)
+                    .expect("Fail to write to Err");
+            } else {
+                write!(errlock, "\rTiles remaining: {} ", remaining).expect("Fail to write to Err");
+            }
+            errlock.flush().expect("Fail to flush stderr");
+
+            // The deadline is checked before each tile, so a render out of time stops mid-pass, keeping the tiles
+            // already done and the targets of the rest for a resumed render to finish
+            let past_deadline = || sampling.time_limit.is_some_and(|l| start.elapsed() >= l);
+            let sums: Vec<Option<Vec<(Color, f64)>>> = chunk
+                .par_iter()
+                .map(|t| {
+                    if past_deadline() {
+                        return None;
+                    }
+                    Some(
+                        t.pixels()
+                            .map(|(col, row)| {
+                                let index = acc.index(col, row);
+                                let done = acc.count(index);
+                                sample_pixel(col, row, done, acc.target(index) - done)
+                            })
+                            .collect(),
+                    )
+                })
+                .collect();
+
+            for (t, tile_sums) in chunk.iter().zip(sums) {
+                let tile_sums = match tile_sums {
+                    Some(s) => s,
+                    None => {
+                        out_of_time = true;
+                        continue;
+                    }
+                };
+                for ((col, row), (sum, sum_sq)) in t.pixels().zip(tile_sums) {
+                    let index = acc.index(col, row);
+                    let samples = acc.target(index) - acc.count(index);
+                    acc.add(index, sum, sum_sq, samples);
+                }
+            }
+            remaining -= chunk.len();
+
+            if settings.ckpt_interval > 0
+                && last_ckpt.elapsed() >= Duration::from_secs(settings.ckpt_interval)
+            {
+                if let Err(e) = acc.save_checkpoint(&files.checkpoint, key) {
+                    eprintln!("\nError saving checkpoint '{}': {}", files.checkpoint, e);
+                }
+                last_ckpt = Instant::now();
+            }
+
+            if out_of_time || past_deadline() {
+                out_of_time = true;
+                break;
+            }
+        }
+
+        // Progressive renders give a usable image after every pass
+        if sampling.progressive {
+            save(acc)?;
+        }
+    }
+
+    Ok(out_of_time)
+}
+
+/// Saves the image, denoised if asked to and possible, and the sample map if one is wanted
+fn save_image(
+    acc: &Accumulator,
+    aovs: Option<&aov::Aovs>,
+    settings: &RenderSettings,
+    files: &FrameFiles,
+) -> Result<(), RenderErr> {
+    // Pixels are written as their averages, so to the picture each is a single sample
+    let mut img = Picture::new(
+        settings.width,
+        settings.aspect_ratio,
+        1,
+        &files.image,
+        settings.outtype,
+        settings.tone,
+    )?;
+
+    let pixels: Vec<Color> = match aovs {
+        Some(aovs) if settings.denoise => denoise::denoise(acc, aovs),
+        _ => (0..acc.len()).map(|i| acc.average(i)).collect(),
+    };
+    for r in 0..settings.height {
+        let start = acc.index(0, r);
+        img.write_row(&pixels[start..start + settings.width as usize], r)?;
+    }
+
+    if let Some(fname) = &files.sample_map {
+        if let Err(e) = acc.save_sample_map(fname) {
+            return Err(picture::PictureErr::ImgError { err: e }.into());
+        }
+    }
+
+    Ok(img.save()?)
+}
+
+/// Saves each AOV asked for beside the image, and the layered OpenEXR file if one is wanted
+fn save_aovs(
+    aovs: &aov::Aovs,
+    acc: &Accumulator,
+    settings: &RenderSettings,
+    files: &FrameFiles,
+) -> Result<(), RenderErr> {
+    for t in &settings.aov_types {
+        aovs.save(*t, &files.image, settings.aspect_ratio, settings.outtype)?;
+    }
+
+    if let Some(fname) = &files.aov_exr {
+        let layers = if settings.aov_types.is_empty() {
+            aov::AovType::variants()
+                .iter()
+                .map(|v| v.parse::<aov::AovType>().unwrap())
+                .collect()
+        } else {
+            settings.aov_types.clone()
+        };
+        if let Err(err) = aovs.save_layers(fname, acc, &layers) {
+            return Err(RenderErr::AovError { err });
+        }
+    }
+
+    Ok(())
+}
+
+const TILE_SIZE: u32 = 32;
+
+/// Rectangle of pixels rendered as one unit of work, from the top left inclusive to the bottom right exclusive
+struct Tile {
+    col0: u32,
+    row0: u32,
+    col1: u32,
+    row1: u32,
+}
+
+impl Tile {
+    /// Every (col, row) in the tile, in row order
+    fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (self.row0..self.row1)
+            .flat_map(move |row| (self.col0..self.col1).map(move |col| (col, row)))
+    }
+}
+
+/// Splits a width by height image into tiles of up to size square, in row order from the top left
+fn tiles(width: u32, height: u32, size: u32) -> Vec<Tile> {
+    let mut out = Vec::new();
+    for row0 in (0..height).step_by(size as usize) {
+        for col0 in (0..width).step_by(size as usize) {
+            out.push(Tile {
+                col0,
+                row0,
+                col1: u32::min(col0 + size, width),
+                row1: u32::min(row0 + size, height),
+            });
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampling(spp: u32, progressive: bool, adaptive: Option<f64>) -> Sampling {
+        Sampling {
+            spp,
+            progressive,
+            adaptive,
+            min_samples: 4,
+            time_limit: None,
+        }
+    }
+
+    /// Brings every pixel up to its target with samples of mean luminance 1 and the given standard deviation
+    fn render(acc: &mut Accumulator, deviation: impl Fn(usize) -> f64) {
+        for i in 0..acc.len() {
+            let n = acc.target(i) - acc.count(i);
+            let s = deviation(i);
+            acc.add(
+                i,
+                Color::new(1.0, 1.0, 1.0) * n as f64,
+                n as f64 * (1.0 + s * s),
+                n,
+            );
+        }
+    }
+
+    /// Targets of pixel 0 for each pass planned until the render is done
+    fn passes(
+        acc: &mut Accumulator,
+        sampling: &Sampling,
+        deviation: impl Fn(usize) -> f64,
+    ) -> Vec<u32> {
+        let mut targets = Vec::new();
+        while plan_pass(acc, sampling) {
+            targets.push(acc.target(0));
+            render(acc, &deviation);
+            assert!(targets.len() < 100, "passes never finish");
+        }
+        targets
+    }
+
+    #[test]
+    fn progressive_passes_double_up_to_spp() {
+        for (spp, expected) in [
+            (1, vec![1]),
+            (10, vec![1, 2, 4, 8, 10]),
+            (16, vec![1, 2, 4, 8, 16]),
+            (100, vec![1, 2, 4, 8, 16, 32, 64, 100]),
+        ] {
+            let mut acc = Accumulator::new(4, 3);
+            assert_eq!(
+                passes(&mut acc, &sampling(spp, true, None), |_| 1.0),
+                expected
+            );
+            for i in 0..acc.len() {
+                assert_eq!(acc.count(i), spp);
+            }
+        }
+    }
+
+    #[test]
+    fn single_pass_goes_straight_to_spp() {
+        let mut acc = Accumulator::new(4, 3);
+        assert_eq!(
+            passes(&mut acc, &sampling(10, false, None), |_| 1.0),
+            vec![10]
+        );
+    }
+
+    #[test]
+    fn adaptive_samples_start_at_the_minimum() {
+        let mut acc = Accumulator::new(4, 3);
+        assert!(plan_pass(&mut acc, &sampling(16, false, Some(0.01))));
+        for i in 0..acc.len() {
+            assert_eq!(acc.target(i), 4);
+        }
+    }
+
+    #[test]
+    fn adaptive_budget_goes_to_noise() {
+        // The left half is flat, and the right half noisy
+        let mut acc = Accumulator::new(8, 8);
+        let noisy = |i: usize| i % 8 >= 4;
+        let spp = 16;
+        passes(&mut acc, &sampling(spp, false, Some(0.01)), |i| {
+            if noisy(i) {
+                2.0
+            } else {
+                0.0
+            }
+        });
+
+        let used: u32 = (0..acc.len()).map(|i| acc.count(i)).sum();
+        assert!(used <= spp * acc.len() as u32, "used {} samples", used);
+        for i in 0..acc.len() {
+            if noisy(i) {
+                assert!(acc.count(i) > spp, "noisy pixel {} has {}", i, acc.count(i));
+            } else {
+                assert_eq!(acc.count(i), 4, "flat pixel {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn adaptive_samples_stop_at_eight_times_spp() {
+        // One noisy pixel could take the whole budget, but is capped
+        let mut acc = Accumulator::new(8, 8);
+        let spp = 16;
+        passes(&mut acc, &sampling(spp, false, Some(0.001)), |i| {
+            if i == 0 {
+                2.0
+            } else {
+                0.0
+            }
+        });
+        assert_eq!(acc.count(0), 8 * spp);
+        for i in 1..acc.len() {
+            assert_eq!(acc.count(i), 4);
+        }
+    }
+
+    #[test]
+    fn tiles_cover_every_pixel_once() {
+        for (width, height) in [(64, 32), (70, 33), (5, 3), (32, 1)] {
+            let mut covered = vec![0; (width * height) as usize];
+            for tile in tiles(width, height, 32) {
+                assert!(tile.col0 < tile.col1 && tile.row0 < tile.row1);
+                for (col, row) in tile.pixels() {
+                    covered[(row * width + col) as usize] += 1;
+                }
+            }
+            assert!(
+                covered.iter().all(|&c| c == 1),
+                "{}x{} is not covered once",
+                width,
+                height
+            );
+        }
+
+        // The right and bottom tiles hold what is left over
+        let t = tiles(70, 33, 32);
+        assert_eq!(t.len(), 6);
+        assert_eq!((t[2].col0, t[2].col1), (64, 70));
+        assert_eq!((t[5].row0, t[5].row1), (32, 33));
+    }
+}
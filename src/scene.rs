@@ -1,10 +1,11 @@
 use crate::hittable::*;
+use crate::mat4::Mat4;
 use crate::materials::*;
 use crate::texture::*;
 use crate::*;
 use std::sync::Arc;
 
-fn random_scene() -> hittable_list::HittableList {
+fn random_scene(bvh_method: bvh::BvhMethod) -> hittable_list::HittableList {
     let mut world = hittable_list::HittableList {
         objects: Vec::with_capacity(10),
     };
@@ -94,7 +95,7 @@ fn random_scene() -> hittable_list::HittableList {
     )));
 
     //let mut out = hittable_list::HittableList {objects: Vec::with_capacity(10)};
-    world.add(Arc::new(bvh::BvhNode::new_l(&mut spheres, 0.0, 1.0)));
+    world.add(bvh::build(&mut spheres, 0.0, 1.0, bvh_method));
 
     world
 }
@@ -158,7 +159,7 @@ fn earth() -> hittable_list::HittableList {
     hittable_list::HittableList::new(globe)
 }
 
-fn simple_light() -> hittable_list::HittableList {
+fn simple_light(lights: &mut hittable_list::HittableList) -> hittable_list::HittableList {
     let mut objects = hittable_list::HittableList {
         objects: Vec::with_capacity(10),
     };
@@ -182,20 +183,22 @@ fn simple_light() -> hittable_list::HittableList {
     let difflight: Arc<dyn Material + Sync + Send> =
         Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0)));
     //let grnlight: Arc<dyn Material  + Sync + Send> = Arc::new(DiffuseLight::new(Color::new(0.0, 12.0, 0.0)));
-    objects.add(Arc::new(aarect::XYRect::new(
+    let light: Arc<dyn Hittable + Sync + Send> = Arc::new(aarect::XYRect::new(
         3.0,
         5.0,
         1.0,
         3.0,
         -2.0,
         Arc::clone(&difflight),
-    )));
+    ));
+    objects.add(Arc::clone(&light));
+    lights.add(light);
     //objects.add(Arc::new(sphere::Sphere::new(Point::new(0.0, 7.0, 0.0), 2.0, Arc::clone(&difflight))));
 
     objects
 }
 
-fn cornell_box() -> hittable_list::HittableList {
+fn cornell_box(lights: &mut hittable_list::HittableList) -> hittable_list::HittableList {
     let mut objects = hittable_list::HittableList {
         objects: Vec::with_capacity(10),
     };
@@ -225,14 +228,16 @@ fn cornell_box() -> hittable_list::HittableList {
         0.0,
         Arc::clone(&red),
     )));
-    objects.add(Arc::new(aarect::XZRect::new(
+    let light_rect: Arc<dyn Hittable + Sync + Send> = Arc::new(aarect::XZRect::new(
         213.0,
         343.0,
         227.0,
         332.0,
         554.0,
         Arc::clone(&light),
-    )));
+    ));
+    objects.add(Arc::clone(&light_rect));
+    lights.add(light_rect);
     objects.add(Arc::new(aarect::XZRect::new(
         0.0,
         555.0,
@@ -263,8 +268,13 @@ fn cornell_box() -> hittable_list::HittableList {
         &Point::new(165.0, 330.0, 165.0),
         Arc::clone(&white),
     ));
-    box1 = Arc::new(RotateY::new(box1, 15.0));
-    box1 = Arc::new(Translate::new(box1, &Vec3::new(265.0, 0.0, 295.0)));
+    box1 = Arc::new(
+        Transform::new(
+            box1,
+            Mat4::translate(&Vec3::new(265.0, 0.0, 295.0)) * Mat4::rotate_y(15.0),
+        )
+        .unwrap(),
+    );
     objects.add(box1);
 
     let mut box2: Arc<dyn Hittable + Sync + Send> = Arc::new(boxes::Box::new(
@@ -272,14 +282,19 @@ fn cornell_box() -> hittable_list::HittableList {
         &Point::new(165.0, 165.0, 165.0),
         Arc::clone(&white),
     ));
-    box2 = Arc::new(RotateY::new(box2, -18.0));
-    box2 = Arc::new(Translate::new(box2, &Vec3::new(130.0, 0.0, 65.0)));
+    box2 = Arc::new(
+        Transform::new(
+            box2,
+            Mat4::translate(&Vec3::new(130.0, 0.0, 65.0)) * Mat4::rotate_y(-18.0),
+        )
+        .unwrap(),
+    );
     objects.add(box2);
 
     objects
 }
 
-fn cornell_smoke() -> hittable_list::HittableList {
+fn cornell_smoke(lights: &mut hittable_list::HittableList) -> hittable_list::HittableList {
     let mut objects = hittable_list::HittableList {
         objects: Vec::with_capacity(10),
     };
@@ -309,14 +324,16 @@ fn cornell_smoke() -> hittable_list::HittableList {
         0.0,
         Arc::clone(&red),
     )));
-    objects.add(Arc::new(aarect::XZRect::new(
+    let light_rect: Arc<dyn Hittable + Sync + Send> = Arc::new(aarect::XZRect::new(
         113.0,
         443.0,
         127.0,
         432.0,
         554.0,
         Arc::clone(&light),
-    )));
+    ));
+    objects.add(Arc::clone(&light_rect));
+    lights.add(light_rect);
     objects.add(Arc::new(aarect::XZRect::new(
         0.0,
         555.0,
@@ -347,16 +364,26 @@ fn cornell_smoke() -> hittable_list::HittableList {
         &Point::new(165.0, 330.0, 165.0),
         Arc::clone(&white),
     ));
-    box1 = Arc::new(RotateY::new(box1, 15.0));
-    box1 = Arc::new(Translate::new(box1, &Vec3::new(265.0, 0.0, 295.0)));
+    box1 = Arc::new(
+        Transform::new(
+            box1,
+            Mat4::translate(&Vec3::new(265.0, 0.0, 295.0)) * Mat4::rotate_y(15.0),
+        )
+        .unwrap(),
+    );
 
     let mut box2: Arc<dyn Hittable + Sync + Send> = Arc::new(boxes::Box::new(
         &Point::new(0.0, 0.0, 0.0),
         &Point::new(165.0, 165.0, 165.0),
         Arc::clone(&white),
     ));
-    box2 = Arc::new(RotateY::new(box2, -18.0));
-    box2 = Arc::new(Translate::new(box2, &Vec3::new(130.0, 0.0, 65.0)));
+    box2 = Arc::new(
+        Transform::new(
+            box2,
+            Mat4::translate(&Vec3::new(130.0, 0.0, 65.0)) * Mat4::rotate_y(-18.0),
+        )
+        .unwrap(),
+    );
 
     objects.add(Arc::new(constant_medium::ConstantMedium::new(
         box1,
@@ -372,7 +399,10 @@ fn cornell_smoke() -> hittable_list::HittableList {
     objects
 }
 
-fn final_scene() -> hittable_list::HittableList {
+fn final_scene(
+    lights: &mut hittable_list::HittableList,
+    bvh_method: bvh::BvhMethod,
+) -> hittable_list::HittableList {
     let mut boxes1 = hittable_list::HittableList {
         objects: Vec::with_capacity(10),
     };
@@ -399,18 +429,20 @@ fn final_scene() -> hittable_list::HittableList {
     }
 
     let mut objects =
-        hittable_list::HittableList::new(Arc::new(bvh::BvhNode::new_l(&mut boxes1, 0.0, 1.0)));
+        hittable_list::HittableList::new(bvh::build(&mut boxes1, 0.0, 1.0, bvh_method));
 
     let light: Arc<dyn Material + Sync + Send> =
         Arc::new(DiffuseLight::new(Color::new(7.0, 7.0, 7.0)));
-    objects.add(Arc::new(aarect::XZRect::new(
+    let light_rect: Arc<dyn Hittable + Sync + Send> = Arc::new(aarect::XZRect::new(
         123.0,
         423.0,
         147.0,
         412.0,
         554.0,
         Arc::clone(&light),
-    )));
+    ));
+    objects.add(Arc::clone(&light_rect));
+    lights.add(light_rect);
 
     let center1 = Point::new(400.0, 400.0, 200.0);
     let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
@@ -487,13 +519,13 @@ fn final_scene() -> hittable_list::HittableList {
         )));
     }
 
-    objects.add(Arc::new(Translate::new(
-        Arc::new(RotateY::new(
-            Arc::new(bvh::BvhNode::new_l(&mut boxes2, 0.0, 1.0)),
-            15.0,
-        )),
-        &Vec3::new(-100.0, 270.0, 395.0),
-    )));
+    objects.add(Arc::new(
+        Transform::new(
+            bvh::build(&mut boxes2, 0.0, 1.0, bvh_method),
+            Mat4::translate(&Vec3::new(-100.0, 270.0, 395.0)) * Mat4::rotate_y(15.0),
+        )
+        .unwrap(),
+    ));
 
     objects
 }
@@ -520,7 +552,7 @@ fn glow_earth() -> hittable_list::HittableList {
     objects
 }
 
-fn bg() -> hittable_list::HittableList {
+fn bg(lights: &mut hittable_list::HittableList) -> hittable_list::HittableList {
     let mut objects = hittable_list::HittableList {
         objects: Vec::with_capacity(10),
     };
@@ -535,11 +567,13 @@ fn bg() -> hittable_list::HittableList {
         3.5,
         Color::new(1.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0) * 1.5,
     ));
-    objects.add(Arc::new(sphere::Sphere::new(
+    let light: Arc<dyn Hittable + Sync + Send> = Arc::new(sphere::Sphere::new(
         Point::new(0.0, 3.0, 0.0),
         2.0,
         Arc::new(DiffuseLight::new_txtr(pertext)),
-    )));
+    ));
+    objects.add(Arc::clone(&light));
+    lights.add(light);
 
     //let difflight: Arc<dyn Material  + Sync + Send> = Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0)));
     //let grnlight: Arc<dyn Material  + Sync + Send> = Arc::new(DiffuseLight::new(Color::new(0.0, 12.0, 0.0)));
@@ -555,7 +589,7 @@ fn bg() -> hittable_list::HittableList {
     objects
 }
 
-fn pandorba() -> hittable_list::HittableList {
+fn pandorba(lights: &mut hittable_list::HittableList) -> hittable_list::HittableList {
     let mut objects = hittable_list::HittableList {
         objects: Vec::with_capacity(10),
     };
@@ -581,11 +615,13 @@ fn pandorba() -> hittable_list::HittableList {
         1.0,
         2.0,
     ));
-    objects.add(Arc::new(sphere::Sphere::new(
+    let light: Arc<dyn Hittable + Sync + Send> = Arc::new(sphere::Sphere::new(
         Point::new(0.0, 3.0, 0.0),
         2.0,
         Arc::new(DiffuseLight::new_txtr(pertext)),
-    )));
+    ));
+    objects.add(Arc::clone(&light));
+    lights.add(light);
 
     //let _fog: Arc<dyn Hittable + Sync + Send> = Arc::new(sphere::Sphere::new(Point::new(0.0, 3.0, 0.0), 10.0, Arc::new(Lambertian::new(Color::new_e()))));
     //objects.add(Arc::new(constant_medium::ConstantMedium::new(_fog, 0.1, Color::new(1.0, 1.0, 1.0))));
@@ -622,7 +658,7 @@ fn noises() -> hittable_list::HittableList {
 }
 
 arg_enum! {
-    #[derive(Debug, PartialEq)]
+    #[derive(Debug, PartialEq, Clone, Copy)]
     pub enum Scene{
         RandomSpheres,
         TwoSpheres,
@@ -642,6 +678,8 @@ arg_enum! {
 pub struct SceneData {
     pub image_width: u32,
     pub background: Color,
+    /// Lighting from the surroundings, replacing the background when present
+    pub environment: Option<Arc<dyn environment::Environment + Sync + Send>>,
     pub lookfrom: Point,
     pub lookat: Point,
     pub vup: Point,
@@ -650,11 +688,57 @@ pub struct SceneData {
     pub aperture: f64,
     pub sample_per_pixel: u32,
     pub aspect_ratio: f64,
+    /// Objects to sample scattered rays towards directly, normally the scene's light sources
+    pub lights: hittable_list::HittableList,
+    /// Point, spot and directional lights, reached by shadow rays from every surface but mirrors and smooth glass
+    pub punctual_lights: Vec<Arc<dyn light::Light + Sync + Send>>,
+    /// Trace a few wavelengths per sample rather than red, green and blue, so glass can disperse light
+    pub spectral: bool,
+    /// How scenes should build their bounding volume hierarchies
+    pub bvh_method: bvh::BvhMethod,
+    /// Keyframed camera for animations, overriding the fixed camera above
+    pub camera_path: Option<animation::CameraPath>,
+    pub projection: camera::Projection,
+    pub stereo: camera::Stereo,
+    pub lens: lens::Lens,
+    /// Photographic exposure, which sets the aperture and shutter from camera settings when present
+    pub photographic: Option<lens::Photographic>,
+}
+
+impl SceneData {
+    /// Picture and camera defaults, which scenes and the user may override
+    pub fn new() -> SceneData {
+        SceneData {
+            image_width: 1920,
+            background: Color::new(0.0, 0.0, 0.0),
+            environment: None,
+            lookfrom: Point::new_e(),
+            lookat: Point::new_e(),
+            vup: Point::new(0.0, 1.0, 0.0),
+            dist_to_focus: 10.0,
+            vfov: 40.0,
+            aperture: 0.0,
+            sample_per_pixel: 100,
+            aspect_ratio: 16.0 / 9.0,
+            lights: hittable_list::HittableList {
+                objects: Vec::new(),
+            },
+            punctual_lights: Vec::new(),
+            spectral: false,
+            bvh_method: bvh::BvhMethod::Flat,
+            camera_path: None,
+            projection: camera::Projection::perspective(),
+            stereo: camera::Stereo::mono(),
+            lens: lens::Lens::circle(),
+            photographic: None,
+        }
+    }
 }
 
 /**
  * Returns the scene, along with scene defined options, if present
  *
+ * Scenes with light sources also add them to scene_dat.lights.
  */
 pub fn match_scene(scene: Scene, scene_dat: &mut SceneData) -> hittable_list::HittableList {
     //Create empty return types to modify in the match
@@ -663,7 +747,7 @@ pub fn match_scene(scene: Scene, scene_dat: &mut SceneData) -> hittable_list::Hi
     //Fill world and scene_dat
     match scene {
         Scene::RandomSpheres => {
-            world = random_scene();
+            world = random_scene(scene_dat.bvh_method);
             scene_dat.image_width = 1200;
             scene_dat.background = Color::new(0.70, 0.80, 1.00);
             scene_dat.lookfrom = Point::new(13.0, 2.0, 3.0);
@@ -698,7 +782,7 @@ pub fn match_scene(scene: Scene, scene_dat: &mut SceneData) -> hittable_list::Hi
         }
 
         Scene::SimpleLight => {
-            world = simple_light();
+            world = simple_light(&mut scene_dat.lights);
             scene_dat.sample_per_pixel = 400;
             scene_dat.background = Color::new(0.0, 0.0, 0.0);
             scene_dat.lookfrom = Point::new(26.0, 3.0, 6.0);
@@ -707,7 +791,7 @@ pub fn match_scene(scene: Scene, scene_dat: &mut SceneData) -> hittable_list::Hi
         }
 
         Scene::CornellBox => {
-            world = cornell_box();
+            world = cornell_box(&mut scene_dat.lights);
             scene_dat.aspect_ratio = 1.0;
             scene_dat.image_width = 600;
             scene_dat.sample_per_pixel = 200;
@@ -718,7 +802,7 @@ pub fn match_scene(scene: Scene, scene_dat: &mut SceneData) -> hittable_list::Hi
         }
 
         Scene::CornellSmoke => {
-            world = cornell_smoke();
+            world = cornell_smoke(&mut scene_dat.lights);
             scene_dat.aspect_ratio = 1.0;
             scene_dat.image_width = 600;
             scene_dat.sample_per_pixel = 200;
@@ -728,7 +812,7 @@ pub fn match_scene(scene: Scene, scene_dat: &mut SceneData) -> hittable_list::Hi
         }
 
         Scene::FinalScene => {
-            world = final_scene();
+            world = final_scene(&mut scene_dat.lights, scene_dat.bvh_method);
             scene_dat.aspect_ratio = 1.0;
             scene_dat.image_width = 800;
             scene_dat.sample_per_pixel = 10000;
@@ -747,7 +831,7 @@ pub fn match_scene(scene: Scene, scene_dat: &mut SceneData) -> hittable_list::Hi
         }
 
         Scene::Background => {
-            world = bg();
+            world = bg(&mut scene_dat.lights);
             scene_dat.sample_per_pixel = 400;
             scene_dat.background = Color::new(0.0, 0.0, 0.0);
             scene_dat.lookfrom = Point::new(26.0, 3.0, 6.0);
@@ -756,7 +840,7 @@ pub fn match_scene(scene: Scene, scene_dat: &mut SceneData) -> hittable_list::Hi
         }
 
         Scene::Pandorba => {
-            world = pandorba();
+            world = pandorba(&mut scene_dat.lights);
             scene_dat.sample_per_pixel = 400;
             scene_dat.background = Color::new(1.0, 1.0, 1.0) * 0.01;
             scene_dat.lookfrom = Point::new(26.0, 3.0, 6.0);
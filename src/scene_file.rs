@@ -0,0 +1,1722 @@
+//! Loader for declarative TOML scene descriptions, an alternative to the hard-coded scenes in scene.rs
+//!
+//! A scene file has an optional `[camera]` table overriding SceneData, named `[textures]` and
+//! `[materials]` tables, and `[[objects]]` and `[[lights]]` arrays. Lights are rendered like any
+//! other object, and are also sampled directly when scattering. Every texture, material and object
+//! is a table with a single key naming its type, for example:
+//!
+//! ```toml
+//! [materials]
+//! white = { lambertian = { color = [0.73, 0.73, 0.73] } }
+//!
+//! [[objects]]
+//! sphere = { center = [0, 1, 0], radius = 1, material = "white" }
+//! ```
+//!
+//! A `metal` takes a `color` it reflects head on, a `preset` of gold, copper, silver or aluminum,
+//! or a complex refractive index `eta` and `k`, with a GGX `roughness` that may be a pair for a
+//! brushed finish. A `dielectric` may also have a `roughness` to frost it, and an `absorption`
+//! coefficient in each color, per unit of distance travelled inside, to tint it. Its index of
+//! refraction is a constant `ir`, or varies with wavelength as a `preset` of bk7, fusedsilica or
+//! diamond, a `cauchy = [a, b]` or a `sellmeier = { b = [..], c = [..] }`, in micrometers. Setting
+//! `spectral = true` in `[camera]` renders by wavelength, so such glass splits light into rainbows.
+//!
+//! Objects in the named `[prototypes]` table are built once, and placed any number of times by
+//! `instance` objects, each with its own list of transforms applied in order:
+//!
+//! ```toml
+//! [prototypes]
+//! tree = { mesh = { file = "tree.obj" } }
+//!
+//! [[objects]]
+//! instance = { prototype = "tree", transform = [{ scale = [2, 2, 2] }, { translate = [5, 0, 0] }] }
+//! ```
+//!
+//! The `[camera]` table may also hold `keyframes`, each a `frame` number with any of the camera's
+//! position, target, vup, vfov, aperture and focus distance, which drive the camera when rendering
+//! a sequence of frames. Settings a key leaves out are taken from the rest of the table.
+//!
+//! Point, spot and directional lights, which can't be seen or hit, go in a
+//! `[[punctual_lights]]` array and light every surface but mirrors and smooth glass through
+//! shadow rays:
+//!
+//! ```toml
+//! [[punctual_lights]]
+//! spot = { position = [0, 5, 0], direction = [0, -1, 0], intensity = [50, 50, 50], angle = 30, falloff = 5 }
+//! ```
+//!
+//! An `[environment]` table lights the scene from an equirectangular HDR or EXR image in place of
+//! the background, as in `[environment] map = { file = "sky.hdr", rotation = 90, intensity = 1 }`,
+//! or from a daylight sky with the sun, as in `[environment] sky = { sun_elevation = 30,
+//! sun_azimuth = 120, turbidity = 3, ground_albedo = [0.3, 0.3, 0.3], sun_radius = 0.27 }`.
+//!
+//! The camera's lens may be shaped with `aperture_blades` or an `aperture_image`, and with
+//! `f_stop`, `shutter` and `iso` it exposes like a photograph, its aperture and brightness
+//! following from them.
+//!
+//! An `animated` object moves through keyframes from time 0 to 1, blurring with its motion while
+//! the camera's shutter is open:
+//!
+//! ```toml
+//! [[objects]]
+//! [objects.animated]
+//! keyframes = [
+//!     { time = 0, translate = [0, 0, 0] },
+//!     { time = 1, translate = [0, 1, 0], rotate = { axis = [0, 1, 0], angle = 90 } },
+//! ]
+//! object = { box = { min = [-1, -1, -1], max = [1, 1, 1], material = "white" } }
+//! ```
+
+use crate::hittable::*;
+use crate::mat4::Mat4;
+use crate::materials::*;
+use crate::quat::Quat;
+use crate::scene::SceneData;
+use crate::texture::*;
+use crate::*;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use toml::Spanned;
+
+#[derive(Debug)]
+pub enum SceneFileErr {
+    IoError { err: std::io::Error },
+    ParseError { err: String },
+    InvalidScene { err: String },
+}
+
+impl fmt::Display for SceneFileErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileErr::IoError { err } => write!(f, "{}", err),
+            SceneFileErr::ParseError { err } => write!(f, "{}", err),
+            SceneFileErr::InvalidScene { err } => write!(f, "{}", err),
+        }
+    }
+}
+
+type Vec3Desc = [f64; 3];
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    camera: CameraDesc,
+    #[serde(default)]
+    textures: HashMap<String, Spanned<TextureDesc>>,
+    #[serde(default)]
+    materials: HashMap<String, Spanned<MaterialDesc>>,
+    #[serde(default)]
+    prototypes: HashMap<String, Spanned<ObjectDesc>>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    lights: Vec<ObjectDesc>,
+    #[serde(default)]
+    punctual_lights: Vec<PunctualLightDesc>,
+    environment: Option<EnvironmentDesc>,
+}
+
+/// Every field is optional, and only overrides SceneData when present
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    image_width: Option<u32>,
+    aspect_ratio: Option<f64>,
+    samples_per_pixel: Option<u32>,
+    background: Option<Vec3Desc>,
+    lookfrom: Option<Vec3Desc>,
+    lookat: Option<Vec3Desc>,
+    vup: Option<Vec3Desc>,
+    vfov: Option<f64>,
+    aperture: Option<f64>,
+    dist_to_focus: Option<f64>,
+    projection: Option<Spanned<String>>,
+    view_width: Option<Spanned<f64>>,
+    fisheye_fov: Option<Spanned<f64>>,
+    fisheye_mapping: Option<Spanned<String>>,
+    stereo: Option<Spanned<String>>,
+    interocular: Option<Spanned<f64>>,
+    convergence: Option<Spanned<f64>>,
+    stereo_method: Option<Spanned<String>>,
+    aperture_blades: Option<Spanned<u32>>,
+    aperture_image: Option<Spanned<String>>,
+    aperture_rotation: Option<f64>,
+    cat_eye: Option<Spanned<f64>>,
+    f_stop: Option<Spanned<f64>>,
+    shutter: Option<Spanned<SecondsDesc>>,
+    iso: Option<Spanned<f64>>,
+    sensor_height: Option<Spanned<f64>>,
+    scene_scale: Option<Spanned<f64>>,
+    keyframes: Option<Spanned<Vec<CameraKeyDesc>>>,
+    spectral: Option<bool>,
+}
+
+/// Time in seconds, as a number or as a fraction such as "1/60"
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SecondsDesc {
+    Number(f64),
+    Fraction(String),
+}
+
+/// Camera at one frame of an animation. Missing fields take their value from the rest of the [camera] table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraKeyDesc {
+    frame: f64,
+    lookfrom: Option<Vec3Desc>,
+    lookat: Option<Vec3Desc>,
+    vup: Option<Vec3Desc>,
+    vfov: Option<f64>,
+    aperture: Option<f64>,
+    dist_to_focus: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid {
+        color: Vec3Desc,
+    },
+    Checker {
+        even: Option<Vec3Desc>,
+        even_texture: Option<Spanned<String>>,
+        odd: Option<Vec3Desc>,
+        odd_texture: Option<Spanned<String>>,
+    },
+    Noise {
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default = "default_white")]
+        color: Vec3Desc,
+    },
+    Turbulence {
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default = "default_white")]
+        color: Vec3Desc,
+    },
+    Marble {
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default = "default_white")]
+        color: Vec3Desc,
+    },
+    DualMarble {
+        #[serde(default = "default_scale")]
+        scale: f64,
+        color1: Vec3Desc,
+        color2: Vec3Desc,
+        #[serde(default = "default_scale")]
+        weight: f64,
+    },
+    Fragment {
+        noises: usize,
+        background: Vec3Desc,
+        line: Vec3Desc,
+        line_range: [f64; 2],
+        fragment_range: [f64; 2],
+        #[serde(default = "default_scale")]
+        line_scale: f64,
+        #[serde(default = "default_scale")]
+        mask_scale: f64,
+    },
+    Image {
+        file: String,
+    },
+}
+
+/**
+ * Light with no surface. The intensity of point and spot lights is the irradiance at a distance of 1, and a
+ * directional light's is its irradiance everywhere.
+ */
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum PunctualLightDesc {
+    Point {
+        position: Vec3Desc,
+        intensity: Vec3Desc,
+    },
+    Spot {
+        position: Vec3Desc,
+        direction: Spanned<Vec3Desc>,
+        intensity: Vec3Desc,
+        angle: Spanned<f64>,
+        falloff: Option<Spanned<f64>>,
+    },
+    Directional {
+        direction: Spanned<Vec3Desc>,
+        intensity: Vec3Desc,
+    },
+}
+
+/// Lighting from the surroundings, replacing the camera's background
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum EnvironmentDesc {
+    Map {
+        file: Spanned<String>,
+        #[serde(default)]
+        rotation: f64,
+        intensity: Option<Spanned<f64>>,
+    },
+    /// Fields left out take their values from SkySettings::new
+    Sky {
+        sun_elevation: Option<Spanned<f64>>,
+        sun_azimuth: Option<f64>,
+        turbidity: Option<Spanned<f64>>,
+        ground_albedo: Option<Vec3Desc>,
+        sun_radius: Option<Spanned<f64>>,
+        intensity: Option<Spanned<f64>>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        color: Option<Vec3Desc>,
+        texture: Option<Spanned<String>>,
+    },
+    /// Exactly one of a color, a preset, or an eta and k
+    Metal {
+        color: Option<Vec3Desc>,
+        preset: Option<Spanned<String>>,
+        eta: Option<Vec3Desc>,
+        k: Option<Vec3Desc>,
+        /// Older name for an isotropic roughness
+        fuzz: Option<f64>,
+        roughness: Option<RoughnessDesc>,
+    },
+    /// Smooth and clear unless given a roughness or an absorption, with exactly one of an ir, a preset, or a Cauchy
+    /// or Sellmeier curve
+    Dielectric {
+        ir: Option<f64>,
+        preset: Option<Spanned<String>>,
+        cauchy: Option<[f64; 2]>,
+        sellmeier: Option<SellmeierDesc>,
+        roughness: Option<RoughnessDesc>,
+        absorption: Option<Vec3Desc>,
+    },
+    DiffuseLight {
+        color: Option<Vec3Desc>,
+        texture: Option<Spanned<String>>,
+    },
+    Isotropic {
+        color: Option<Vec3Desc>,
+        texture: Option<Spanned<String>>,
+    },
+}
+
+/// Coefficients of the Sellmeier equation, for wavelengths in micrometers
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SellmeierDesc {
+    b: [f64; 3],
+    c: [f64; 3],
+}
+
+/// One roughness, or two for a brushed metal, the first along lines of latitude about y and the second across them
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RoughnessDesc {
+    Isotropic(f64),
+    Anisotropic([f64; 2]),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: Vec3Desc,
+        radius: Spanned<f64>,
+        material: Spanned<String>,
+    },
+    MovingSphere {
+        center0: Vec3Desc,
+        center1: Vec3Desc,
+        #[serde(default)]
+        time0: f64,
+        #[serde(default = "default_scale")]
+        time1: f64,
+        radius: Spanned<f64>,
+        material: Spanned<String>,
+    },
+    /// Each pair of bounds is the rectangle's low edge then its high edge
+    XyRect {
+        x: Spanned<[f64; 2]>,
+        y: Spanned<[f64; 2]>,
+        k: f64,
+        material: Spanned<String>,
+    },
+    XzRect {
+        x: Spanned<[f64; 2]>,
+        z: Spanned<[f64; 2]>,
+        k: f64,
+        material: Spanned<String>,
+    },
+    YzRect {
+        y: Spanned<[f64; 2]>,
+        z: Spanned<[f64; 2]>,
+        k: f64,
+        material: Spanned<String>,
+    },
+    Triangle {
+        vertices: [Vec3Desc; 3],
+        normals: Option<[Vec3Desc; 3]>,
+        uvs: Option<[[f64; 2]; 3]>,
+        material: Spanned<String>,
+    },
+    Mesh {
+        file: Spanned<String>,
+        material: Option<Spanned<String>>,
+    },
+    Box {
+        min: Vec3Desc,
+        max: Vec3Desc,
+        material: Spanned<String>,
+    },
+    ConstantMedium {
+        boundary: std::boxed::Box<ObjectDesc>,
+        density: Spanned<f64>,
+        color: Option<Vec3Desc>,
+        texture: Option<Spanned<String>>,
+    },
+    Transform {
+        transform: Spanned<Vec<TransformDesc>>,
+        object: std::boxed::Box<ObjectDesc>,
+    },
+    Instance {
+        prototype: Spanned<String>,
+        transform: Spanned<Vec<TransformDesc>>,
+    },
+    Animated {
+        keyframes: Spanned<Vec<KeyframeDesc>>,
+        object: std::boxed::Box<ObjectDesc>,
+    },
+    Translate {
+        offset: Vec3Desc,
+        object: std::boxed::Box<ObjectDesc>,
+    },
+    RotateX {
+        angle: f64,
+        object: std::boxed::Box<ObjectDesc>,
+    },
+    RotateY {
+        angle: f64,
+        object: std::boxed::Box<ObjectDesc>,
+    },
+    RotateZ {
+        angle: f64,
+        object: std::boxed::Box<ObjectDesc>,
+    },
+    Bvh {
+        objects: Spanned<Vec<ObjectDesc>>,
+    },
+    List {
+        objects: Vec<ObjectDesc>,
+    },
+}
+
+/// One step of a transform list, applied after the steps before it
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TransformDesc {
+    Translate(Vec3Desc),
+    /// Counter-clockwise by angle degrees, looking down axis towards the origin
+    Rotate {
+        axis: Vec3Desc,
+        angle: f64,
+    },
+    Scale(Vec3Desc),
+    Shear {
+        #[serde(default)]
+        xy: f64,
+        #[serde(default)]
+        xz: f64,
+        #[serde(default)]
+        yx: f64,
+        #[serde(default)]
+        yz: f64,
+        #[serde(default)]
+        zx: f64,
+        #[serde(default)]
+        zy: f64,
+    },
+    /// Row major, applied to column vectors
+    Matrix([[f64; 4]; 4]),
+}
+
+/// Pose at time, scaled, then rotated, then translated. Rays are timed from 0 to 1 over the camera's shutter.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDesc {
+    time: f64,
+    #[serde(default)]
+    translate: Vec3Desc,
+    rotate: Option<RotationDesc>,
+    #[serde(default = "default_ones")]
+    scale: Vec3Desc,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RotationDesc {
+    axis: Vec3Desc,
+    angle: f64,
+}
+
+fn default_ones() -> Vec3Desc {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_white() -> Vec3Desc {
+    [1.0, 1.0, 1.0]
+}
+
+fn to_vec3(v: &Vec3Desc) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+/// Wraps obj in m, a translation or rotation, which is always invertible
+fn rigid(obj: Arc<dyn Hittable + Sync + Send>, m: Mat4) -> Arc<dyn Hittable + Sync + Send> {
+    Arc::new(Transform::new(obj, m).unwrap())
+}
+
+/**
+ * Builds textures, materials and objects from a parsed SceneFile, resolving names as it goes.
+ *
+ * Spans are byte offsets into src, and are only used to report line numbers in errors.
+ */
+struct Builder<'a> {
+    src: &'a str,
+    dir: PathBuf,
+    file: &'a SceneFile,
+    textures: HashMap<String, Arc<dyn Texture + Sync + Send>>,
+    materials: HashMap<String, Arc<dyn Material + Sync + Send>>,
+    resolving: HashSet<String>,
+    prototypes: HashMap<String, Arc<dyn Hittable + Sync + Send>>,
+    resolving_prototypes: HashSet<String>,
+    bvh_method: bvh::BvhMethod,
+}
+
+impl<'a> Builder<'a> {
+    fn line_of(&self, span: std::ops::Range<usize>) -> usize {
+        self.src[..span.start].matches('\n').count() + 1
+    }
+
+    fn invalid<T>(&self, span: std::ops::Range<usize>, msg: String) -> Result<T, SceneFileErr> {
+        Err(SceneFileErr::InvalidScene {
+            err: format!("line {}: {}", self.line_of(span), msg),
+        })
+    }
+
+    fn texture(
+        &mut self,
+        name: &str,
+        span: std::ops::Range<usize>,
+    ) -> Result<Arc<dyn Texture + Sync + Send>, SceneFileErr> {
+        if let Some(t) = self.textures.get(name) {
+            return Ok(Arc::clone(t));
+        }
+
+        let file = self.file;
+        let desc = match file.textures.get(name) {
+            Some(d) => d,
+            None => return self.invalid(span, format!("unknown texture '{}'", name)),
+        };
+
+        // Checker textures may refer to other textures, so guard against reference cycles
+        if !self.resolving.insert(name.to_string()) {
+            return self.invalid(desc.span(), format!("texture '{}' refers to itself", name));
+        }
+
+        let txtr: Arc<dyn Texture + Sync + Send> = match desc.get_ref() {
+            TextureDesc::Solid { color } => Arc::new(SolidColor::new(to_vec3(color))),
+            TextureDesc::Checker {
+                even,
+                even_texture,
+                odd,
+                odd_texture,
+            } => {
+                let even = self.albedo(even, even_texture, desc.span(), "checker even")?;
+                let odd = self.albedo(odd, odd_texture, desc.span(), "checker odd")?;
+                Arc::new(CheckerTexture::new(&even, &odd))
+            }
+            TextureDesc::Noise { scale, color } => {
+                Arc::new(NoiseTexture::new_sc_clr(*scale, to_vec3(color)))
+            }
+            TextureDesc::Turbulence { scale, color } => {
+                Arc::new(TurbNoiseTexture::new_sc_clr(*scale, to_vec3(color)))
+            }
+            TextureDesc::Marble { scale, color } => {
+                Arc::new(MarbleNoiseTexture::new_sc_clr(*scale, to_vec3(color)))
+            }
+            TextureDesc::DualMarble {
+                scale,
+                color1,
+                color2,
+                weight,
+            } => Arc::new(DualMarbleNoiseTexture::new_sc_clr_weight(
+                *scale,
+                to_vec3(color1),
+                to_vec3(color2),
+                *weight,
+            )),
+            TextureDesc::Fragment {
+                noises,
+                background,
+                line,
+                line_range,
+                fragment_range,
+                line_scale,
+                mask_scale,
+            } => Arc::new(FragmentNoiseTexture::new(
+                *noises,
+                to_vec3(background),
+                to_vec3(line),
+                line_range[0],
+                line_range[1],
+                fragment_range[0],
+                fragment_range[1],
+                *line_scale,
+                *mask_scale,
+            )),
+            TextureDesc::Image { file } => {
+                let path = self.dir.join(file);
+                Arc::new(ImageTexture::new(&path.to_string_lossy()))
+            }
+        };
+
+        self.resolving.remove(name);
+        self.textures.insert(name.to_string(), Arc::clone(&txtr));
+
+        Ok(txtr)
+    }
+
+    /// Resolves a `color` / `texture` pair, exactly one of which must be given
+    fn albedo(
+        &mut self,
+        color: &Option<Vec3Desc>,
+        texture: &Option<Spanned<String>>,
+        span: std::ops::Range<usize>,
+        what: &str,
+    ) -> Result<Arc<dyn Texture + Sync + Send>, SceneFileErr> {
+        match (color, texture) {
+            (Some(c), None) => Ok(Arc::new(SolidColor::new(to_vec3(c)))),
+            (None, Some(name)) => self.texture(name.get_ref(), name.span()),
+            _ => self.invalid(
+                span,
+                format!("{} needs exactly one of a color or a texture", what),
+            ),
+        }
+    }
+
+    fn material(
+        &mut self,
+        name: &str,
+        span: std::ops::Range<usize>,
+    ) -> Result<Arc<dyn Material + Sync + Send>, SceneFileErr> {
+        if let Some(m) = self.materials.get(name) {
+            return Ok(Arc::clone(m));
+        }
+
+        let file = self.file;
+        let desc = match file.materials.get(name) {
+            Some(d) => d,
+            None => return self.invalid(span, format!("unknown material '{}'", name)),
+        };
+
+        let mat: Arc<dyn Material + Sync + Send> = match desc.get_ref() {
+            MaterialDesc::Lambertian { color, texture } => Arc::new(Lambertian::new_txtr(
+                &self.albedo(color, texture, desc.span(), "lambertian material")?,
+            )),
+            MaterialDesc::Metal {
+                color,
+                preset,
+                eta,
+                k,
+                fuzz,
+                roughness,
+            } => {
+                let fresnel = match (color, preset, eta, k) {
+                    (Some(c), None, None, None) => MetalFresnel::Schlick(to_vec3(c)),
+                    (None, Some(p), None, None) => self
+                        .variant::<MetalPreset>(p, "metal preset", &MetalPreset::variants())?
+                        .fresnel(),
+                    (None, None, Some(eta), Some(k)) => MetalFresnel::Conductor {
+                        eta: to_vec3(eta),
+                        k: to_vec3(k),
+                    },
+                    _ => return self.invalid(
+                        desc.span(),
+                        "metal material needs exactly one of a color, a preset, or an eta and k"
+                            .to_string(),
+                    ),
+                };
+                let (u, v) = match (fuzz, roughness) {
+                    (Some(f), None) => (*f, *f),
+                    (None, Some(RoughnessDesc::Isotropic(r))) => (*r, *r),
+                    (None, Some(RoughnessDesc::Anisotropic([u, v]))) => (*u, *v),
+                    (None, None) => (0.0, 0.0),
+                    (Some(_), Some(_)) => {
+                        return self.invalid(
+                            desc.span(),
+                            "metal material can't have both a fuzz and a roughness".to_string(),
+                        )
+                    }
+                };
+                Arc::new(Metal::new_conductor(fresnel, u, v))
+            }
+            MaterialDesc::Dielectric {
+                ir,
+                preset,
+                cauchy,
+                sellmeier,
+                roughness,
+                absorption,
+            } => {
+                let ior = match (ir, preset, cauchy, sellmeier) {
+                    (Some(ir), None, None, None) => spectrum::Ior::Constant(*ir),
+                    (None, Some(p), None, None) => self
+                        .variant::<spectrum::GlassPreset>(
+                            p,
+                            "glass preset",
+                            &spectrum::GlassPreset::variants(),
+                        )?
+                        .ior(),
+                    (None, None, Some([a, b]), None) => spectrum::Ior::Cauchy { a: *a, b: *b },
+                    (None, None, None, Some(SellmeierDesc { b, c })) => {
+                        spectrum::Ior::Sellmeier { b: *b, c: *c }
+                    }
+                    _ => return self.invalid(
+                        desc.span(),
+                        "dielectric material needs exactly one of an ir, a preset, a cauchy or a sellmeier"
+                            .to_string(),
+                    ),
+                };
+                if roughness.is_none() && absorption.is_none() {
+                    Arc::new(Dialectric::new_ior(ior))
+                } else {
+                    let (u, v) = match roughness {
+                        Some(RoughnessDesc::Isotropic(r)) => (*r, *r),
+                        Some(RoughnessDesc::Anisotropic([u, v])) => (*u, *v),
+                        None => (0.0, 0.0),
+                    };
+                    let absorption = absorption.as_ref().map_or(Color::new_e(), to_vec3);
+                    if absorption.x() < 0.0 || absorption.y() < 0.0 || absorption.z() < 0.0 {
+                        return self.invalid(
+                            desc.span(),
+                            "dielectric absorption must be 0 or more".to_string(),
+                        );
+                    }
+                    Arc::new(RoughDielectric::new(ior, u, v, absorption))
+                }
+            }
+            MaterialDesc::DiffuseLight { color, texture } => Arc::new(DiffuseLight::new_txtr(
+                self.albedo(color, texture, desc.span(), "diffuse_light material")?,
+            )),
+            MaterialDesc::Isotropic { color, texture } => Arc::new(Isotropic::new_txtr(
+                self.albedo(color, texture, desc.span(), "isotropic material")?,
+            )),
+        };
+
+        self.materials.insert(name.to_string(), Arc::clone(&mat));
+
+        Ok(mat)
+    }
+
+    fn prototype(
+        &mut self,
+        name: &str,
+        span: std::ops::Range<usize>,
+    ) -> Result<Arc<dyn Hittable + Sync + Send>, SceneFileErr> {
+        if let Some(p) = self.prototypes.get(name) {
+            return Ok(Arc::clone(p));
+        }
+
+        let file = self.file;
+        let desc = match file.prototypes.get(name) {
+            Some(d) => d,
+            None => return self.invalid(span, format!("unknown prototype '{}'", name)),
+        };
+
+        // Prototypes may instance other prototypes, so guard against reference cycles
+        if !self.resolving_prototypes.insert(name.to_string()) {
+            return self.invalid(
+                desc.span(),
+                format!("prototype '{}' refers to itself", name),
+            );
+        }
+
+        let obj = self.object(desc.get_ref())?;
+
+        self.resolving_prototypes.remove(name);
+        self.prototypes.insert(name.to_string(), Arc::clone(&obj));
+
+        Ok(obj)
+    }
+
+    /// Wraps obj in the transform built from steps, which must be invertible
+    fn transform(
+        &self,
+        obj: Arc<dyn Hittable + Sync + Send>,
+        steps: &Spanned<Vec<TransformDesc>>,
+    ) -> Result<Arc<dyn Hittable + Sync + Send>, SceneFileErr> {
+        let m = steps.get_ref().iter().fold(Mat4::identity(), |m, step| {
+            let step = match step {
+                TransformDesc::Translate(offset) => Mat4::translate(&to_vec3(offset)),
+                TransformDesc::Rotate { axis, angle } => Mat4::rotate(&to_vec3(axis), *angle),
+                TransformDesc::Scale(factors) => Mat4::scale(&to_vec3(factors)),
+                TransformDesc::Shear {
+                    xy,
+                    xz,
+                    yx,
+                    yz,
+                    zx,
+                    zy,
+                } => Mat4::shear(*xy, *xz, *yx, *yz, *zx, *zy),
+                TransformDesc::Matrix(rows) => Mat4::new(*rows),
+            };
+            step * m
+        });
+
+        match Transform::new(obj, m) {
+            Some(t) => Ok(Arc::new(t)),
+            None => self.invalid(
+                steps.span(),
+                "transform flattens the object, so can't be undone".to_string(),
+            ),
+        }
+    }
+
+    fn environment(
+        &self,
+        desc: &EnvironmentDesc,
+    ) -> Result<Arc<dyn environment::Environment + Sync + Send>, SceneFileErr> {
+        match desc {
+            EnvironmentDesc::Map {
+                file,
+                rotation,
+                intensity,
+            } => {
+                let intensity = match intensity {
+                    Some(i) if *i.get_ref() < 0.0 => {
+                        return self.invalid(i.span(), "intensity must be 0 or more".to_string())
+                    }
+                    Some(i) => *i.get_ref(),
+                    None => 1.0,
+                };
+                let path = self.dir.join(file.get_ref());
+                match environment::EnvironmentMap::load(
+                    &path.to_string_lossy(),
+                    *rotation,
+                    intensity,
+                ) {
+                    Ok(m) => Ok(Arc::new(m)),
+                    Err(e) => self.invalid(
+                        file.span(),
+                        format!("could not load environment map '{}': {}", file.get_ref(), e),
+                    ),
+                }
+            }
+            EnvironmentDesc::Sky {
+                sun_elevation,
+                sun_azimuth,
+                turbidity,
+                ground_albedo,
+                sun_radius,
+                intensity,
+            } => {
+                let mut settings = sky::SkySettings::new();
+                if let Some(e) = sun_elevation {
+                    settings.sun_elevation = self.within(e, "sun_elevation", 0.0, 90.0)?;
+                }
+                if let Some(a) = sun_azimuth {
+                    settings.sun_azimuth = *a;
+                }
+                if let Some(t) = turbidity {
+                    settings.turbidity = self.within(t, "turbidity", 2.0, 10.0)?;
+                }
+                if let Some(g) = ground_albedo {
+                    settings.ground_albedo = to_vec3(g);
+                }
+                if let Some(r) = sun_radius {
+                    if !(*r.get_ref() > 0.0 && *r.get_ref() <= 90.0) {
+                        return self.invalid(
+                            r.span(),
+                            "sun_radius must be above 0 and at most 90".to_string(),
+                        );
+                    }
+                    settings.sun_radius = *r.get_ref();
+                }
+                match intensity {
+                    Some(i) if *i.get_ref() < 0.0 => {
+                        return self.invalid(i.span(), "intensity must be 0 or more".to_string())
+                    }
+                    Some(i) => settings.intensity = *i.get_ref(),
+                    None => (),
+                }
+                Ok(Arc::new(sky::PreethamSky::new(&settings)))
+            }
+        }
+    }
+
+    fn punctual_light(
+        &self,
+        desc: &PunctualLightDesc,
+    ) -> Result<Arc<dyn light::Light + Sync + Send>, SceneFileErr> {
+        let direction = |d: &Spanned<Vec3Desc>| {
+            let v = to_vec3(d.get_ref());
+            if v.length_squared() <= 0.0 {
+                return self.invalid(d.span(), "direction must not be zero".to_string());
+            }
+            Ok(v)
+        };
+
+        Ok(match desc {
+            PunctualLightDesc::Point {
+                position,
+                intensity,
+            } => Arc::new(light::PointLight::new(
+                to_vec3(position),
+                to_vec3(intensity),
+            )),
+            PunctualLightDesc::Spot {
+                position,
+                direction: d,
+                intensity,
+                angle,
+                falloff,
+            } => {
+                if !(*angle.get_ref() > 0.0 && *angle.get_ref() <= 180.0) {
+                    return self.invalid(
+                        angle.span(),
+                        "angle must be above 0 and at most 180".to_string(),
+                    );
+                }
+                let falloff = match falloff {
+                    Some(f) if *f.get_ref() < 0.0 => {
+                        return self.invalid(f.span(), "falloff must be 0 or more".to_string())
+                    }
+                    Some(f) => *f.get_ref(),
+                    None => 0.0,
+                };
+                Arc::new(light::SpotLight::new(
+                    to_vec3(position),
+                    direction(d)?,
+                    to_vec3(intensity),
+                    *angle.get_ref(),
+                    falloff,
+                ))
+            }
+            PunctualLightDesc::Directional {
+                direction: d,
+                intensity,
+            } => Arc::new(light::DirectionalLight::new(
+                direction(d)?,
+                to_vec3(intensity),
+            )),
+        })
+    }
+
+    /// Parses one of the variants of an arg_enum, ignoring case, as the command line does
+    fn variant<T: std::str::FromStr>(
+        &self,
+        v: &Spanned<String>,
+        what: &str,
+        variants: &[&str],
+    ) -> Result<T, SceneFileErr> {
+        match v.get_ref().parse::<T>() {
+            Ok(t) => Ok(t),
+            Err(_) => self.invalid(
+                v.span(),
+                format!(
+                    "unknown {} '{}', expected one of {}",
+                    what,
+                    v.get_ref(),
+                    variants.join(", ")
+                ),
+            ),
+        }
+    }
+
+    fn within(
+        &self,
+        v: &Spanned<f64>,
+        what: &str,
+        min: f64,
+        max: f64,
+    ) -> Result<f64, SceneFileErr> {
+        let x = *v.get_ref();
+        if !(x >= min && x <= max) {
+            return self.invalid(
+                v.span(),
+                format!("{} must be from {} to {}", what, min, max),
+            );
+        }
+        Ok(x)
+    }
+
+    /// Time above 0 and at most 1 second, given as a number or parsed from a fraction
+    fn seconds(&self, v: &Spanned<SecondsDesc>, what: &str) -> Result<f64, SceneFileErr> {
+        match v.get_ref() {
+            SecondsDesc::Number(t) if *t > 0.0 && *t <= 1.0 => Ok(*t),
+            SecondsDesc::Number(_) => self.invalid(
+                v.span(),
+                format!("{} must be above 0 and at most 1 second", what),
+            ),
+            SecondsDesc::Fraction(f) => lens::parse_seconds(f)
+                .or_else(|e| self.invalid(v.span(), format!("{}: {}", what, e))),
+        }
+    }
+
+    /// Low and high edges of a range, which must be given in that order
+    fn bounds(&self, v: &Spanned<[f64; 2]>, what: &str) -> Result<(f64, f64), SceneFileErr> {
+        let [lo, hi] = *v.get_ref();
+        if lo >= hi {
+            return self.invalid(
+                v.span(),
+                format!("{} must go from its low edge to its high edge", what),
+            );
+        }
+        Ok((lo, hi))
+    }
+
+    fn positive(&self, v: &Spanned<f64>, what: &str) -> Result<f64, SceneFileErr> {
+        if *v.get_ref() <= 0.0 {
+            return self.invalid(v.span(), format!("{} must be positive", what));
+        }
+        Ok(*v.get_ref())
+    }
+
+    fn list(
+        &mut self,
+        objects: &[ObjectDesc],
+    ) -> Result<hittable_list::HittableList, SceneFileErr> {
+        let mut list = hittable_list::HittableList {
+            objects: Vec::with_capacity(objects.len()),
+        };
+
+        for o in objects {
+            list.add(self.object(o)?);
+        }
+
+        Ok(list)
+    }
+
+    fn object(
+        &mut self,
+        desc: &ObjectDesc,
+    ) -> Result<Arc<dyn Hittable + Sync + Send>, SceneFileErr> {
+        let obj: Arc<dyn Hittable + Sync + Send> = match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => Arc::new(sphere::Sphere::new(
+                to_vec3(center),
+                self.positive(radius, "sphere radius")?,
+                self.material(material.get_ref(), material.span())?,
+            )),
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => Arc::new(moving_sphere::MovingSphere::new(
+                to_vec3(center0),
+                to_vec3(center1),
+                *time0,
+                *time1,
+                self.positive(radius, "sphere radius")?,
+                self.material(material.get_ref(), material.span())?,
+            )),
+            ObjectDesc::XyRect { x, y, k, material } => {
+                let (x0, x1) = self.bounds(x, "rect x")?;
+                let (y0, y1) = self.bounds(y, "rect y")?;
+                Arc::new(aarect::XYRect::new(
+                    x0,
+                    x1,
+                    y0,
+                    y1,
+                    *k,
+                    self.material(material.get_ref(), material.span())?,
+                ))
+            }
+            ObjectDesc::XzRect { x, z, k, material } => {
+                let (x0, x1) = self.bounds(x, "rect x")?;
+                let (z0, z1) = self.bounds(z, "rect z")?;
+                Arc::new(aarect::XZRect::new(
+                    x0,
+                    x1,
+                    z0,
+                    z1,
+                    *k,
+                    self.material(material.get_ref(), material.span())?,
+                ))
+            }
+            ObjectDesc::YzRect { y, z, k, material } => {
+                let (y0, y1) = self.bounds(y, "rect y")?;
+                let (z0, z1) = self.bounds(z, "rect z")?;
+                Arc::new(aarect::YZRect::new(
+                    y0,
+                    y1,
+                    z0,
+                    z1,
+                    *k,
+                    self.material(material.get_ref(), material.span())?,
+                ))
+            }
+            ObjectDesc::Triangle {
+                vertices,
+                normals,
+                uvs,
+                material,
+            } => Arc::new(triangle::Triangle::new_full(
+                [
+                    to_vec3(&vertices[0]),
+                    to_vec3(&vertices[1]),
+                    to_vec3(&vertices[2]),
+                ],
+                normals.map(|n| [to_vec3(&n[0]), to_vec3(&n[1]), to_vec3(&n[2])]),
+                uvs.map(|uv| {
+                    [
+                        (uv[0][0], uv[0][1]),
+                        (uv[1][0], uv[1][1]),
+                        (uv[2][0], uv[2][1]),
+                    ]
+                }),
+                self.material(material.get_ref(), material.span())?,
+            )),
+            ObjectDesc::Mesh { file, material } => {
+                // Faces without an MTL material fall back to the given material, or a plain grey
+                let default_mat: Arc<dyn Material + Sync + Send> = match material {
+                    Some(m) => self.material(m.get_ref(), m.span())?,
+                    None => Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73))),
+                };
+                let path = self.dir.join(file.get_ref());
+                match mesh::load_obj(
+                    &path.to_string_lossy(),
+                    default_mat,
+                    0.0,
+                    1.0,
+                    self.bvh_method,
+                ) {
+                    Ok(m) => m,
+                    Err(e) => {
+                        return self.invalid(
+                            file.span(),
+                            format!("could not load mesh '{}': {}", file.get_ref(), e),
+                        )
+                    }
+                }
+            }
+            ObjectDesc::Box { min, max, material } => Arc::new(boxes::Box::new(
+                &to_vec3(min),
+                &to_vec3(max),
+                self.material(material.get_ref(), material.span())?,
+            )),
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                color,
+                texture,
+            } => {
+                let albedo = self.albedo(color, texture, density.span(), "constant_medium")?;
+                Arc::new(constant_medium::ConstantMedium::new_txtr(
+                    self.object(boundary)?,
+                    self.positive(density, "medium density")?,
+                    albedo,
+                ))
+            }
+            ObjectDesc::Transform { transform, object } => {
+                let obj = self.object(object)?;
+                self.transform(obj, transform)?
+            }
+            ObjectDesc::Instance {
+                prototype,
+                transform,
+            } => {
+                let obj = self.prototype(prototype.get_ref(), prototype.span())?;
+                self.transform(obj, transform)?
+            }
+            ObjectDesc::Animated { keyframes, object } => {
+                let frames = keyframes
+                    .get_ref()
+                    .iter()
+                    .map(|k| Keyframe {
+                        time: k.time,
+                        translation: to_vec3(&k.translate),
+                        rotation: match &k.rotate {
+                            Some(r) => Quat::from_axis_angle(&to_vec3(&r.axis), r.angle),
+                            None => Quat::identity(),
+                        },
+                        scale: to_vec3(&k.scale),
+                    })
+                    .collect();
+                match AnimatedTransform::new(self.object(object)?, frames) {
+                    Some(a) => Arc::new(a),
+                    None => {
+                        return self.invalid(
+                            keyframes.span(),
+                            "animated needs at least one keyframe, with no scale zero or changing sign between keyframes"
+                                .to_string(),
+                        )
+                    }
+                }
+            }
+            // rotate_x and rotate_z have always turned clockwise, unlike rotate_y, and are kept that way so
+            // existing scenes render the same
+            ObjectDesc::Translate { offset, object } => {
+                rigid(self.object(object)?, Mat4::translate(&to_vec3(offset)))
+            }
+            ObjectDesc::RotateX { angle, object } => {
+                rigid(self.object(object)?, Mat4::rotate_x(-*angle))
+            }
+            ObjectDesc::RotateY { angle, object } => {
+                rigid(self.object(object)?, Mat4::rotate_y(*angle))
+            }
+            ObjectDesc::RotateZ { angle, object } => {
+                rigid(self.object(object)?, Mat4::rotate_z(-*angle))
+            }
+            ObjectDesc::Bvh { objects } => {
+                if objects.get_ref().is_empty() {
+                    return self.invalid(
+                        objects.span(),
+                        "bvh must contain at least one object".to_string(),
+                    );
+                }
+                let mut list = self.list(objects.get_ref())?;
+                bvh::build(&mut list, 0.0, 1.0, self.bvh_method)
+            }
+            ObjectDesc::List { objects } => Arc::new(self.list(objects)?),
+        };
+
+        Ok(obj)
+    }
+}
+
+/**
+ * Loads the scene described by the TOML file at fname.
+ *
+ * Values given in the file's [camera] table override those in scene_dat. Relative image texture and mesh paths are resolved
+ * against the directory containing the scene file. Errors report the line at fault.
+ */
+pub fn load_scene(
+    fname: &str,
+    scene_dat: &mut SceneData,
+) -> Result<hittable_list::HittableList, SceneFileErr> {
+    let src = match std::fs::read_to_string(fname) {
+        Ok(s) => s,
+        Err(e) => return Err(SceneFileErr::IoError { err: e }),
+    };
+
+    let dir = Path::new(fname)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+
+    build_scene(&src, dir, scene_dat)
+}
+
+/// Builds the scene described by the TOML in src, with relative paths resolved against dir
+fn build_scene(
+    src: &str,
+    dir: PathBuf,
+    scene_dat: &mut SceneData,
+) -> Result<hittable_list::HittableList, SceneFileErr> {
+    let file: SceneFile = match toml::from_str(src) {
+        Ok(f) => f,
+        Err(e) => return Err(SceneFileErr::ParseError { err: e.to_string() }),
+    };
+
+    let mut builder = Builder {
+        src,
+        dir,
+        file: &file,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        resolving: HashSet::new(),
+        prototypes: HashMap::new(),
+        resolving_prototypes: HashSet::new(),
+        bvh_method: scene_dat.bvh_method,
+    };
+
+    // Build every named texture and material up front in file order, so mistakes in unused ones are still reported
+    let mut names: Vec<(&String, std::ops::Range<usize>)> =
+        file.textures.iter().map(|(k, v)| (k, v.span())).collect();
+    names.sort_by_key(|(_, span)| span.start);
+    for (name, span) in names {
+        builder.texture(name, span)?;
+    }
+
+    let mut names: Vec<(&String, std::ops::Range<usize>)> =
+        file.materials.iter().map(|(k, v)| (k, v.span())).collect();
+    names.sort_by_key(|(_, span)| span.start);
+    for (name, span) in names {
+        builder.material(name, span)?;
+    }
+
+    let mut names: Vec<(&String, std::ops::Range<usize>)> =
+        file.prototypes.iter().map(|(k, v)| (k, v.span())).collect();
+    names.sort_by_key(|(_, span)| span.start);
+    for (name, span) in names {
+        builder.prototype(name, span)?;
+    }
+
+    let mut world = builder.list(&file.objects)?;
+    for l in &file.lights {
+        let light = builder.object(l)?;
+        world.add(Arc::clone(&light));
+        scene_dat.lights.add(light);
+    }
+    for l in &file.punctual_lights {
+        scene_dat.punctual_lights.push(builder.punctual_light(l)?);
+    }
+
+    if let Some(env) = &file.environment {
+        scene_dat.environment = Some(builder.environment(env)?);
+    }
+
+    let cam = &file.camera;
+    if let Some(w) = cam.image_width {
+        scene_dat.image_width = w;
+    }
+    if let Some(a) = cam.aspect_ratio {
+        scene_dat.aspect_ratio = a;
+    }
+    if let Some(s) = cam.samples_per_pixel {
+        scene_dat.sample_per_pixel = s;
+    }
+    if let Some(b) = &cam.background {
+        scene_dat.background = to_vec3(b);
+    }
+    if let Some(p) = &cam.lookfrom {
+        scene_dat.lookfrom = to_vec3(p);
+    }
+    if let Some(p) = &cam.lookat {
+        scene_dat.lookat = to_vec3(p);
+    }
+    if let Some(v) = &cam.vup {
+        scene_dat.vup = to_vec3(v);
+    }
+    if let Some(v) = cam.vfov {
+        scene_dat.vfov = v;
+    }
+    if let Some(a) = cam.aperture {
+        scene_dat.aperture = a;
+    }
+    if let Some(d) = cam.dist_to_focus {
+        scene_dat.dist_to_focus = d;
+    }
+    if let Some(s) = cam.spectral {
+        scene_dat.spectral = s;
+    }
+
+    if let Some(p) = &cam.projection {
+        scene_dat.projection.kind =
+            builder.variant(p, "projection", &camera::ProjectionType::variants())?;
+    }
+    if let Some(w) = &cam.view_width {
+        scene_dat.projection.view_width = Some(builder.positive(w, "view_width")?);
+    }
+    if let Some(f) = &cam.fisheye_fov {
+        if !(*f.get_ref() > 0.0 && *f.get_ref() <= 360.0) {
+            return builder.invalid(
+                f.span(),
+                "fisheye_fov must be above 0 and at most 360".to_string(),
+            );
+        }
+        scene_dat.projection.fisheye_fov = *f.get_ref();
+    }
+    if let Some(m) = &cam.fisheye_mapping {
+        scene_dat.projection.fisheye_mapping =
+            builder.variant(m, "fisheye mapping", &camera::FisheyeMapping::variants())?;
+    }
+    if let Some(f) = &cam.stereo {
+        scene_dat.stereo.format =
+            Some(builder.variant(f, "stereo format", &camera::StereoFormat::variants())?);
+    }
+    if let Some(d) = &cam.interocular {
+        scene_dat.stereo.interocular = Some(builder.positive(d, "interocular")?);
+    }
+    if let Some(d) = &cam.convergence {
+        scene_dat.stereo.convergence = Some(builder.positive(d, "convergence")?);
+    }
+    if let Some(m) = &cam.stereo_method {
+        scene_dat.stereo.method =
+            builder.variant(m, "stereo method", &camera::StereoMethod::variants())?;
+    }
+
+    if let Some(b) = &cam.aperture_blades {
+        if *b.get_ref() < 3 {
+            return builder.invalid(b.span(), "aperture_blades must be at least 3".to_string());
+        }
+        scene_dat.lens.shape = lens::ApertureShape::Polygon {
+            blades: *b.get_ref(),
+        };
+    }
+    if let Some(file) = &cam.aperture_image {
+        if cam.aperture_blades.is_some() {
+            return builder.invalid(
+                file.span(),
+                "aperture_image can't be given with aperture_blades".to_string(),
+            );
+        }
+        let path = builder.dir.join(file.get_ref());
+        match lens::ApertureMask::load(&path.to_string_lossy()) {
+            Ok(m) => scene_dat.lens.shape = lens::ApertureShape::Image(Arc::new(m)),
+            Err(e) => {
+                return builder.invalid(
+                    file.span(),
+                    format!("could not load aperture image '{}': {}", file.get_ref(), e),
+                )
+            }
+        }
+    }
+    if let Some(r) = cam.aperture_rotation {
+        scene_dat.lens.rotation = r;
+    }
+    if let Some(c) = &cam.cat_eye {
+        if *c.get_ref() < 0.0 {
+            return builder.invalid(c.span(), "cat_eye must be 0 or more".to_string());
+        }
+        scene_dat.lens.cat_eye = *c.get_ref();
+    }
+
+    let photographic = [&cam.f_stop, &cam.iso, &cam.sensor_height, &cam.scene_scale];
+    if photographic.iter().any(|v| v.is_some()) || cam.shutter.is_some() {
+        let mut p = lens::Photographic::new();
+        if let Some(f) = &cam.f_stop {
+            p.f_stop = builder.positive(f, "f_stop")?;
+        }
+        if let Some(t) = &cam.shutter {
+            p.shutter = builder.seconds(t, "shutter")?;
+        }
+        if let Some(s) = &cam.iso {
+            p.iso = builder.positive(s, "iso")?;
+        }
+        if let Some(h) = &cam.sensor_height {
+            p.sensor_height = builder.positive(h, "sensor_height")?;
+        }
+        if let Some(s) = &cam.scene_scale {
+            p.scene_scale = builder.positive(s, "scene_scale")?;
+        }
+        scene_dat.photographic = Some(p);
+    }
+
+    if let Some(keys) = &cam.keyframes {
+        let span = keys.span();
+        let keys: Vec<animation::CameraKey> = keys
+            .get_ref()
+            .iter()
+            .map(|k| animation::CameraKey {
+                frame: k.frame,
+                lookfrom: k.lookfrom.map_or(scene_dat.lookfrom, |v| to_vec3(&v)),
+                lookat: k.lookat.map_or(scene_dat.lookat, |v| to_vec3(&v)),
+                vup: k.vup.map_or(scene_dat.vup, |v| to_vec3(&v)),
+                vfov: k.vfov.unwrap_or(scene_dat.vfov),
+                aperture: k.aperture.unwrap_or(scene_dat.aperture),
+                dist_to_focus: k.dist_to_focus.unwrap_or(scene_dat.dist_to_focus),
+            })
+            .collect();
+        scene_dat.camera_path = match animation::CameraPath::new(keys) {
+            Some(p) => Some(p),
+            None => {
+                return builder.invalid(
+                    span,
+                    "camera keyframes must contain at least one key".to_string(),
+                )
+            }
+        };
+    }
+
+    Ok(world)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    const MATERIALS: &str = r#"
+[materials]
+white = { lambertian = { color = [0.73, 0.73, 0.73] } }
+"#;
+
+    /// Path in the temporary directory, unique to this process and test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rust_raytracer_{}_{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn build(
+        src: &str,
+        dir: PathBuf,
+    ) -> Result<(hittable_list::HittableList, SceneData), SceneFileErr> {
+        let mut scene_dat = SceneData::new();
+        build_scene(src, dir, &mut scene_dat).map(|world| (world, scene_dat))
+    }
+
+    /// Error message from building src, which must fail
+    fn error(src: &str) -> String {
+        match build(src, PathBuf::new()) {
+            Ok(_) => panic!("built a scene from\n{}", src),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    /// Distance along a ray from (0, 0, 10) towards the origin to the first thing hit in world
+    fn hit_distance(world: &hittable_list::HittableList) -> Option<f64> {
+        let r = Ray::new(&Point::new(0.0, 0.0, 10.0), &Vec3::new(0.0, 0.0, -1.0), 0.5);
+        let mut rec = HitRecord::new();
+        world.hit(&r, 0.0001, INFINITY, &mut rec).then_some(rec.t)
+    }
+
+    #[test]
+    fn builds_every_object_kind() {
+        let dir = temp_dir("objects");
+        std::fs::write(
+            dir.join("quad.obj"),
+            "v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\nf 1 2 3 4\n",
+        )
+        .unwrap();
+
+        let sphere = r#"{ sphere = { center = [0, 0, 0], radius = 1, material = "white" } }"#;
+        let cube = r#"{ box = { min = [-1, -1, -1], max = [1, 1, 1], material = "white" } }"#;
+        let objects = [
+            (sphere.to_string(), 9.0),
+            (
+                r#"{ moving_sphere = { center0 = [0, 0, -1], center1 = [0, 0, 1], radius = 1, material = "white" } }"#
+                    .to_string(),
+                9.0,
+            ),
+            (
+                r#"{ xy_rect = { x = [-1, 1], y = [-1, 1], k = 0, material = "white" } }"#
+                    .to_string(),
+                10.0,
+            ),
+            // The other rects lie along the ray, so are turned to face it
+            (
+                r#"{ rotate_x = { angle = 90, object = { xz_rect = { x = [-1, 1], z = [-1, 1], k = 0, material = "white" } } } }"#
+                    .to_string(),
+                10.0,
+            ),
+            (
+                r#"{ rotate_y = { angle = 90, object = { yz_rect = { y = [-1, 1], z = [-1, 1], k = 0, material = "white" } } } }"#
+                    .to_string(),
+                10.0,
+            ),
+            (
+                r#"{ triangle = { vertices = [[-1, -1, 0], [1, -1, 0], [0, 1, 0]], material = "white" } }"#
+                    .to_string(),
+                10.0,
+            ),
+            (
+                r#"{ mesh = { file = "quad.obj", material = "white" } }"#.to_string(),
+                10.0,
+            ),
+            (cube.to_string(), 9.0),
+            (
+                format!(
+                    "{{ constant_medium = {{ boundary = {}, density = 1e9, color = [1, 1, 1] }} }}",
+                    cube
+                ),
+                9.0,
+            ),
+            (
+                format!(
+                    "{{ transform = {{ transform = [{{ scale = [2, 2, 2] }}], object = {} }} }}",
+                    sphere
+                ),
+                8.0,
+            ),
+            (
+                r#"{ instance = { prototype = "ball", transform = [{ translate = [0, 0, 1] }] } }"#
+                    .to_string(),
+                8.0,
+            ),
+            (
+                format!(
+                    "{{ animated = {{ keyframes = [{{ time = 0 }}, {{ time = 1, translate = [0, 0, 2] }}], object = {} }} }}",
+                    sphere
+                ),
+                8.0,
+            ),
+            (
+                format!(
+                    "{{ translate = {{ offset = [0, 0, 1], object = {} }} }}",
+                    sphere
+                ),
+                8.0,
+            ),
+            (
+                format!("{{ rotate_z = {{ angle = 45, object = {} }} }}", cube),
+                9.0,
+            ),
+            (format!("{{ bvh = {{ objects = [{}] }} }}", sphere), 9.0),
+            (format!("{{ list = {{ objects = [{}] }} }}", sphere), 9.0),
+        ];
+
+        for (object, t) in objects {
+            let src = format!(
+                "{}\n[prototypes]\nball = {}\n\n[[objects]]\n{}",
+                MATERIALS,
+                sphere,
+                object.replacen('{', "", 1).trim_end_matches('}')
+            );
+            let (world, _) = match build(&src, dir.clone()) {
+                Ok(w) => w,
+                Err(e) => panic!("{}\n{}", e, src),
+            };
+            assert_eq!(world.objects.len(), 1);
+            let hit = hit_distance(&world).unwrap_or_else(|| panic!("missed {}", object));
+            assert!(
+                (hit - t).abs() < 1e-3,
+                "{} hit at {}, not {}",
+                object,
+                hit,
+                t
+            );
+        }
+    }
+
+    #[test]
+    fn lights_are_objects_that_are_also_sampled() {
+        let src = r#"
+[materials]
+light = { diffuse_light = { color = [4, 4, 4] } }
+
+[[lights]]
+xy_rect = { x = [-1, 1], y = [-1, 1], k = 0, material = "light" }
+
+[[punctual_lights]]
+point = { position = [0, 5, 0], intensity = [1, 1, 1] }
+
+[[punctual_lights]]
+spot = { position = [0, 5, 0], direction = [0, -1, 0], intensity = [1, 1, 1], angle = 30, falloff = 5 }
+
+[[punctual_lights]]
+directional = { direction = [0, -1, 0], intensity = [1, 1, 1] }
+
+[environment]
+sky = { sun_elevation = 30 }
+"#;
+        let (world, scene_dat) = build(src, PathBuf::new()).unwrap();
+        assert_eq!(world.objects.len(), 1);
+        assert_eq!(scene_dat.lights.objects.len(), 1);
+        assert_eq!(scene_dat.punctual_lights.len(), 3);
+        assert!(scene_dat.environment.is_some());
+    }
+
+    #[test]
+    fn builds_every_material_kind() {
+        let materials = [
+            "lambertian = { color = [0.5, 0.5, 0.5] }",
+            "lambertian = { texture = \"grey\" }",
+            "metal = { color = [0.9, 0.9, 0.9] }",
+            "metal = { color = [0.9, 0.9, 0.9], fuzz = 0.2 }",
+            "metal = { preset = \"gold\", roughness = 0.1 }",
+            "metal = { eta = [0.2, 0.9, 1.1], k = [3.9, 2.5, 2.4], roughness = [0.1, 0.4] }",
+            "dielectric = { ir = 1.5 }",
+            "dielectric = { preset = \"bk7\" }",
+            "dielectric = { cauchy = [1.5, 0.004] }",
+            "dielectric = { sellmeier = { b = [1.04, 0.23, 1.01], c = [0.006, 0.02, 103.6] } }",
+            "dielectric = { ir = 1.5, roughness = 0.2, absorption = [0.1, 0, 0] }",
+            "diffuse_light = { color = [4, 4, 4] }",
+            "isotropic = { color = [0.5, 0.5, 0.5] }",
+        ];
+        for m in materials {
+            let src = format!(
+                "[textures]\ngrey = {{ solid = {{ color = [0.5, 0.5, 0.5] }} }}\n\n[materials]\nm = {{ {} }}\n\n[[objects]]\nsphere = {{ center = [0, 0, 0], radius = 1, material = \"m\" }}\n",
+                m
+            );
+            if let Err(e) = build(&src, PathBuf::new()) {
+                panic!("{}: {}", m, e);
+            }
+        }
+    }
+
+    #[test]
+    fn builds_every_texture_kind() {
+        let dir = temp_dir("textures");
+        image::RgbImage::from_pixel(2, 2, image::Rgb([200, 100, 50]))
+            .save(dir.join("tile.png"))
+            .unwrap();
+
+        let textures = [
+            "solid = { color = [0.5, 0.5, 0.5] }",
+            "checker = { even = [0, 0, 0], odd = [1, 1, 1] }",
+            "checker = { even_texture = \"base\", odd = [1, 1, 1] }",
+            "noise = { scale = 4 }",
+            "turbulence = { color = [0.5, 0.5, 1] }",
+            "marble = {}",
+            "dual_marble = { color1 = [1, 0, 0], color2 = [0, 0, 1], weight = 0.5 }",
+            "fragment = { noises = 2, background = [1, 1, 1], line = [0, 0, 0], line_range = [0.4, 0.6], fragment_range = [0, 1] }",
+            "image = { file = \"tile.png\" }",
+        ];
+        for t in textures {
+            let src = format!(
+                "[textures]\nbase = {{ solid = {{ color = [0.2, 0.2, 0.2] }} }}\nt = {{ {} }}\n",
+                t
+            );
+            if let Err(e) = build(&src, dir.clone()) {
+                panic!("{}: {}", t, e);
+            }
+        }
+    }
+
+    #[test]
+    fn errors_give_the_line_at_fault() {
+        let e = error(
+            r#"
+[[objects]]
+sphere = { center = [0, 0, 0], radius = 1, material = "missing" }
+"#,
+        );
+        assert_eq!(e, "line 3: unknown material 'missing'");
+
+        let e = error(
+            r#"
+[[objects]]
+sphere = { center = [0, 0, 0], radius = 1, material = "white", colour = [1, 0, 0] }
+"#,
+        );
+        assert!(e.contains("line 3"), "{}", e);
+        assert!(e.contains("unexpected keys in table: colour"), "{}", e);
+
+        let e = error(&format!(
+            "{}\n[[objects]]\nxz_rect = {{ x = [1, -1], z = [-1, 1], k = 0, material = \"white\" }}\n",
+            MATERIALS
+        ));
+        assert_eq!(
+            e,
+            "line 6: rect x must go from its low edge to its high edge"
+        );
+
+        let e = error(&format!(
+            "{}\n[[objects]]\nsphere = {{ center = [0, 0, 0], radius = 0, material = \"white\" }}\n",
+            MATERIALS
+        ));
+        assert_eq!(e, "line 6: sphere radius must be positive");
+
+        let e = error(&format!(
+            "{}\n[[objects]]\nbvh = {{ objects = [] }}\n",
+            MATERIALS
+        ));
+        assert_eq!(e, "line 6: bvh must contain at least one object");
+    }
+}
@@ -0,0 +1,354 @@
+//! Analytic daylight sky, after Preetham, Shirley and Smits' "A Practical Analytic Model for Daylight"
+
+use crate::{environment::Environment, onb::ONB, spectrum::xyz_to_rgb, util::*, vec3::*};
+
+// Angular radius of the real sun in degrees
+pub const SUN_RADIUS: f64 = 0.27;
+// Luminance of the sun's disk above the atmosphere, in thousands of candela per square meter
+const SUN_LUMINANCE: f64 = 2.0e6;
+// Steps across the hemisphere when totalling the light reaching the ground
+const IRRADIANCE_STEPS: u32 = 64;
+
+/// Settings of a sky, with angles in degrees
+#[derive(Debug, Clone, Copy)]
+pub struct SkySettings {
+    /// Height of the sun above the horizon, from 0 to 90
+    pub sun_elevation: f64,
+    /// Bearing of the sun, from -z towards +x
+    pub sun_azimuth: f64,
+    /// Haziness of the air, from 2 for a very clear sky to 10 for a hazy one
+    pub turbidity: f64,
+    /// Color of the ground seen below the horizon
+    pub ground_albedo: Color,
+    /// Angular radius of the sun's disk. A larger sun gives softer shadows, with the same total light
+    pub sun_radius: f64,
+    /// Scale of the radiance, which is in thousands of candela per square meter at 1
+    pub intensity: f64,
+}
+
+impl SkySettings {
+    /// Clear afternoon sky over grey ground, scaled so the sky is about as bright as the built-in backgrounds
+    pub fn new() -> SkySettings {
+        SkySettings {
+            sun_elevation: 45.0,
+            sun_azimuth: 30.0,
+            turbidity: 3.0,
+            ground_albedo: Color::new(0.3, 0.3, 0.3),
+            sun_radius: SUN_RADIUS,
+            intensity: 0.1,
+        }
+    }
+}
+
+/**
+ * Preetham daylight sky with the sun's disk, and the ground below the horizon.
+ *
+ * The sky's brightness and color follow the Perez distribution fitted to the sun's position and the turbidity. The
+ * sun is dimmed and reddened by the air it shines through, and the ground reflects the light of both. Half the
+ * sampled directions go towards the sun, so it lights the scene like a small, distant lamp with soft shadows.
+ */
+pub struct PreethamSky {
+    sun: Vec3,
+    sun_frame: ONB,
+    // Cosine of the sun's angular radius
+    sun_cos: f64,
+    sun_radiance: Color,
+    ground: Color,
+    intensity: f64,
+    // Sun zenith angle, and the Perez coefficients and zenith value of luminance and chromaticity x and y
+    theta_sun: f64,
+    perez: [[f64; 5]; 3],
+    zenith: [f64; 3],
+}
+
+impl PreethamSky {
+    pub fn new(settings: &SkySettings) -> PreethamSky {
+        let elevation = degs_to_rads(settings.sun_elevation);
+        let azimuth = degs_to_rads(settings.sun_azimuth);
+        let sun = Vec3::new(
+            f64::cos(elevation) * f64::sin(azimuth),
+            f64::sin(elevation),
+            -f64::cos(elevation) * f64::cos(azimuth),
+        );
+
+        let t = settings.turbidity;
+        let theta_sun = PI / 2.0 - elevation;
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_y = (4.0453 * t - 4.9710) * f64::tan(chi) - 0.2155 * t + 2.4192;
+        let (th, th2, th3) = (theta_sun, theta_sun * theta_sun, theta_sun.powi(3));
+        let zenith_x = t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_yc = t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        // The sun's total light is kept as its size changes, so only the sharpness of shadows does
+        let solid_angle = |radius: f64| 2.0 * PI * (1.0 - f64::cos(degs_to_rads(radius)));
+        let sun_cos = f64::cos(degs_to_rads(settings.sun_radius));
+        let sun_radiance = solid_angle(SUN_RADIUS) / solid_angle(settings.sun_radius)
+            * SUN_LUMINANCE
+            * sun_transmittance(theta_sun, t);
+
+        let mut sky = PreethamSky {
+            sun,
+            sun_frame: ONB::build_from_w(&sun),
+            sun_cos,
+            sun_radiance,
+            ground: Color::new_e(),
+            intensity: settings.intensity,
+            theta_sun,
+            perez,
+            zenith: [f64::max(0.0, zenith_y), zenith_x, zenith_yc],
+        };
+
+        // The ground is lit by the sun and the whole sky, and reflects it evenly
+        let sun_light =
+            solid_angle(settings.sun_radius) * f64::max(0.0, sun.y()) * sky.sun_radiance;
+        sky.ground = settings.ground_albedo * (sun_light + sky.sky_irradiance()) / PI;
+        sky
+    }
+
+    /// Perez distribution for one of luminance, x or y, at zenith angle theta and angle gamma from the sun
+    fn perez(&self, which: usize, cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.perez[which];
+        let f = |cos_theta: f64, gamma: f64| {
+            (1.0 + a * f64::exp(b / cos_theta))
+                * (1.0 + c * f64::exp(d * gamma) + e * f64::cos(gamma).powi(2))
+        };
+        self.zenith[which] * f(cos_theta, gamma) / f(1.0, self.theta_sun)
+    }
+
+    /// Radiance of the sky alone, without the sun, towards unit direction d above the horizon
+    fn sky(&self, d: &Vec3) -> Color {
+        // Rays just above the horizon take the horizon's value, where the model's exponent would blow up
+        let cos_theta = f64::max(d.y(), 0.01);
+        let gamma = f64::acos(clamp(dot(*d, self.sun), -1.0, 1.0));
+
+        let lum = self.perez(0, cos_theta, gamma);
+        let x = self.perez(1, cos_theta, gamma);
+        let y = self.perez(2, cos_theta, gamma);
+        xyy_to_rgb(x, y, lum)
+    }
+
+    /// Light falling on level ground from the sky, without the sun, by summing over the hemisphere
+    fn sky_irradiance(&self) -> Color {
+        let mut sum = Color::new_e();
+        let (steps_theta, steps_phi) = (IRRADIANCE_STEPS / 2, IRRADIANCE_STEPS);
+        let (d_theta, d_phi) = (0.5 * PI / steps_theta as f64, 2.0 * PI / steps_phi as f64);
+        for i in 0..steps_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..steps_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let d = Vec3::new(
+                    f64::sin(theta) * f64::cos(phi),
+                    f64::cos(theta),
+                    f64::sin(theta) * f64::sin(phi),
+                );
+                // Radiance times the cosine to the ground's normal, over the solid angle of the step
+                sum += (f64::cos(theta) * f64::sin(theta) * d_theta * d_phi) * self.sky(&d);
+            }
+        }
+        sum
+    }
+}
+
+impl Environment for PreethamSky {
+    fn value(&self, direction: &Vec3) -> Color {
+        let d = unit_vector(*direction);
+        if d.y() < 0.0 {
+            return self.intensity * self.ground;
+        }
+
+        let mut value = self.sky(&d);
+        if dot(d, self.sun) >= self.sun_cos {
+            value += self.sun_radiance;
+        }
+        self.intensity * value
+    }
+
+    fn pdf_value(&self, direction: &Vec3) -> f64 {
+        let d = unit_vector(*direction);
+        let sun_pdf = if dot(d, self.sun) >= self.sun_cos {
+            1.0 / (2.0 * PI * (1.0 - self.sun_cos))
+        } else {
+            0.0
+        };
+        0.5 * sun_pdf + 0.5 / (4.0 * PI)
+    }
+
+    fn random(&self) -> Vec3 {
+        if random_double() < 0.5 {
+            // Uniform over the cone of the sun's disk
+            let z = 1.0 - random_double() * (1.0 - self.sun_cos);
+            let phi = 2.0 * PI * random_double();
+            let r = f64::sqrt(1.0 - z * z);
+            self.sun_frame
+                .local(r * f64::cos(phi), r * f64::sin(phi), z)
+        } else {
+            random_unit_vector()
+        }
+    }
+}
+
+/**
+ * Fraction of the sun's light in red, green and blue that passes through the air at sun zenith angle theta.
+ *
+ * Rayleigh scattering by the air and scattering by haze, from Preetham's appendix, at a wavelength typical of each
+ * channel.
+ */
+fn sun_transmittance(theta: f64, turbidity: f64) -> Color {
+    // Relative length of the path through the air, compared to straight up
+    let theta_deg = rads_to_degs(theta);
+    let mass = 1.0 / (f64::cos(theta) + 0.15 * f64::powf(93.885 - theta_deg, -1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+
+    let channel = |lambda: f64| {
+        let rayleigh = f64::exp(-0.008735 * lambda.powf(-4.08) * mass);
+        let haze = f64::exp(-beta * lambda.powf(-1.3) * mass);
+        rayleigh * haze
+    };
+    // Wavelengths in micrometers
+    Color::new(channel(0.61), channel(0.55), channel(0.465))
+}
+
+/// Linear sRGB of CIE chromaticity x and y with luminance lum
+fn xyy_to_rgb(x: f64, y: f64, lum: f64) -> Color {
+    if y <= 0.0 {
+        return Color::new_e();
+    }
+    let c = xyz_to_rgb(x * lum / y, lum, (1.0 - x - y) * lum / y);
+    Color::new(
+        f64::max(0.0, c.x()),
+        f64::max(0.0, c.y()),
+        f64::max(0.0, c.z()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::luminance;
+
+    fn sky(sun_elevation: f64, turbidity: f64) -> PreethamSky {
+        PreethamSky::new(&SkySettings {
+            sun_elevation,
+            turbidity,
+            ..SkySettings::new()
+        })
+    }
+
+    #[test]
+    fn zenith_is_finite_and_positive() {
+        for t in 2..=10 {
+            for elevation in [5.0, 15.0, 30.0, 45.0, 60.0, 75.0, 90.0] {
+                let s = sky(elevation, t as f64);
+                for z in s.zenith {
+                    assert!(
+                        z.is_finite() && z > 0.0,
+                        "turbidity {} at {}: {:?}",
+                        t,
+                        elevation,
+                        s.zenith
+                    );
+                }
+                let up = s.value(&Vec3::new(0.0, 1.0, 0.0));
+                for c in 0..3 {
+                    assert!(
+                        up[c].is_finite() && up[c] > 0.0,
+                        "turbidity {} at {}: {}",
+                        t,
+                        elevation,
+                        up
+                    );
+                }
+                assert!(s.sun_radiance.x().is_finite() && s.sun_radiance.x() > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn sky_is_brightest_towards_the_sun() {
+        seed_rng(1);
+        for (elevation, azimuth, t) in [(20.0, 30.0, 2.0), (45.0, -100.0, 3.0), (60.0, 170.0, 8.0)]
+        {
+            let s = PreethamSky::new(&SkySettings {
+                sun_elevation: elevation,
+                sun_azimuth: azimuth,
+                turbidity: t,
+                ..SkySettings::new()
+            });
+            let towards = |elevation: f64, azimuth: f64| {
+                let (e, a) = (degs_to_rads(elevation), degs_to_rads(azimuth));
+                Vec3::new(
+                    f64::cos(e) * f64::sin(a),
+                    f64::sin(e),
+                    -f64::cos(e) * f64::cos(a),
+                )
+            };
+
+            // Around the sky at the sun's height, the light fades the further round from the sun, until the darkest
+            // part of the sky about a right angle away
+            let mut last = INFINITY;
+            for step in 1..=40 {
+                let l = luminance(s.value(&towards(elevation, azimuth + 2.0 * step as f64)));
+                assert!(
+                    l < last,
+                    "brightening {} degrees round from the sun",
+                    2 * step
+                );
+                last = l;
+            }
+
+            // The brightest of the sky away from the sun's disk is around it, as the horizon only brightens a little
+            let (mut brightest, mut direction) = (0.0, Vec3::new_e());
+            for _ in 0..20_000 {
+                let d = random_unit_vector();
+                if d.y() <= 0.0 || dot(d, s.sun) >= s.sun_cos {
+                    continue;
+                }
+                let l = luminance(s.value(&d));
+                if l > brightest {
+                    (brightest, direction) = (l, d);
+                }
+            }
+            let off = rads_to_degs(f64::acos(dot(direction, s.sun)));
+            assert!(off < 15.0, "brightest {} degrees from the sun", off);
+
+            // The disk itself outshines all of it
+            assert!(luminance(s.value(&s.sun)) > 100.0 * brightest);
+        }
+    }
+
+    #[test]
+    fn haze_reddens_the_sun() {
+        let (clear, hazy) = (sky(10.0, 2.0), sky(10.0, 10.0));
+        let ratio = |s: &PreethamSky| s.sun_radiance.z() / s.sun_radiance.x();
+        assert!(luminance(hazy.sun_radiance) < luminance(clear.sun_radiance));
+        assert!(ratio(&hazy) < ratio(&clear));
+    }
+}
@@ -0,0 +1,293 @@
+//! Spectral rendering: sampled wavelengths, RGB to spectrum conversion and back, and refractive indices that vary
+//! with wavelength
+
+use crate::{util::*, vec3::*};
+
+// Visible wavelengths sampled, in nanometers
+const LAMBDA_MIN: f64 = 380.0;
+const LAMBDA_MAX: f64 = 780.0;
+// Wavelength that indices of refraction are quoted at, the sodium D line, used when rendering without wavelengths
+const LAMBDA_D: f64 = 587.6;
+
+// Smits' spectra for "An RGB to Spectrum Conversion for Reflectances", in 10 even bins from 380 to 720 nm
+const SMITS_MIN: f64 = 380.0;
+const SMITS_MAX: f64 = 720.0;
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/**
+ * Wavelengths carried by one camera sample, in nanometers.
+ *
+ * The first, the hero, is uniformly random, and the others are spaced evenly after it, wrapping around the visible
+ * range. While tracing, a Color holds the value at each of these wavelengths in turn rather than red, green and blue.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct Wavelengths {
+    lambda: [f64; 3],
+    // Whether the path has scattered in a direction only the hero would take, so the others were dropped
+    secondary_terminated: bool,
+}
+
+impl Wavelengths {
+    pub fn sample() -> Wavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = random_double() * range;
+        let lambda = [0.0, 1.0, 2.0].map(|i| LAMBDA_MIN + (hero + i * range / 3.0) % range);
+        Wavelengths {
+            lambda,
+            secondary_terminated: false,
+        }
+    }
+
+    /// Wavelength that decides directions where they depend on the wavelength
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    /// Values at each wavelength of a smooth spectrum with the given linear RGB color
+    pub fn spectrum_of(&self, c: &Color) -> Color {
+        let s = |i: usize| rgb_to_spectrum(c, self.lambda[i]);
+        Color::new(s(0), s(1), s(2))
+    }
+
+    /**
+     * Linear RGB color of values s at each wavelength.
+     *
+     * The result is balanced so a flat spectrum of 1 comes back as white, like the RGB colors it was made from.
+     * A few wavelengths are more saturated than any RGB color, so components can be negative. They cancel out as
+     * samples are averaged, so are left for the image to clamp.
+     */
+    pub fn rgb_of(&self, s: &Color) -> Color {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for (i, &lambda) in self.lambda.iter().enumerate() {
+            // Each wavelength is one of three uniform samples across the range
+            let weight = s[i] * range / (3.0 * CIE_Y_INTEGRAL);
+            x += weight * cie_x(lambda);
+            y += weight * cie_y(lambda);
+            z += weight * cie_z(lambda);
+        }
+
+        let c = xyz_to_rgb(x, y, z);
+        let white = xyz_to_rgb(
+            CIE_X_INTEGRAL / CIE_Y_INTEGRAL,
+            1.0,
+            CIE_Z_INTEGRAL / CIE_Y_INTEGRAL,
+        );
+        Color::new(c.x() / white.x(), c.y() / white.y(), c.z() / white.z())
+    }
+
+    pub fn secondary_terminated(&self) -> bool {
+        self.secondary_terminated
+    }
+
+    /// The same wavelengths after scattering in a direction only the hero would take
+    pub fn terminate_secondary(self) -> Wavelengths {
+        Wavelengths {
+            secondary_terminated: true,
+            ..self
+        }
+    }
+
+    /// Keeps only the hero's value of s, where terminate_secondary dropped the others
+    pub fn hero_only(s: &Color) -> Color {
+        // The hero alone is a full estimate, rather than one of three to average
+        Color::new(3.0 * s.x(), 0.0, 0.0)
+    }
+}
+
+/// Value at wavelength lambda of Smits' smooth spectrum for linear RGB color c
+fn rgb_to_spectrum(c: &Color, lambda: f64) -> f64 {
+    let bin = ((lambda - SMITS_MIN) / (SMITS_MAX - SMITS_MIN) * 10.0) as i64;
+    let bin = bin.clamp(0, 9) as usize;
+    let (r, g, b) = (c.x(), c.y(), c.z());
+
+    // The smallest component is white, and the rest is made of the primaries and their complements
+    if r <= g && r <= b {
+        if g <= b {
+            r * SMITS_WHITE[bin] + (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+        } else {
+            r * SMITS_WHITE[bin] + (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+        }
+    } else if g <= r && g <= b {
+        if r <= b {
+            g * SMITS_WHITE[bin] + (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+        } else {
+            g * SMITS_WHITE[bin] + (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+        }
+    } else if r <= g {
+        b * SMITS_WHITE[bin] + (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+    } else {
+        b * SMITS_WHITE[bin] + (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+    }
+}
+
+// Integrals of the fitted matching functions over the sampled wavelengths, in nanometers
+const CIE_X_INTEGRAL: f64 = 106.7650;
+const CIE_Y_INTEGRAL: f64 = 106.9197;
+const CIE_Z_INTEGRAL: f64 = 106.8253;
+
+/// Gaussian with a different width either side of its peak
+fn lobe(lambda: f64, mu: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let sigma = if lambda < mu {
+        sigma_below
+    } else {
+        sigma_above
+    };
+    let t = (lambda - mu) / sigma;
+    f64::exp(-0.5 * t * t)
+}
+
+// CIE 1931 2 degree color matching functions, as fitted by Wyman, Sloan and Shirley's "Simple Analytic
+// Approximations to the CIE XYZ Color Matching Functions"
+fn cie_x(lambda: f64) -> f64 {
+    1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(lambda, 501.1, 20.4, 26.2)
+}
+
+fn cie_y(lambda: f64) -> f64 {
+    0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1)
+}
+
+fn cie_z(lambda: f64) -> f64 {
+    1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8)
+}
+
+/// Linear sRGB of CIE XYZ, which may be negative for colors outside sRGB
+pub fn xyz_to_rgb(x: f64, y: f64, z: f64) -> Color {
+    Color::new(
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    )
+}
+
+arg_enum! {
+    /// Public GlassPreset enum of materials with measured Sellmeier coefficients
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum GlassPreset {
+        Bk7,
+        FusedSilica,
+        Diamond,
+    }
+}
+
+impl GlassPreset {
+    pub fn ior(self) -> Ior {
+        match self {
+            GlassPreset::Bk7 => Ior::Sellmeier {
+                b: [1.03961212, 0.231792344, 1.01046945],
+                c: [0.00600069867, 0.0200179144, 103.560653],
+            },
+            GlassPreset::FusedSilica => Ior::Sellmeier {
+                b: [0.6961663, 0.4079426, 0.8974794],
+                c: [0.0046791, 0.0135121, 97.934],
+            },
+            GlassPreset::Diamond => Ior::Sellmeier {
+                b: [4.3356, 0.3306, 0.0],
+                c: [0.011236, 0.030625, 0.0],
+            },
+        }
+    }
+}
+
+/// Index of refraction, which may vary with wavelength, giving dispersion in spectral renders
+#[derive(Debug, Clone, Copy)]
+pub enum Ior {
+    Constant(f64),
+    /// a + b / lambda^2, with lambda in micrometers
+    Cauchy {
+        a: f64,
+        b: f64,
+    },
+    /// Square root of 1 + sum of b lambda^2 / (lambda^2 - c), with lambda in micrometers
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+impl Ior {
+    /// Index at wavelength in nanometers, or at the sodium D line for renders without wavelengths
+    pub fn at(&self, wavelength: Option<f64>) -> f64 {
+        let um = wavelength.unwrap_or(LAMBDA_D) / 1000.0;
+        let um2 = um * um;
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / um2,
+            Ior::Sellmeier { b, c } => {
+                let sum: f64 = (0..3).map(|i| b[i] * um2 / (um2 - c[i])).sum();
+                f64::sqrt(1.0 + sum)
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Average over n seeded samples of the RGB color that spectrum_of and rgb_of give back for c
+    fn round_trip(c: Color, n: usize) -> Color {
+        seed_rng(11);
+        let mut sum = Color::new_e();
+        for _ in 0..n {
+            let w = Wavelengths::sample();
+            sum += w.rgb_of(&w.spectrum_of(&c));
+        }
+        sum / n as f64
+    }
+
+    #[test]
+    fn grey_comes_back_grey() {
+        for g in [0.18, 0.5, 1.0] {
+            let c = round_trip(Color::new(g, g, g), 20000);
+            for i in 0..3 {
+                assert!((c[i] - g).abs() < 0.02 * g, "{} comes back as {:?}", g, c);
+            }
+        }
+    }
+
+    #[test]
+    fn saturated_colors_average_back_to_themselves() {
+        // Single wavelengths of these give negative components, which have to be kept for the average to come out
+        for c in [
+            Color::new(0.8, 0.1, 0.1),
+            Color::new(0.1, 0.8, 0.1),
+            Color::new(0.1, 0.1, 0.8),
+        ] {
+            let back = round_trip(c, 20000);
+            for i in 0..3 {
+                assert!(
+                    (back[i] - c[i]).abs() < 0.05,
+                    "{:?} comes back as {:?}",
+                    c,
+                    back
+                );
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{aabb::AABB, hittable::*, materials::*, ray::Ray, util::*, vec3::*};
+use crate::{aabb::AABB, hittable::*, materials::*, onb::ONB, ray::Ray, util::*, vec3::*};
 use std::f64;
 use std::sync::Arc;
 
@@ -77,4 +77,33 @@ impl Hittable for Sphere {
         );
         (true, out_box)
     }
+
+    fn pdf_value(&self, o: &Point, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(o, v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+
+        // From inside the sphere every direction hits it, and random samples uniformly
+        let distance_squared = (self.center - *o).length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return 1.0 / (4.0 * PI);
+        }
+
+        let cos_theta_max = f64::sqrt(1.0 - self.radius * self.radius / distance_squared);
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+
+        1.0 / solid_angle
+    }
+
+    fn random(&self, o: &Point) -> Vec3 {
+        let direction = self.center - *o;
+        let distance_squared = direction.length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return random_unit_vector();
+        }
+
+        let uvw = ONB::build_from_w(&direction);
+        uvw.local_v(&random_to_sphere(self.radius, distance_squared))
+    }
 }
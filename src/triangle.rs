@@ -0,0 +1,245 @@
+use crate::{aabb::AABB, hittable::*, materials::*, ray::Ray, util::*, vec3::*};
+use std::f64;
+use std::sync::Arc;
+
+pub struct Triangle {
+    v0: Point,
+    v1: Point,
+    v2: Point,
+    normals: Option<[Vec3; 3]>,
+    uvs: [(f64, f64); 3],
+    mat_ptr: Arc<dyn Material + Sync + Send>,
+}
+
+impl Triangle {
+    /// Flat shaded triangle, with u and v set to the barycentric coordinates of the hit point
+    pub fn new(v0: Point, v1: Point, v2: Point, mat: Arc<dyn Material + Sync + Send>) -> Triangle {
+        Triangle::new_full([v0, v1, v2], None, None, mat)
+    }
+
+    /**
+     * Creates a triangle from vertices given counter-clockwise when seen from the front.
+     *
+     * If normals are given they are interpolated across the face for smooth shading, otherwise the face is flat.
+     * If uvs are given they are interpolated for texture lookups, otherwise u and v are the barycentric coordinates.
+     */
+    pub fn new_full(
+        verts: [Point; 3],
+        normals: Option<[Vec3; 3]>,
+        uvs: Option<[(f64, f64); 3]>,
+        mat: Arc<dyn Material + Sync + Send>,
+    ) -> Triangle {
+        Triangle {
+            v0: verts[0],
+            v1: verts[1],
+            v2: verts[2],
+            normals: normals.map(|n| [unit_vector(n[0]), unit_vector(n[1]), unit_vector(n[2])]),
+            uvs: uvs.unwrap_or([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]),
+            mat_ptr: mat,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        // Moller-Trumbore intersection, solving for t and the barycentric coordinates b1 and b2 directly
+        let edge1 = self.v1 - self.v0;
+        let edge2 = self.v2 - self.v0;
+        let pvec = cross(r.direction(), edge2);
+        let det = dot(edge1, pvec);
+
+        // Ray is parallel to the triangle's plane
+        if det.abs() < 1e-12 {
+            return false;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = r.origin() - self.v0;
+        let b1 = dot(tvec, pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return false;
+        }
+
+        let qvec = cross(tvec, edge1);
+        let b2 = dot(r.direction(), qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return false;
+        }
+
+        let t = dot(edge2, qvec) * inv_det;
+        if t < t_min || t > t_max {
+            return false;
+        }
+
+        let b0 = 1.0 - b1 - b2;
+
+        rec.t = t;
+        rec.p = r.at(t);
+
+        let outward_normal = unit_vector(cross(edge1, edge2));
+        rec.set_face_normal(r, &outward_normal);
+
+        // Shading normal keeps the geometric normal's orientation, so front_face still reflects the winding
+        if let Some(n) = &self.normals {
+            let mut shading = unit_vector(b0 * n[0] + b1 * n[1] + b2 * n[2]);
+            if dot(shading, outward_normal) < 0.0 {
+                shading = -shading;
+            }
+            rec.normal = if rec.front_face { shading } else { -shading };
+        }
+
+        rec.u = b0 * self.uvs[0].0 + b1 * self.uvs[1].0 + b2 * self.uvs[2].0;
+        rec.v = b0 * self.uvs[0].1 + b1 * self.uvs[1].1 + b2 * self.uvs[2].1;
+        rec.mat_ptr = Arc::clone(&self.mat_ptr);
+
+        true
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> (bool, AABB) {
+        // Axis aligned triangles have a flat box, so pad every dimension a small amount
+        let pad = Vec3::new(0.0001, 0.0001, 0.0001);
+        let min = Point::new(
+            f64::min(self.v0.x(), f64::min(self.v1.x(), self.v2.x())),
+            f64::min(self.v0.y(), f64::min(self.v1.y(), self.v2.y())),
+            f64::min(self.v0.z(), f64::min(self.v1.z(), self.v2.z())),
+        );
+        let max = Point::new(
+            f64::max(self.v0.x(), f64::max(self.v1.x(), self.v2.x())),
+            f64::max(self.v0.y(), f64::max(self.v1.y(), self.v2.y())),
+            f64::max(self.v0.z(), f64::max(self.v1.z(), self.v2.z())),
+        );
+
+        (true, AABB::new(&(min - pad), &(max + pad)))
+    }
+
+    fn pdf_value(&self, o: &Point, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(o, v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+
+        let n = cross(self.v1 - self.v0, self.v2 - self.v0);
+        let area = 0.5 * n.length();
+        let distance_squared = rec.t * rec.t * v.length_squared();
+        let cosine = f64::abs(dot(*v, unit_vector(n)) / v.length());
+
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, o: &Point) -> Vec3 {
+        // Uniform over the triangle's area, folding the unit square onto the triangle
+        let mut b1 = random_double();
+        let mut b2 = random_double();
+        if b1 + b2 > 1.0 {
+            b1 = 1.0 - b1;
+            b2 = 1.0 - b2;
+        }
+
+        let random_point = self.v0 + b1 * (self.v1 - self.v0) + b2 * (self.v2 - self.v0);
+        random_point - *o
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> Triangle {
+        Triangle::new(
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(1.0, 0.0, 0.0),
+            Point::new(0.0, 1.0, 0.0),
+            Arc::new(NoHit::new()),
+        )
+    }
+
+    fn hit(tri: &Triangle, orig: Point, dir: Vec3) -> Option<HitRecord> {
+        let mut rec = HitRecord::new();
+        if tri.hit(&Ray::new(&orig, &dir, 0.0), 0.001, INFINITY, &mut rec) {
+            Some(rec)
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn hits_inside_with_barycentric_uv() {
+        let rec = hit(
+            &triangle(),
+            Point::new(0.25, 0.5, 2.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        )
+        .expect("ray through the face should hit");
+        assert!((rec.t - 2.0).abs() < 1e-12);
+        assert!((rec.p - Point::new(0.25, 0.5, 0.0)).length() < 1e-12);
+        assert!((rec.u - 0.25).abs() < 1e-12);
+        assert!((rec.v - 0.5).abs() < 1e-12);
+        assert!(rec.front_face);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-12);
+    }
+
+    #[test]
+    fn back_face_hit_flips_normal() {
+        let rec = hit(
+            &triangle(),
+            Point::new(0.25, 0.25, -1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        )
+        .expect("ray through the back should hit");
+        assert!(!rec.front_face);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-12);
+    }
+
+    #[test]
+    fn misses_outside_each_edge() {
+        let tri = triangle();
+        let down = Vec3::new(0.0, 0.0, -1.0);
+        assert!(hit(&tri, Point::new(-0.1, 0.5, 1.0), down).is_none());
+        assert!(hit(&tri, Point::new(0.5, -0.1, 1.0), down).is_none());
+        assert!(hit(&tri, Point::new(0.6, 0.6, 1.0), down).is_none());
+    }
+
+    #[test]
+    fn misses_parallel_and_out_of_range() {
+        let tri = triangle();
+        // In the triangle's plane
+        assert!(hit(&tri, Point::new(-1.0, 0.25, 0.0), Vec3::new(1.0, 0.0, 0.0)).is_none());
+        // Triangle is behind the ray
+        assert!(hit(&tri, Point::new(0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, 1.0)).is_none());
+        // Triangle is beyond t_max
+        let mut rec = HitRecord::new();
+        let r = Ray::new(
+            &Point::new(0.25, 0.25, 2.0),
+            &Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        assert!(!tri.hit(&r, 0.001, 1.5, &mut rec));
+    }
+
+    #[test]
+    fn smooth_normals_are_interpolated() {
+        let n = [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        let tri = Triangle::new_full(
+            [
+                Point::new(0.0, 0.0, 0.0),
+                Point::new(1.0, 0.0, 0.0),
+                Point::new(0.0, 1.0, 0.0),
+            ],
+            Some(n),
+            None,
+            Arc::new(NoHit::new()),
+        );
+        let rec = hit(
+            &tri,
+            Point::new(1.0, 0.0, 1.0) * 0.5,
+            Vec3::new(0.0, 0.0, -1.0),
+        )
+        .unwrap();
+        let expected = unit_vector(0.5 * unit_vector(n[0]) + 0.5 * unit_vector(n[1]));
+        assert!((rec.normal - expected).length() < 1e-12);
+    }
+}
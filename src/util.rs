@@ -12,16 +12,45 @@ pub fn rads_to_degs(rad: f64) -> f64 {
     rad * 180.0 / PI
 }
 
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    // Every random value is drawn from this, so reseeding it makes the values that follow reproducible
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Reseeds the current thread's random number generator
+pub fn seed_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// Gets a seed from the operating system, for runs where none is given
+pub fn entropy_seed() -> u64 {
+    StdRng::from_entropy().gen()
+}
+
+/**
+ * Derives an independent seed for stream from seed, such as one per pixel.
+ *
+ * Uses the SplitMix64 finalizer, so neighbouring streams get unrelated seeds.
+ */
+pub fn stream_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed ^ stream.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
 
 pub fn random_double() -> f64 {
     // Gets a random double in [0,1)
-    rand::thread_rng().gen_range(0.0..1.0)
+    RNG.with(|rng| rng.borrow_mut().gen_range(0.0..1.0))
 }
 
 // Gets a random double in [min,max). panics if min >= max
 pub fn random_double_range(min: f64, max: f64) -> f64 {
-    rand::thread_rng().gen_range(min..max)
+    RNG.with(|rng| rng.borrow_mut().gen_range(min..max))
 }
 
 pub fn random_int_range(min: i32, max: i32) -> i32 {
@@ -37,3 +66,71 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     }
     x
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequence(seed: u64, stream: u64) -> Vec<f64> {
+        seed_rng(stream_seed(seed, stream));
+        (0..16).map(|_| random_double()).collect()
+    }
+
+    #[test]
+    fn same_seed_and_stream_repeat() {
+        for (seed, stream) in [(0, 0), (1, 7), (42, 1 << 40), (u64::MAX, u64::MAX)] {
+            let first = sequence(seed, stream);
+            random_double();
+            assert_eq!(sequence(seed, stream), first);
+
+            // Each thread has its own generator, so another thread sees the same sequence
+            let other = std::thread::spawn(move || sequence(seed, stream))
+                .join()
+                .unwrap();
+            assert_eq!(other, first);
+        }
+    }
+
+    #[test]
+    fn nearby_streams_differ() {
+        const STREAMS: u64 = 4096;
+        for seed in [0, 1, 42] {
+            let mut seeds: Vec<u64> = (0..STREAMS).map(|s| stream_seed(seed, s)).collect();
+
+            // Neighbouring streams, and the same stream under neighbouring seeds, differ in about half their bits
+            let bits = |a: u64, b: u64| (a ^ b).count_ones() as f64;
+            let apart = (0..STREAMS)
+                .map(|s| bits(stream_seed(seed, s), stream_seed(seed, s + 1)))
+                .sum::<f64>()
+                / STREAMS as f64;
+            assert!((apart - 32.0).abs() < 1.0, "{} bits apart", apart);
+            let apart = (0..STREAMS)
+                .map(|s| bits(stream_seed(seed, s), stream_seed(seed + 1, s)))
+                .sum::<f64>()
+                / STREAMS as f64;
+            assert!((apart - 32.0).abs() < 1.0, "{} bits apart", apart);
+
+            seeds.sort_unstable();
+            seeds.dedup();
+            assert_eq!(seeds.len(), STREAMS as usize);
+
+            // Their sequences start evenly spread, with no correlation between neighbours
+            let first: Vec<f64> = (0..STREAMS).map(|s| sequence(seed, s)[0]).collect();
+            let mean = first.iter().sum::<f64>() / STREAMS as f64;
+            assert!((mean - 0.5).abs() < 0.02);
+            let covariance = first
+                .windows(2)
+                .map(|w| (w[0] - 0.5) * (w[1] - 0.5))
+                .sum::<f64>()
+                / (STREAMS - 1) as f64;
+            assert!(
+                (covariance * 12.0).abs() < 0.05,
+                "correlation {}",
+                covariance * 12.0
+            );
+            for w in first.windows(2) {
+                assert_ne!(w[0], w[1]);
+            }
+        }
+    }
+}
@@ -86,6 +86,32 @@ pub fn random_in_unit_disk() -> Vec3 {
     }
 }
 
+/// Random direction in the hemisphere about +z, with density proportional to the cosine from +z
+pub fn random_cosine_direction() -> Vec3 {
+    let r1 = random_double();
+    let r2 = random_double();
+    let z = f64::sqrt(1.0 - r2);
+
+    let phi = 2.0 * PI * r1;
+    let x = f64::cos(phi) * f64::sqrt(r2);
+    let y = f64::sin(phi) * f64::sqrt(r2);
+
+    Vec3::new(x, y, z)
+}
+
+/// Random direction about +z towards a sphere of the given radius, whose center is distance_squared away along +z
+pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
+    let r1 = random_double();
+    let r2 = random_double();
+    let z = 1.0 + r2 * (f64::sqrt(1.0 - radius * radius / distance_squared) - 1.0);
+
+    let phi = 2.0 * PI * r1;
+    let x = f64::cos(phi) * f64::sqrt(1.0 - z * z);
+    let y = f64::sin(phi) * f64::sqrt(1.0 - z * z);
+
+    Vec3::new(x, y, z)
+}
+
 /// reflects a vector impacting a mirrored surface with normal n. The normal is assumed to be a unit vector. Returns the reflected vector.
 pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     *v - 2.0 * dot(*v, *n) * *n
@@ -129,7 +155,7 @@ impl Index<usize> for Vec3 {
         match index {
             0 => &self.e0,
             1 => &self.e1,
-            2 => &self.e1,
+            2 => &self.e2,
             _ => &0.0,
         }
     }
@@ -225,3 +251,21 @@ impl Div<f64> for Vec3 {
 pub type Color = Vec3;
 #[allow(dead_code)]
 pub type Point = Vec3;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_reaches_each_component() {
+        let v = Vec3::new(1.0, 2.0, 3.0);
+        assert_eq!([v[0], v[1], v[2]], [v.x(), v.y(), v.z()]);
+        assert_eq!([v[0], v[1], v[2]], [1.0, 2.0, 3.0]);
+
+        let mut w = Vec3::new_e();
+        for a in 0..3 {
+            w[a] = 10.0 * (a + 1) as f64;
+        }
+        assert_eq!(w, Vec3::new(10.0, 20.0, 30.0));
+    }
+}